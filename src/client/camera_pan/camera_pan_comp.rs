@@ -1,7 +1,34 @@
 use bevy::{math::Vec2, prelude::Entity};
 
-#[derive(Default)]
 pub struct CameraPan {
     pub camera: Option<Entity>,
     pub last_click_position: Option<Vec2>,
+    /// Bounds of `OrthographicProjection::scale`, smaller values zoom in.
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// Fraction of the zoom changed by a mouse wheel line.
+    pub zoom_speed: f32,
+    /// Screen pixels per second, for screen edge and keyboard scrolling.
+    pub scroll_speed: f32,
+    /// Pixels from the window border where the cursor scrolls the camera.
+    pub edge_margin: f32,
+    /// Scrolls with WASD on top of the arrow keys.
+    ///
+    /// Off by default, as A and S are also unit command hotkeys, turned on by `--wasd`.
+    pub wasd: bool,
+}
+
+impl Default for CameraPan {
+    fn default() -> Self {
+        CameraPan {
+            camera: None,
+            last_click_position: None,
+            min_zoom: 0.5,
+            max_zoom: 4.0,
+            zoom_speed: 0.1,
+            scroll_speed: 800.0,
+            edge_margin: 10.0,
+            wasd: false,
+        }
+    }
 }
@@ -8,72 +8,153 @@ pub struct CameraPanPlugin;
 
 impl Plugin for CameraPanPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(CameraPan {
-            camera: None,
-            ..Default::default()
-        });
+        // Keeps the settings inserted by `main`, e.g. `--wasd`.
+        app.init_resource::<CameraPan>();
         app.add_system(systems::camera_pan)
-            .add_system(systems::camera_zoom);
+            .add_system(systems::camera_zoom)
+            .add_system(systems::camera_scroll)
+            // Once the camera was moved by the systems above.
+            .add_system_to_stage(CoreStage::PostUpdate, systems::camera_bounds);
     }
 }
 
 mod systems {
     use bevy::{
-        ecs::event::Events, input::mouse::MouseWheel, input::Input, math::Vec3, prelude::*,
-        render::camera::CameraProjection, render::camera::OrthographicProjection,
+        input::mouse::{MouseScrollUnit, MouseWheel},
+        input::Input,
+        prelude::*,
+        render::camera::OrthographicProjection,
     };
 
-    use crate::client::components::{MainCamera, MyCursorState};
+    use crate::{
+        client::components::{MainCamera, MyCursorState},
+        core_game::map::Map,
+    };
 
     use super::camera_pan_comp::CameraPan;
 
+    /// Middle mouse drag, the dragged point stays under the cursor whatever the zoom.
     pub fn camera_pan(
         mut camera_pan: ResMut<CameraPan>,
         main_camera: Res<MainCamera>,
         mouse_button: Res<MyCursorState>,
         mouse_event: Res<Input<MouseButton>>,
-        mut query: Query<&mut Transform>,
+        mut query: Query<(&mut Transform, &OrthographicProjection)>,
     ) {
         if mouse_event.pressed(MouseButton::Middle) {
-            if camera_pan.last_click_position.is_some() {
-                let mut camera = query
-                    .get_component_mut::<Transform>(main_camera.camera_e)
-                    .unwrap();
-                let offset = mouse_button.ui_position - camera_pan.last_click_position.unwrap();
-                camera.translation -= Vec3::new(offset.x, offset.y, 0.0);
+            if let Some(last_click_position) = camera_pan.last_click_position {
+                if let Ok((mut camera, projection)) = query.get_mut(main_camera.camera_e) {
+                    let offset =
+                        (mouse_button.ui_position - last_click_position) * projection.scale;
+                    camera.translation -= offset.extend(0.0);
+                }
             }
             camera_pan.last_click_position = Some(mouse_button.ui_position);
         } else if camera_pan.last_click_position.is_some() {
             camera_pan.last_click_position = None;
         }
     }
+
+    /// Mouse wheel zoom, keeping the point under the cursor in place.
     pub fn camera_zoom(
-        mut commands: Commands,
-        mut camera_pan: ResMut<CameraPan>,
+        camera_pan: Res<CameraPan>,
         main_camera: Res<MainCamera>,
+        cursor_state: Res<MyCursorState>,
+        windows: Res<Windows>,
         mut scroll_evr: EventReader<MouseWheel>,
-        mut query: Query<&mut Transform>,
+        mut query: Query<(&mut Transform, &mut OrthographicProjection)>,
     ) {
-        use bevy::input::mouse::MouseScrollUnit;
+        let (mut camera, mut projection) = match query.get_mut(main_camera.camera_e) {
+            Ok(camera) => camera,
+            Err(_) => return,
+        };
+        let window_size = match windows.get_primary() {
+            Some(window) => Vec2::new(window.width(), window.height()),
+            None => return,
+        };
         for ev in scroll_evr.iter() {
-            match ev.unit {
-                MouseScrollUnit::Line => {
-                    zoom(&mut query, &main_camera, ev);
+            let lines = match ev.unit {
+                MouseScrollUnit::Line => ev.y,
+                // Roughly a line per 20 pixels, as for most touchpads.
+                MouseScrollUnit::Pixel => ev.y / 20.0,
+            };
+            let new_scale = (projection.scale * (1.0 - lines * camera_pan.zoom_speed))
+                .clamp(camera_pan.min_zoom, camera_pan.max_zoom);
+            let cursor_offset = cursor_state.ui_position - window_size / 2.0;
+            let offset = cursor_offset * (projection.scale - new_scale);
+            camera.translation += offset.extend(0.0);
+            projection.scale = new_scale;
+        }
+    }
+
+    /// Arrow keys (and WASD if enabled) and cursor on the window edges.
+    pub fn camera_scroll(
+        time: Res<Time>,
+        camera_pan: Res<CameraPan>,
+        main_camera: Res<MainCamera>,
+        key_button: Res<Input<KeyCode>>,
+        windows: Res<Windows>,
+        mut query: Query<(&mut Transform, &OrthographicProjection)>,
+    ) {
+        let mut direction = Vec2::ZERO;
+        let mut bindings = vec![
+            (KeyCode::Left, Vec2::new(-1.0, 0.0)),
+            (KeyCode::Right, Vec2::new(1.0, 0.0)),
+            (KeyCode::Down, Vec2::new(0.0, -1.0)),
+            (KeyCode::Up, Vec2::new(0.0, 1.0)),
+        ];
+        if camera_pan.wasd {
+            bindings.extend([
+                (KeyCode::A, Vec2::new(-1.0, 0.0)),
+                (KeyCode::D, Vec2::new(1.0, 0.0)),
+                (KeyCode::S, Vec2::new(0.0, -1.0)),
+                (KeyCode::W, Vec2::new(0.0, 1.0)),
+            ]);
+        }
+        for (key, key_direction) in bindings {
+            if key_button.pressed(key) {
+                direction += key_direction;
+            }
+        }
+        if let Some(window) = windows.get_primary() {
+            // Only while the cursor is inside the window.
+            if let Some(cursor) = window.cursor_position() {
+                let size = Vec2::new(window.width(), window.height());
+                let margin = camera_pan.edge_margin;
+                if cursor.x < margin {
+                    direction.x -= 1.0;
+                } else if cursor.x > size.x - margin {
+                    direction.x += 1.0;
                 }
-                MouseScrollUnit::Pixel => {
-                    zoom(&mut query, &main_camera, ev);
+                if cursor.y < margin {
+                    direction.y -= 1.0;
+                } else if cursor.y > size.y - margin {
+                    direction.y += 1.0;
                 }
             }
         }
+        let direction = direction.clamp_length_max(1.0);
+        if direction == Vec2::ZERO {
+            return;
+        }
+        if let Ok((mut camera, projection)) = query.get_mut(main_camera.camera_e) {
+            let distance = camera_pan.scroll_speed * projection.scale * time.delta_seconds();
+            camera.translation += (direction * distance).extend(0.0);
+        }
     }
 
-    fn zoom(query: &mut Query<&mut Transform>, main_camera: &Res<MainCamera>, ev: &MouseWheel) {
-        let mut transform = query
-            .get_component_mut::<Transform>(main_camera.camera_e)
-            .unwrap();
-        let offset = ev.y * -0.1f32;
-        let newScale = transform.scale + Vec3::ONE * offset;
-        // FIXME: Scale is very ugly: zooming out messes up with cursor drag movement (should take zoom into account), and we can't zoom in.
-        transform.scale = newScale.max(Vec3::ONE);
+    /// Keeps the center of the camera over the map.
+    pub fn camera_bounds(
+        main_camera: Res<MainCamera>,
+        map: Res<Map>,
+        mut query: Query<&mut Transform, With<OrthographicProjection>>,
+    ) {
+        if let Ok(mut camera) = query.get_mut(main_camera.camera_e) {
+            let (min, max) = map.bounds();
+            let clamped = camera.translation.truncate().clamp(min, max);
+            if clamped != camera.translation.truncate() {
+                camera.translation = clamped.extend(camera.translation.z);
+            }
+        }
     }
 }
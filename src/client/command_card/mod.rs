@@ -0,0 +1,434 @@
+use bevy::prelude::*;
+
+use super::orders::orders_comp::UnitCommand;
+
+pub struct CommandCardPlugin;
+
+impl Plugin for CommandCardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system_to_stage(StartupStage::PostStartup, systems::create_command_card)
+            .add_system(systems::command_card_portraits)
+            .add_system(systems::command_card_details)
+            .add_system(systems::command_card_buttons);
+    }
+}
+
+/// Right of the minimap.
+const CARD_LEFT: f32 = 220f32;
+/// Distance from the bottom of the window, in pixels.
+const CARD_MARGIN: f32 = 10f32;
+const PADDING: f32 = 8f32;
+const PORTRAIT_SIZE: f32 = 40f32;
+const HEALTH_BAR_HEIGHT: f32 = 4f32;
+const PORTRAIT_COLUMNS: usize = 8;
+const PORTRAIT_ROWS: usize = 2;
+const DETAILS_WIDTH: f32 = 200f32;
+const BUTTON_SIZE: f32 = 56f32;
+const BUTTON_COLUMNS: usize = 3;
+const FONT_SIZE: f32 = 14f32;
+
+const PORTRAITS_WIDTH: f32 = PORTRAIT_COLUMNS as f32 * (PORTRAIT_SIZE + PADDING);
+const BUTTONS_WIDTH: f32 = BUTTON_COLUMNS as f32 * (BUTTON_SIZE + PADDING);
+const CARD_WIDTH: f32 = PADDING + PORTRAITS_WIDTH + DETAILS_WIDTH + PADDING + BUTTONS_WIDTH;
+const CARD_HEIGHT: f32 = 2f32 * (BUTTON_SIZE + PADDING) + PADDING;
+
+/// Portrait and health bar of a selected unit.
+struct PortraitSlot {
+    root: Entity,
+    image: Entity,
+    health: Entity,
+}
+
+/// Bottom panel showing the selected units, and buttons issuing their commands.
+pub struct CommandCard {
+    slots: Vec<PortraitSlot>,
+    details: Entity,
+    tooltip: Entity,
+    tooltip_text: Entity,
+}
+
+impl CommandCard {
+    /// Whether `ui_position` (in window pixels from the bottom left) is over the command card.
+    pub fn contains(&self, ui_position: Vec2) -> bool {
+        let min = Vec2::new(CARD_LEFT, CARD_MARGIN);
+        let max = min + Vec2::new(CARD_WIDTH, CARD_HEIGHT);
+        ui_position.cmpge(min).all() && ui_position.cmple(max).all()
+    }
+}
+
+#[derive(Component)]
+struct CommandButton(UnitCommand);
+
+mod systems {
+    use bevy::prelude::*;
+
+    use crate::{
+        client::{
+            components::{RenderResource, Selectable},
+            orders::orders_comp::CommandMode,
+        },
+        core_game::{
+            abilities::{Abilities, AbilityDefinition, AbilityEffect},
+            components::{Health, OffensiveStats, RenderSprite},
+        },
+    };
+
+    use super::*;
+
+    const PANEL_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.8);
+    const BUTTON_COLOR: Color = Color::rgb(0.2, 0.2, 0.2);
+    const BUTTON_HOVERED_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+    /// Pressed, or waiting for the target of its command.
+    const BUTTON_ACTIVE_COLOR: Color = Color::rgb(0.3, 0.5, 0.3);
+
+    fn absolute(position: Vec2, size: Vec2) -> Style {
+        Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(position.x),
+                bottom: Val::Px(position.y),
+                ..default()
+            },
+            size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+            ..default()
+        }
+    }
+
+    fn health_color(ratio: f32) -> Color {
+        Color::rgb(1f32 - ratio, ratio, 0f32)
+    }
+
+    pub fn create_command_card(mut commands: Commands, asset_server: Res<AssetServer>) {
+        // Only printable ASCII, which is all the card shows.
+        let font = asset_server.load("fonts/DejaVuSansASCII.ttf");
+        let text_style = TextStyle {
+            font,
+            font_size: FONT_SIZE,
+            color: Color::WHITE,
+        };
+        let mut slots = vec![];
+        let mut details = None;
+        let mut tooltip = None;
+        let mut tooltip_text = None;
+        commands
+            .spawn_bundle(NodeBundle {
+                style: absolute(
+                    Vec2::new(CARD_LEFT, CARD_MARGIN),
+                    Vec2::new(CARD_WIDTH, CARD_HEIGHT),
+                ),
+                color: PANEL_COLOR.into(),
+                ..default()
+            })
+            .with_children(|parent| {
+                for index in 0..PORTRAIT_COLUMNS * PORTRAIT_ROWS {
+                    let (column, row) = (index % PORTRAIT_COLUMNS, index / PORTRAIT_COLUMNS);
+                    let slot_size = PORTRAIT_SIZE + HEALTH_BAR_HEIGHT + PADDING;
+                    let position = Vec2::new(
+                        PADDING + column as f32 * (PORTRAIT_SIZE + PADDING),
+                        CARD_HEIGHT - (row + 1) as f32 * slot_size,
+                    );
+                    let size = Vec2::new(PORTRAIT_SIZE, PORTRAIT_SIZE + HEALTH_BAR_HEIGHT);
+                    let (mut image, mut health) = (None, None);
+                    let root = parent
+                        .spawn_bundle(NodeBundle {
+                            style: absolute(position, size),
+                            color: Color::NONE.into(),
+                            visibility: Visibility { is_visible: false },
+                            ..default()
+                        })
+                        .with_children(|slot| {
+                            image = Some(
+                                slot.spawn_bundle(ImageBundle {
+                                    style: absolute(
+                                        Vec2::new(0f32, HEALTH_BAR_HEIGHT),
+                                        Vec2::splat(PORTRAIT_SIZE),
+                                    ),
+                                    ..default()
+                                })
+                                .id(),
+                            );
+                            slot.spawn_bundle(NodeBundle {
+                                style: absolute(
+                                    Vec2::ZERO,
+                                    Vec2::new(PORTRAIT_SIZE, HEALTH_BAR_HEIGHT),
+                                ),
+                                color: Color::rgb(0.3, 0.0, 0.0).into(),
+                                ..default()
+                            })
+                            .with_children(|bar| {
+                                health = Some(
+                                    bar.spawn_bundle(NodeBundle {
+                                        style: absolute(
+                                            Vec2::ZERO,
+                                            Vec2::new(PORTRAIT_SIZE, HEALTH_BAR_HEIGHT),
+                                        ),
+                                        color: health_color(1f32).into(),
+                                        ..default()
+                                    })
+                                    .id(),
+                                );
+                            });
+                        })
+                        .id();
+                    slots.push(PortraitSlot {
+                        root,
+                        image: image.unwrap(),
+                        health: health.unwrap(),
+                    });
+                }
+
+                details = Some(
+                    parent
+                        .spawn_bundle(TextBundle::from_section("", text_style.clone()).with_style(
+                            Style {
+                                position_type: PositionType::Absolute,
+                                position: UiRect {
+                                    left: Val::Px(PADDING + PORTRAITS_WIDTH),
+                                    top: Val::Px(PADDING),
+                                    ..default()
+                                },
+                                max_size: Size::new(Val::Px(DETAILS_WIDTH), Val::Undefined),
+                                ..default()
+                            },
+                        ))
+                        .id(),
+                );
+
+                let buttons_left = CARD_WIDTH - BUTTONS_WIDTH;
+                for (index, command) in UnitCommand::ALL.into_iter().enumerate() {
+                    let (column, row) = (index % BUTTON_COLUMNS, index / BUTTON_COLUMNS);
+                    let position = Vec2::new(
+                        buttons_left + column as f32 * (BUTTON_SIZE + PADDING),
+                        CARD_HEIGHT - (row + 1) as f32 * (BUTTON_SIZE + PADDING),
+                    );
+                    parent
+                        .spawn_bundle(ButtonBundle {
+                            style: Style {
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..absolute(position, Vec2::splat(BUTTON_SIZE))
+                            },
+                            color: BUTTON_COLOR.into(),
+                            ..default()
+                        })
+                        .insert(CommandButton(command))
+                        .with_children(|button| {
+                            let hotkey_style = TextStyle {
+                                color: Color::YELLOW,
+                                ..text_style.clone()
+                            };
+                            button.spawn_bundle(
+                                TextBundle::from_sections([
+                                    TextSection::new(command.name(), text_style.clone()),
+                                    TextSection::new(
+                                        format!("\n{:?}", command.hotkey()),
+                                        hotkey_style,
+                                    ),
+                                ])
+                                .with_text_alignment(TextAlignment::CENTER),
+                            );
+                        });
+                }
+
+                tooltip = Some(
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                padding: UiRect::all(Val::Px(PADDING / 2f32)),
+                                size: Size::new(Val::Px(BUTTONS_WIDTH), Val::Auto),
+                                ..absolute(
+                                    Vec2::new(buttons_left, CARD_HEIGHT + PADDING / 2f32),
+                                    Vec2::ZERO,
+                                )
+                            },
+                            color: PANEL_COLOR.into(),
+                            visibility: Visibility { is_visible: false },
+                            ..default()
+                        })
+                        .with_children(|tooltip| {
+                            tooltip_text = Some(
+                                tooltip
+                                    .spawn_bundle(
+                                        TextBundle::from_section("", text_style.clone())
+                                            .with_style(Style {
+                                                max_size: Size::new(
+                                                    Val::Px(BUTTONS_WIDTH - PADDING),
+                                                    Val::Undefined,
+                                                ),
+                                                ..default()
+                                            }),
+                                    )
+                                    .id(),
+                            );
+                        })
+                        .id(),
+                );
+            });
+        commands.insert_resource(CommandCard {
+            slots,
+            details: details.unwrap(),
+            tooltip: tooltip.unwrap(),
+            tooltip_text: tooltip_text.unwrap(),
+        });
+    }
+
+    /// Portraits of the selected units, with their health.
+    pub fn command_card_portraits(
+        card: Res<CommandCard>,
+        render: Res<RenderResource>,
+        q_units: Query<(Entity, &Selectable, &RenderSprite, &Health)>,
+        mut q_slots: Query<(
+            &mut Visibility,
+            &mut Style,
+            &mut UiColor,
+            Option<&mut UiImage>,
+        )>,
+    ) {
+        let mut selected: Vec<_> = q_units
+            .iter()
+            .filter(|(_, selectable, _, _)| selectable.is_selected)
+            .collect();
+        selected.sort_by_key(|(entity, _, _, _)| *entity);
+        for (index, slot) in card.slots.iter().enumerate() {
+            let unit = selected.get(index);
+            if let Ok((mut visibility, _, _, _)) = q_slots.get_mut(slot.root) {
+                if visibility.is_visible != unit.is_some() {
+                    visibility.is_visible = unit.is_some();
+                }
+            }
+            let (render_sprite, health) = match unit {
+                Some((_, _, render_sprite, health)) => (render_sprite, health),
+                None => continue,
+            };
+            if let Some(visual) = render.render_sprite_visuals.get(render_sprite) {
+                if let Ok((_, _, mut color, Some(mut image))) = q_slots.get_mut(slot.image) {
+                    if image.0 != visual.image {
+                        image.0 = visual.image.clone();
+                    }
+                    if color.0 != visual.color {
+                        color.0 = visual.color;
+                    }
+                }
+            }
+            let ratio = (health.current_hp / health.max_hp).clamp(0f32, 1f32);
+            if let Ok((_, mut style, mut color, _)) = q_slots.get_mut(slot.health) {
+                let width = Val::Px(PORTRAIT_SIZE * ratio);
+                if style.size.width != width {
+                    style.size.width = width;
+                    color.0 = health_color(ratio);
+                }
+            }
+        }
+    }
+
+    fn attack_description(ability: &AbilityDefinition, stats: Option<&OffensiveStats>) -> String {
+        let power = stats.map_or(0f32, |stats| stats.power);
+        let damage = match &ability.effect {
+            AbilityEffect::Damage { ratio, .. } => format!("{:.1} damage", power * ratio),
+            AbilityEffect::Projectile { .. } => format!("{:.1} damage", power),
+            AbilityEffect::DamageOverTime {
+                ratio, duration, ..
+            } => format!("{:.1} damage over {}s", power * ratio, duration),
+            _ => String::new(),
+        };
+        format!(
+            "{}: {}\nRange {}, cooldown {}s",
+            ability.name, damage, ability.range, ability.cooldown
+        )
+    }
+
+    /// Health and attack of the first selected unit.
+    pub fn command_card_details(
+        card: Res<CommandCard>,
+        q_units: Query<(
+            Entity,
+            &Selectable,
+            &RenderSprite,
+            &Health,
+            &Abilities,
+            Option<&OffensiveStats>,
+        )>,
+        mut q_text: Query<&mut Text>,
+    ) {
+        let selected: Vec<_> = q_units
+            .iter()
+            .filter(|(_, selectable, _, _, _, _)| selectable.is_selected)
+            .collect();
+        let count = selected.len();
+        let first = selected
+            .into_iter()
+            .min_by_key(|(entity, _, _, _, _, _)| *entity);
+        let description = match first {
+            Some((_, _, render_sprite, health, abilities, stats)) => {
+                let mut description = render_sprite.0.clone();
+                if count > 1 {
+                    description += &format!(" ({} selected)", count);
+                }
+                description += &format!("\nHealth {:.0}/{:.0}", health.current_hp, health.max_hp);
+                if let Some((_, attack)) = abilities.attack() {
+                    description += "\n";
+                    description += &attack_description(attack, stats);
+                }
+                description
+            }
+            None => String::new(),
+        };
+        if let Ok(mut text) = q_text.get_mut(card.details) {
+            if text.sections[0].value != description {
+                text.sections[0].value = description;
+            }
+        }
+    }
+
+    /// Pressing a button requests its command, hovering it shows its tooltip.
+    pub fn command_card_buttons(
+        card: Res<CommandCard>,
+        mut command_mode: ResMut<CommandMode>,
+        q_pressed: Query<(&Interaction, &CommandButton), Changed<Interaction>>,
+        mut q_buttons: Query<(&Interaction, &CommandButton, &mut UiColor)>,
+        mut q_text: Query<&mut Text>,
+        mut q_visibility: Query<&mut Visibility>,
+    ) {
+        for (interaction, button) in q_pressed.iter() {
+            if *interaction == Interaction::Clicked {
+                command_mode.requested = Some(button.0);
+            }
+        }
+        let mut hovered = None;
+        for (interaction, button, mut color) in q_buttons.iter_mut() {
+            let command = button.0;
+            if *interaction != Interaction::None {
+                hovered = Some(command);
+            }
+            let waiting =
+                command.targeted().is_some() && command.targeted() == command_mode.command;
+            let new_color = match interaction {
+                Interaction::Clicked => BUTTON_ACTIVE_COLOR,
+                _ if waiting => BUTTON_ACTIVE_COLOR,
+                Interaction::Hovered => BUTTON_HOVERED_COLOR,
+                Interaction::None => BUTTON_COLOR,
+            };
+            if color.0 != new_color {
+                color.0 = new_color;
+            }
+        }
+        if let Some(command) = hovered {
+            if let Ok(mut text) = q_text.get_mut(card.tooltip_text) {
+                let tooltip = format!(
+                    "{} ({:?})\n{}",
+                    command.name(),
+                    command.hotkey(),
+                    command.description()
+                );
+                if text.sections[0].value != tooltip {
+                    text.sections[0].value = tooltip;
+                }
+            }
+        }
+        if let Ok(mut visibility) = q_visibility.get_mut(card.tooltip) {
+            if visibility.is_visible != hovered.is_some() {
+                visibility.is_visible = hovered.is_some();
+            }
+        }
+    }
+}
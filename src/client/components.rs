@@ -70,4 +70,15 @@ pub struct RenderResource {
     pub color_selection: Color,
     pub color_walls: Color,
     pub team_colors: Vec<Color>,
+    /// For teams past `team_colors`, scenarios can use any team id.
+    pub color_other_teams: Color,
+}
+
+impl RenderResource {
+    pub fn team_color(&self, team: usize) -> Color {
+        self.team_colors
+            .get(team)
+            .copied()
+            .unwrap_or(self.color_other_teams)
+    }
 }
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(systems::fog_setup)
+            .add_system(systems::fog_visual)
+            .add_system(systems::hide_units_in_fog);
+    }
+}
+
+/// Overlay sprites, by tile index.
+pub struct FogTiles {
+    pub tiles: Vec<Entity>,
+    pub width: usize,
+}
+
+mod systems {
+    use bevy::prelude::*;
+
+    use crate::{
+        client::orders::orders_comp::TeamResource,
+        core_game::{
+            components::{Health, Team},
+            map::{Map, TILE_SIZE},
+            vision::{TileVisibility, Vision},
+        },
+    };
+
+    use super::FogTiles;
+
+    /// Above units and their health bars.
+    const FOG_Z: f32 = 10f32;
+
+    fn fog_color(visibility: TileVisibility) -> Color {
+        match visibility {
+            TileVisibility::Unexplored => Color::rgba(0.0, 0.0, 0.0, 1.0),
+            TileVisibility::Explored => Color::rgba(0.0, 0.0, 0.0, 0.5),
+            TileVisibility::Visible => Color::NONE,
+        }
+    }
+
+    pub fn fog_setup(mut commands: Commands, map: Res<Map>, fog: Option<Res<FogTiles>>) {
+        if fog.is_some() {
+            return;
+        }
+        let mut tiles = vec![];
+        for y in 0..map.map.height {
+            for x in 0..map.map.width {
+                let position = map.real_position_at(x, y).extend(FOG_Z);
+                let tile = commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite {
+                            custom_size: Some(Vec2::splat(TILE_SIZE)),
+                            color: fog_color(TileVisibility::Unexplored),
+                            ..default()
+                        },
+                        transform: Transform::from_translation(position),
+                        ..default()
+                    })
+                    .id();
+                tiles.push(tile);
+            }
+        }
+        commands.insert_resource(FogTiles {
+            tiles,
+            width: map.map.width,
+        });
+    }
+
+    pub fn fog_visual(
+        vision: Res<Vision>,
+        team: Res<TeamResource>,
+        fog: Option<Res<FogTiles>>,
+        mut q_sprites: Query<&mut Sprite>,
+    ) {
+        let fog = match fog {
+            Some(fog) => fog,
+            None => return,
+        };
+        if !vision.is_changed() && !fog.is_added() {
+            return;
+        }
+        for (index, tile) in fog.tiles.iter().enumerate() {
+            let visibility = vision.tile(team.team.id, index % fog.width, index / fog.width);
+            if let Ok(mut sprite) = q_sprites.get_mut(*tile) {
+                let color = fog_color(visibility);
+                if sprite.color != color {
+                    sprite.color = color;
+                }
+            }
+        }
+    }
+
+    /// Enemy units are only shown where the player's team sees them.
+    pub fn hide_units_in_fog(
+        vision: Res<Vision>,
+        map: Res<Map>,
+        team: Res<TeamResource>,
+        mut q_units: Query<(&Team, &Transform, &mut Visibility), With<Health>>,
+    ) {
+        for (unit_team, transform, mut visibility) in q_units.iter_mut() {
+            let is_visible = unit_team.id == team.team.id
+                || vision.is_visible(&map, team.team.id, transform.translation.truncate());
+            if visibility.is_visible != is_visible {
+                visibility.is_visible = is_visible;
+            }
+        }
+    }
+}
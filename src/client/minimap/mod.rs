@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system_to_stage(StartupStage::PostStartup, systems::create_minimap)
+            .add_system(systems::minimap_input)
+            .add_system(systems::minimap_units)
+            .add_system(systems::minimap_viewport);
+    }
+}
+
+/// Side of the minimap, in pixels.
+const MINIMAP_SIZE: f32 = 200f32;
+/// Distance from the bottom left corner of the window, in pixels.
+const MINIMAP_MARGIN: f32 = 10f32;
+const DOT_SIZE: f32 = 4f32;
+
+/// Overview of the whole map in the bottom left corner of the window.
+pub struct Minimap {
+    root: Entity,
+    viewport: Entity,
+    /// Bottom left corner of the map, in world units.
+    world_origin: Vec2,
+    /// Minimap pixels per world unit.
+    scale: f32,
+    /// Dot of each unit.
+    dots: HashMap<Entity, Entity>,
+    /// Set by a right click on the minimap, for `move_order_system` to move the selection there.
+    pub move_order: Option<Vec3>,
+}
+
+impl Minimap {
+    /// Whether `ui_position` (in window pixels from the bottom left) is over the minimap.
+    pub fn contains(&self, ui_position: Vec2) -> bool {
+        let min = Vec2::splat(MINIMAP_MARGIN);
+        let max = min + Vec2::splat(MINIMAP_SIZE);
+        ui_position.cmpge(min).all() && ui_position.cmple(max).all()
+    }
+    fn to_world(&self, ui_position: Vec2) -> Vec2 {
+        (ui_position - Vec2::splat(MINIMAP_MARGIN)) / self.scale + self.world_origin
+    }
+    /// Position in the minimap node, clamped to it.
+    fn to_minimap(&self, world_position: Vec2) -> Vec2 {
+        ((world_position - self.world_origin) * self.scale)
+            .clamp(Vec2::ZERO, Vec2::splat(MINIMAP_SIZE))
+    }
+}
+
+mod systems {
+    use bevy::prelude::*;
+
+    use crate::{
+        client::components::{MainCamera, MyCursorState, RenderResource},
+        core_game::{
+            components::{Health, Team},
+            map::{Map, TILE_SIZE},
+        },
+    };
+
+    use super::*;
+
+    fn absolute(position: Vec2, size: Vec2) -> Style {
+        Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(position.x),
+                bottom: Val::Px(position.y),
+                ..default()
+            },
+            size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+            ..default()
+        }
+    }
+
+    pub fn create_minimap(mut commands: Commands, map: Res<Map>, render: Res<RenderResource>) {
+        let (width, height) = (map.map.width, map.map.height);
+        let scale = MINIMAP_SIZE / (width.max(height) as f32 * TILE_SIZE);
+        let (world_origin, _) = map.bounds();
+        let tile_size = Vec2::splat(TILE_SIZE * scale);
+        let mut viewport = None;
+        let root = commands
+            .spawn_bundle(NodeBundle {
+                style: absolute(Vec2::splat(MINIMAP_MARGIN), Vec2::splat(MINIMAP_SIZE)),
+                color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+                ..default()
+            })
+            .with_children(|parent| {
+                for y in 0..height {
+                    for x in 0..width {
+                        if map.map.at(x, y).is_walkable() {
+                            continue;
+                        }
+                        parent.spawn_bundle(NodeBundle {
+                            style: absolute(Vec2::new(x as f32, y as f32) * tile_size, tile_size),
+                            color: render.color_walls.into(),
+                            ..default()
+                        });
+                    }
+                }
+                viewport = Some(
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: absolute(Vec2::ZERO, Vec2::ZERO),
+                            color: Color::rgba(1.0, 1.0, 1.0, 0.2).into(),
+                            ..default()
+                        })
+                        .id(),
+                );
+            })
+            .id();
+        commands.insert_resource(Minimap {
+            root,
+            viewport: viewport.unwrap(),
+            world_origin,
+            scale,
+            dots: HashMap::new(),
+            move_order: None,
+        });
+    }
+
+    /// Left click recenters the camera, right click moves the selected units.
+    pub fn minimap_input(
+        mut minimap: ResMut<Minimap>,
+        cursor_state: Res<MyCursorState>,
+        mouse_button: Res<Input<MouseButton>>,
+        main_camera: Res<MainCamera>,
+        mut q_transforms: Query<&mut Transform>,
+    ) {
+        if !minimap.contains(cursor_state.ui_position) {
+            return;
+        }
+        let world_position = minimap.to_world(cursor_state.ui_position);
+        if mouse_button.pressed(MouseButton::Left) {
+            if let Ok(mut camera) = q_transforms.get_mut(main_camera.camera_e) {
+                camera.translation.x = world_position.x;
+                camera.translation.y = world_position.y;
+            }
+        }
+        if mouse_button.just_pressed(MouseButton::Right) {
+            minimap.move_order = Some(world_position.extend(0f32));
+        }
+    }
+
+    /// Units are shown as dots of their team color, hidden ones (e.g. in the fog) aren't shown.
+    pub fn minimap_units(
+        mut commands: Commands,
+        mut minimap: ResMut<Minimap>,
+        render: Res<RenderResource>,
+        q_units: Query<(Entity, &Team, &Transform, &Visibility), With<Health>>,
+        mut q_dots: Query<(&mut Style, &mut Visibility), Without<Health>>,
+    ) {
+        minimap.dots.retain(|unit, dot| {
+            let alive = q_units.get(*unit).is_ok();
+            if !alive {
+                commands.entity(*dot).despawn();
+            }
+            alive
+        });
+        for (entity, team, transform, unit_visibility) in q_units.iter() {
+            let position =
+                minimap.to_minimap(transform.translation.truncate()) - Vec2::splat(DOT_SIZE / 2f32);
+            let dot = match minimap.dots.get(&entity) {
+                Some(dot) => *dot,
+                None => {
+                    let dot = commands
+                        .spawn_bundle(NodeBundle {
+                            style: absolute(position, Vec2::splat(DOT_SIZE)),
+                            color: render.team_color(team.id).into(),
+                            visibility: Visibility {
+                                is_visible: unit_visibility.is_visible,
+                            },
+                            ..default()
+                        })
+                        .id();
+                    commands.entity(minimap.root).add_child(dot);
+                    minimap.dots.insert(entity, dot);
+                    continue;
+                }
+            };
+            if let Ok((mut style, mut visibility)) = q_dots.get_mut(dot) {
+                style.position.left = Val::Px(position.x);
+                style.position.bottom = Val::Px(position.y);
+                visibility.is_visible = unit_visibility.is_visible;
+            }
+        }
+    }
+
+    /// Part of the map seen by the main camera.
+    pub fn minimap_viewport(
+        minimap: Res<Minimap>,
+        windows: Res<Windows>,
+        main_camera: Res<MainCamera>,
+        q_cameras: Query<(&Transform, &OrthographicProjection)>,
+        mut q_style: Query<&mut Style>,
+    ) {
+        let (window, (camera, projection)) =
+            match (windows.get_primary(), q_cameras.get(main_camera.camera_e)) {
+                (Some(window), Ok(camera)) => (window, camera),
+                _ => return,
+            };
+        let half_extents = Vec2::new(window.width(), window.height()) * projection.scale / 2f32;
+        let center = camera.translation.truncate();
+        let min = minimap.to_minimap(center - half_extents);
+        let max = minimap.to_minimap(center + half_extents);
+        if let Ok(mut style) = q_style.get_mut(minimap.viewport) {
+            *style = absolute(min, max - min);
+        }
+    }
+}
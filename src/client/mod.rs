@@ -1,7 +1,11 @@
 use bevy::prelude::*;
+use bevy_inspector_egui::RegisterInspectable;
 
-mod camera_pan;
+pub(crate) mod camera_pan;
+mod command_card;
 mod components;
+mod fog;
+mod minimap;
 mod orders;
 mod selection;
 mod systems;
@@ -9,13 +13,16 @@ mod systems;
 use bevy_prototype_lyon::plugin::ShapePlugin;
 use systems::*;
 
-use crate::core_game::components::Team;
+use crate::core_game::orders::orders_comp::*;
 
 use self::{
     camera_pan::CameraPanPlugin,
-    orders::{orders_comp::TeamResource, orders_sys::*},
+    command_card::CommandCardPlugin,
+    fog::FogPlugin,
+    minimap::MinimapPlugin,
+    orders::orders_sys::*,
     selection::selection_syst::*,
-    systems::ability::*,
+    systems::{ability::*, status::*},
 };
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
@@ -29,12 +36,14 @@ impl Plugin for ClientPlugin {
     fn build(&self, app: &mut App) {
         use bevy_inspector_egui::WorldInspectorPlugin;
         app.add_plugin(CameraPanPlugin);
+        app.add_plugin(CommandCardPlugin);
+        app.add_plugin(FogPlugin);
+        app.add_plugin(MinimapPlugin);
         app.add_plugin(ShapePlugin);
         app.add_plugin(WorldInspectorPlugin::new());
-
-        app.insert_resource(TeamResource {
-            team: Team { id: 2 },
-        });
+        app.register_inspectable::<Speed>();
+        app.register_inspectable::<RotateBeforeMove>();
+        app.register_inspectable::<Mover>();
 
         app.add_stage_after(
             CoreStage::Update,
@@ -42,24 +51,39 @@ impl Plugin for ClientPlugin {
             SystemStage::single_threaded(),
         );
 
-        app.add_startup_system(create_camera)
+        app.add_startup_system_to_stage(StartupStage::PreStartup, create_team_resource)
+            .add_startup_system(create_camera)
             .add_startup_system(create_render_resource)
             .add_startup_system_to_stage(StartupStage::PostStartup, create_ui)
             .add_startup_system_to_stage(StartupStage::PostStartup, adapt_units_for_client)
             .add_startup_system_to_stage(StartupStage::PostStartup, adapt_map_for_client)
             .add_system(bevy::window::close_on_esc)
+            .add_system(quicksave_system)
+            .add_system(render_resource_reload_system)
             .add_system(mouse_world_position_system)
+            .init_resource::<selection::selection_comp::LastClick>()
+            .init_resource::<selection::selection_comp::ControlGroups>()
             .add_system(selection_system)
+            .add_system(control_group_system)
+            .add_system(select_army_system)
+            .add_system(projectile_visual_setup)
             .add_system(selection_visual_system)
             .add_system(selection_ui_visual)
             .add_startup_system(health_visual_startup)
             .add_startup_system(ability_visual_startup)
             .add_system(health_visual_setup_system)
             .add_system(ability_visual_setup)
+            .add_system(status_visual_setup)
             .add_system_to_stage(CoreStage::PostUpdate, health_visual_system)
             .add_system_to_stage(CoreStage::PreUpdate, ability_visual)
+            .add_system_to_stage(CoreStage::PostUpdate, status_visual)
             // TODO: make the input system trigger before update, and the ai system trigger after update ?
+            .init_resource::<orders::orders_comp::SelectedFormation>()
+            .add_system(formation_hotkey_system)
             .add_system(move_order_system)
+            .init_resource::<orders::orders_comp::CommandMode>()
+            .add_system(command_system)
+            .add_system(command_cursor_system)
             .add_startup_system(order_system_visual_startup)
             .add_system(order_system_visual_init)
             .add_system(order_system_visual)
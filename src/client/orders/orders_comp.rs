@@ -1,11 +1,110 @@
 use bevy::prelude::*;
 
-use crate::core_game::components::Team;
+use crate::core_game::{components::Team, formation::Formation};
 
 pub struct TeamResource {
     pub team: Team,
 }
 
+/// Commands given to the selected units, by hotkey or from the command card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitCommand {
+    Move,
+    Stop,
+    HoldPosition,
+    AttackMove,
+    Patrol,
+    Follow,
+}
+
+impl UnitCommand {
+    pub const ALL: [UnitCommand; 6] = [
+        UnitCommand::Move,
+        UnitCommand::Stop,
+        UnitCommand::HoldPosition,
+        UnitCommand::AttackMove,
+        UnitCommand::Patrol,
+        UnitCommand::Follow,
+    ];
+    pub fn hotkey(&self) -> KeyCode {
+        match self {
+            UnitCommand::Move => KeyCode::M,
+            UnitCommand::Stop => KeyCode::S,
+            UnitCommand::HoldPosition => KeyCode::H,
+            UnitCommand::AttackMove => KeyCode::A,
+            UnitCommand::Patrol => KeyCode::P,
+            UnitCommand::Follow => KeyCode::F,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnitCommand::Move => "Move",
+            UnitCommand::Stop => "Stop",
+            UnitCommand::HoldPosition => "Hold",
+            UnitCommand::AttackMove => "Attack",
+            UnitCommand::Patrol => "Patrol",
+            UnitCommand::Follow => "Follow",
+        }
+    }
+    pub fn description(&self) -> &'static str {
+        match self {
+            UnitCommand::Move => "Moves to the clicked point, ignoring enemies until arrival.",
+            UnitCommand::Stop => "Stops moving and casting.",
+            UnitCommand::HoldPosition => "Attacks enemies in range, but never moves.",
+            UnitCommand::AttackMove => {
+                "Moves to the clicked point fighting enemies on the way, or attacks the clicked enemy."
+            }
+            UnitCommand::Patrol => "Moves back and forth to the clicked point, fighting enemies on the way.",
+            UnitCommand::Follow => "Stays close to the clicked friendly unit.",
+        }
+    }
+    /// Left click target waited for, if any.
+    pub fn targeted(&self) -> Option<TargetedCommand> {
+        match self {
+            UnitCommand::Move => Some(TargetedCommand::Move),
+            UnitCommand::Stop | UnitCommand::HoldPosition => None,
+            UnitCommand::AttackMove => Some(TargetedCommand::AttackMove),
+            UnitCommand::Patrol => Some(TargetedCommand::Patrol),
+            UnitCommand::Follow => Some(TargetedCommand::Follow),
+        }
+    }
+}
+
+/// Command waiting for a left click to choose where or on whom it applies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetedCommand {
+    /// To the clicked point, handled by `move_order_system`.
+    Move,
+    /// To the clicked point, or attacking the clicked enemy.
+    AttackMove,
+    /// Between the units' positions and the clicked point.
+    Patrol,
+    /// The clicked friendly unit.
+    Follow,
+}
+
+#[derive(Default)]
+pub struct CommandMode {
+    pub command: Option<TargetedCommand>,
+    /// Set by a command card button, handled like its hotkey.
+    pub requested: Option<UnitCommand>,
+    /// The click choosing the target is still held, it shouldn't start a selection.
+    pub(super) awaiting_release: bool,
+}
+
+impl CommandMode {
+    /// Left clicks are used by the command rather than selection.
+    pub fn is_active(&self) -> bool {
+        self.command.is_some() || self.awaiting_release
+    }
+}
+
+/// Formation used by the next move orders.
+#[derive(Default)]
+pub struct SelectedFormation {
+    pub formation: Formation,
+}
+
 #[derive(Component, Clone)]
 pub struct DebugOrderMove;
 
@@ -16,6 +115,7 @@ pub struct DebugOrderMoveGraphic {
 
 #[derive(Component)]
 pub struct OrderVisualResource {
-    pub(super) move_material: Handle<ColorMaterial>,
-    pub(super) attack_material: Handle<ColorMaterial>,
+    pub(super) move_color: Color,
+    pub(super) attack_color: Color,
+    pub(super) attack_move_color: Color,
 }
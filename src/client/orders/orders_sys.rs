@@ -2,18 +2,77 @@ use bevy::{math::Vec3, prelude::*};
 use bevy_prototype_lyon::prelude::{DrawMode, GeometryBuilder, PathBuilder, StrokeMode};
 
 use crate::{
-    client::components::*,
-    client::selection,
+    client::{command_card::CommandCard, components::*, minimap::Minimap},
     core_game::components::Attack,
     core_game::{
-        components::{AIUnit, Health, Team},
+        components::{AIUnit, Health, Team, UnitSize},
+        formation::{group_speed, Formation, FormationMember},
         orders::orders_comp::*,
-        pathfinding::pathfinding_comp::Map,
+        pathfinding::{find_world_waypoints, pathfinding_comp::Map},
+        scenario::Teams,
     },
 };
 
 use super::orders_comp::*;
 
+/// From this many units, a move shares a single flow field instead of pathing each unit.
+const FLOW_FIELD_GROUP_SIZE: usize = 8;
+
+/// Pace keeping a group together, the one of its slowest unit; none for a single unit.
+fn group_speed_limit(speeds: &[f32]) -> Option<f32> {
+    if speeds.len() > 1 {
+        group_speed(speeds.iter().copied())
+    } else {
+        None
+    }
+}
+
+/// Mover of a unit of a `group_size` group going to `target`, its slot around `destination`.
+///
+/// Big groups steer through the shared flow field of `destination`.
+fn group_mover(
+    target: Vec3,
+    destination: Vec3,
+    group_size: usize,
+    speed_limit: Option<f32>,
+) -> Mover {
+    let mut mover = Mover::new_to_target(target);
+    if let Some(speed_limit) = speed_limit {
+        mover = mover.with_speed_limit(speed_limit);
+    }
+    if group_size >= FLOW_FIELD_GROUP_SIZE {
+        mover = mover.with_flow_field(destination);
+    }
+    mover
+}
+
+/// The local player controls the team chosen by the scenario.
+pub fn create_team_resource(mut commands: Commands, teams: Res<Teams>) {
+    commands.insert_resource(TeamResource {
+        team: Team {
+            id: teams.player_team,
+        },
+    });
+}
+
+pub fn formation_hotkey_system(
+    key_button: Res<Input<KeyCode>>,
+    mut formation: ResMut<SelectedFormation>,
+) {
+    let hotkeys = [
+        (KeyCode::Z, Formation::PreserveShape),
+        (KeyCode::X, Formation::Line),
+        (KeyCode::C, Formation::Column),
+        (KeyCode::V, Formation::Box),
+        (KeyCode::B, Formation::Wedge),
+    ];
+    for (key, new_formation) in hotkeys {
+        if key_button.just_pressed(key) {
+            formation.formation = new_formation;
+        }
+    }
+}
+
 pub fn move_order_system(
     cursor_state: Res<MyCursorState>,
     mouse_button: Res<Input<MouseButton>>,
@@ -21,30 +80,62 @@ pub fn move_order_system(
     team: Res<TeamResource>,
     selection: Res<Selection>,
     map: Res<Map>,
+    world_map: Res<crate::core_game::map::Map>,
+    mut order_requests: ResMut<OrderRequests>,
     q_attackables: Query<(Entity, &Transform, &Team, &Health, &Selectable)>,
-    mut query: Query<(&mut Orders, &Selectable, &Team, &Transform)>,
+    formation: Res<SelectedFormation>,
+    mut minimap: ResMut<Minimap>,
+    command_card: Res<CommandCard>,
+    mut command_mode: ResMut<CommandMode>,
+    query: Query<(Entity, &Selectable, &Team, &Transform, &UnitSize, &Speed)>,
 ) {
-    if mouse_button.just_pressed(MouseButton::Right) {
-        if let Selection::Hover(Some(selected)) = *selection {
+    // Right click cancels a command waiting for its target.
+    if command_mode.command.is_some() && mouse_button.just_pressed(MouseButton::Right) {
+        command_mode.command = None;
+        return;
+    }
+    let over_ui = minimap.contains(cursor_state.ui_position)
+        || command_card.contains(cursor_state.ui_position);
+    let cursor_position = Vec3::new(
+        cursor_state.world_position.x,
+        cursor_state.world_position.y,
+        0f32,
+    );
+    let commanded_move = command_mode.command == Some(TargetedCommand::Move)
+        && mouse_button.just_pressed(MouseButton::Left)
+        && !over_ui;
+    if commanded_move {
+        command_mode.command = None;
+        command_mode.awaiting_release = true;
+    }
+    // Right clicks on the minimap and the move command go where they point at, never attack.
+    let forced_destination = minimap
+        .move_order
+        .take()
+        .or_else(|| commanded_move.then_some(cursor_position));
+    let clicked = mouse_button.just_pressed(MouseButton::Right) && !over_ui;
+    if forced_destination.is_some() || clicked {
+        if let (None, Selection::Hover(Some(selected))) = (forced_destination, &*selection) {
+            let selected = *selected;
             if let Ok(a_team) = q_attackables.get_component::<Team>(selected) {
                 if a_team.id != team.team.id {
-                    for (mut orders, selectable, b_team, _) in query.iter_mut() {
+                    for (entity, selectable, b_team, _, _, _) in query.iter() {
                         if b_team.id != team.team.id {
                             continue;
                         }
                         if selectable.is_selected {
-                            let mut new_orders = vec![Order::Ai(AIUnit::Attack(Attack {
+                            let new_orders = vec![Order::Ai(AIUnit::Attack(Attack {
                                 target: selected,
                                 chase_when_target_too_far: true,
+                                return_position: None,
                             }))];
 
-                            if key_button.pressed(KeyCode::RShift)
-                                || key_button.pressed(KeyCode::LShift)
-                            {
-                                orders.add_orders(new_orders);
-                            } else {
-                                orders.replace_orders(new_orders);
-                            }
+                            order_requests.issue(
+                                entity,
+                                new_orders,
+                                key_button.pressed(KeyCode::RShift)
+                                    || key_button.pressed(KeyCode::LShift),
+                            );
                         }
                     }
                     return;
@@ -53,96 +144,60 @@ pub fn move_order_system(
         }
 
         let mut selected_units = vec![];
-        for (orders, selectable, b_team, transform) in query.iter_mut() {
+        for (entity, selectable, b_team, transform, size, speed) in query.iter() {
             if b_team.id != team.team.id {
                 continue;
             }
             if selectable.is_selected {
-                selected_units.push((orders, transform.translation));
-            }
-        }
-        let mut magic_box_center: Option<Vec3> = None;
-        if selected_units.len() > 1 {
-            let mut min = Vec3::new(f32::MAX, f32::MAX, 0.0);
-            let mut max = Vec3::new(f32::MIN, f32::MIN, 0.0);
-            for (_, position) in selected_units.iter() {
-                if position.x < min.x {
-                    min.x = position.x;
-                }
-                if position.y < min.y {
-                    min.y = position.y;
-                }
-                if position.x > max.x {
-                    max.x = position.x;
-                }
-                if position.y > max.y {
-                    max.y = position.y;
-                }
-            }
-            // Outside magic box
-            if !selection::helpers::helper_in_rect(
-                &cursor_state.world_position,
-                &Position::from(&min),
-                &Position::from(&max),
-            ) {
-                magic_box_center = Some((min + max) / 2.0);
+                selected_units.push((
+                    entity,
+                    FormationMember {
+                        position: transform.translation,
+                        size: size.0,
+                    },
+                    selectable.half_size,
+                    speed.speed,
+                ));
             }
         }
-        let magic_box_center = magic_box_center;
-        for (orders, position) in selected_units.iter_mut() {
-            let offset = if let Some(center) = magic_box_center {
-                position.clone() - center.clone()
-            } else {
-                Vec3::ZERO
-            };
-            let target = Vec3::new(
-                cursor_state.world_position.x,
-                cursor_state.world_position.y,
-                0f32,
-            ) + offset;
-            let mut new_orders = vec![if key_button.pressed(KeyCode::A) {
-                Order::Ai(AIUnit::SeekEnemy)
+        let destination = forced_destination.unwrap_or(cursor_position);
+        let members: Vec<FormationMember> = selected_units
+            .iter()
+            .map(|(_, member, _, _)| *member)
+            .collect();
+        let targets = formation.formation.targets(&members, destination);
+        let speeds: Vec<f32> = selected_units
+            .iter()
+            .map(|(_, _, _, speed)| *speed)
+            .collect();
+        let speed_limit = group_speed_limit(&speeds);
+        let group_size = selected_units.len();
+        for ((entity, member, half_size, _), target) in selected_units.iter().zip(targets) {
+            // Attack-move is its own command, moves ignore enemies until arrival.
+            let mut new_orders = vec![Order::Ai(AIUnit::Passive)];
+            if group_size >= FLOW_FIELD_GROUP_SIZE {
+                new_orders.push(Orders::order_move_with(group_mover(
+                    target,
+                    destination,
+                    group_size,
+                    speed_limit,
+                )));
             } else {
-                Order::Ai(AIUnit::Passive)
-            }];
-            if map.is_ready() {
-                let start_map = (
-                    crate::core_game::map::Map::map_x_at(position.x) as i32,
-                    crate::core_game::map::Map::map_y_at(position.y) as i32,
-                );
-                let target_map = (
-                    crate::core_game::map::Map::map_x_at(target.x) as i32,
-                    crate::core_game::map::Map::map_y_at(target.y) as i32,
-                );
-                if let Ok(path) = map.dijkstra(start_map, target_map) {
-                    let mut real_path = path
+                new_orders.extend(
+                    find_world_waypoints(&map, &world_map, member.position, target, *half_size)
                         .into_iter()
-                        .map(|pos| {
-                            let real_pos = crate::core_game::map::Map::real_position_at(
-                                pos.0 as usize,
-                                pos.1 as usize,
-                            );
-                            Orders::order_move(Vec3::new(real_pos.x, real_pos.y, 0f32))
-                        })
-                        .collect::<Vec<Order>>();
-                    new_orders.append(&mut real_path);
-                    new_orders.push(Orders::order_move(target));
-                    // First position is current position
-                    new_orders.remove(0);
-                    // Second position is nearest pathable tile, often not exacly in the correct direction.
-                    new_orders.remove(0);
-                } else {
-                    new_orders.push(Orders::order_move(target));
-                }
-            } else {
-                new_orders.push(Orders::order_move(target));
+                        .map(|waypoint| {
+                            let mover = group_mover(waypoint, destination, group_size, speed_limit);
+                            Orders::order_move_with(mover)
+                        }),
+                );
             }
             new_orders.push(Order::Ai(AIUnit::SeekEnemy));
-            if key_button.pressed(KeyCode::RShift) || key_button.pressed(KeyCode::LShift) {
-                orders.add_orders(new_orders);
-            } else {
-                orders.replace_orders(new_orders);
-            }
+            order_requests.issue(
+                *entity,
+                new_orders,
+                key_button.pressed(KeyCode::RShift) || key_button.pressed(KeyCode::LShift),
+            );
         }
         return;
     }
@@ -170,13 +225,183 @@ pub fn move_order_system(
     }*/
 }
 
-pub fn order_system_visual_startup(
-    mut commands: Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+fn is_shift_pressed(key_button: &Input<KeyCode>) -> bool {
+    key_button.pressed(KeyCode::LShift) || key_button.pressed(KeyCode::RShift)
+}
+
+/// Stop and hold position apply right away, other commands wait for a left click on their target.
+pub fn command_system(
+    cursor_state: Res<MyCursorState>,
+    mouse_button: Res<Input<MouseButton>>,
+    key_button: Res<Input<KeyCode>>,
+    team: Res<TeamResource>,
+    selection: Res<Selection>,
+    formation: Res<SelectedFormation>,
+    minimap: Res<Minimap>,
+    command_card: Res<CommandCard>,
+    mut command_mode: ResMut<CommandMode>,
+    mut order_requests: ResMut<OrderRequests>,
+    query: Query<(
+        Entity,
+        &Selectable,
+        &Team,
+        &Transform,
+        &UnitSize,
+        &Speed,
+        &Orders,
+    )>,
 ) {
+    if command_mode.awaiting_release && !mouse_button.pressed(MouseButton::Left) {
+        command_mode.awaiting_release = false;
+    }
+    let queue = is_shift_pressed(&key_button);
+    let selected: Vec<(Entity, FormationMember, f32)> = query
+        .iter()
+        .filter(|(_, selectable, unit_team, ..)| {
+            selectable.is_selected && unit_team.id == team.team.id
+        })
+        .map(|(entity, _, _, transform, size, speed, _)| {
+            let member = FormationMember {
+                position: transform.translation,
+                size: size.0,
+            };
+            (entity, member, speed.speed)
+        })
+        .collect();
+    let requested = command_mode.requested.take();
+    for unit_command in UnitCommand::ALL {
+        if requested != Some(unit_command) && !key_button.just_pressed(unit_command.hotkey()) {
+            continue;
+        }
+        match unit_command {
+            UnitCommand::Stop => {
+                for (entity, ..) in selected.iter() {
+                    order_requests.issue(*entity, vec![Order::Stop], queue);
+                }
+            }
+            UnitCommand::HoldPosition => {
+                for (entity, ..) in selected.iter() {
+                    let new_orders = vec![Order::Ai(AIUnit::SeekEnemy), Order::HoldPosition];
+                    order_requests.issue(*entity, new_orders, queue);
+                }
+            }
+            _ => command_mode.command = unit_command.targeted(),
+        }
+    }
+
+    let over_ui = minimap.contains(cursor_state.ui_position)
+        || command_card.contains(cursor_state.ui_position);
+    let command = match command_mode.command {
+        Some(TargetedCommand::Move) => return,
+        Some(command) if mouse_button.just_pressed(MouseButton::Left) && !over_ui => command,
+        _ => return,
+    };
+    command_mode.command = None;
+    command_mode.awaiting_release = true;
+    let point = Vec3::new(
+        cursor_state.world_position.x,
+        cursor_state.world_position.y,
+        0f32,
+    );
+    let hovered = match *selection {
+        Selection::Hover(hovered) => hovered,
+        _ => None,
+    };
+    let hovered_team = hovered.and_then(|hovered| query.get_component::<Team>(hovered).ok());
+    match command {
+        TargetedCommand::Move => {}
+        TargetedCommand::AttackMove => {
+            if let (Some(target), Some(target_team)) = (hovered, hovered_team) {
+                if target_team.id != team.team.id {
+                    for (entity, ..) in selected {
+                        let new_orders = vec![Order::Ai(AIUnit::Attack(Attack {
+                            target,
+                            chase_when_target_too_far: true,
+                            return_position: None,
+                        }))];
+                        order_requests.issue(entity, new_orders, queue);
+                    }
+                    return;
+                }
+            }
+            let members: Vec<FormationMember> =
+                selected.iter().map(|(_, member, _)| *member).collect();
+            let targets = formation.formation.targets(&members, point);
+            let speeds: Vec<f32> = selected.iter().map(|(_, _, speed)| *speed).collect();
+            let speed_limit = group_speed_limit(&speeds);
+            let group_size = selected.len();
+            for ((entity, ..), destination) in selected.into_iter().zip(targets) {
+                // Same group movement as a move, small groups path on their own.
+                let mut mover = group_mover(destination, point, group_size, speed_limit);
+                if group_size < FLOW_FIELD_GROUP_SIZE {
+                    mover = mover.with_pathfinding();
+                }
+                let new_orders = vec![
+                    Order::Ai(AIUnit::SeekEnemy),
+                    Order::AttackMove(AttackMove {
+                        destination,
+                        mover: Some(mover),
+                    }),
+                ];
+                order_requests.issue(entity, new_orders, queue);
+            }
+        }
+        TargetedCommand::Patrol => {
+            for (entity, member, _) in selected {
+                // Queued patrols start where the previous orders end.
+                let origin = query
+                    .get_component::<Orders>(entity)
+                    .ok()
+                    .and_then(|orders| orders.last_waypoint())
+                    .filter(|_| queue)
+                    .unwrap_or(member.position);
+                let new_orders = vec![
+                    Order::Ai(AIUnit::SeekEnemy),
+                    Order::Patrol(Patrol {
+                        points: vec![origin, point],
+                        next: 1,
+                    }),
+                ];
+                order_requests.issue(entity, new_orders, queue);
+            }
+        }
+        TargetedCommand::Follow => {
+            let target = match (hovered, hovered_team) {
+                (Some(target), Some(target_team)) if target_team.id == team.team.id => target,
+                _ => return,
+            };
+            for (entity, ..) in selected {
+                if entity == target {
+                    continue;
+                }
+                let new_orders = vec![
+                    Order::Ai(AIUnit::SeekEnemy),
+                    Order::Follow(Follow { target }),
+                ];
+                order_requests.issue(entity, new_orders, queue);
+            }
+        }
+    }
+}
+
+/// The cursor shows a crosshair while a command waits for its target.
+pub fn command_cursor_system(command_mode: Res<CommandMode>, mut windows: ResMut<Windows>) {
+    if !command_mode.is_changed() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_icon(match command_mode.command {
+            Some(_) => CursorIcon::Crosshair,
+            None => CursorIcon::Default,
+        });
+    }
+}
+
+pub fn order_system_visual_startup(mut commands: Commands) {
     commands.insert_resource(OrderVisualResource {
-        move_material: materials.add(Color::rgb(1.0, 1.0, 0.0).into()),
-        attack_material: materials.add(Color::rgb(1.0, 0.2, 0.2).into()),
+        move_color: Color::rgb(1.0, 1.0, 0.0),
+        attack_color: Color::rgb(1.0, 0.2, 0.2),
+        attack_move_color: Color::rgb(1.0, 0.6, 0.0),
     });
 }
 
@@ -230,15 +455,29 @@ pub fn order_system_visual(
             } else {
                 vec![first_point]
             };
-        let mut material = &order_visual_resource.move_material;
+        let mut color = order_visual_resource.move_color;
         if let Some(override_order) = &orders.override_order {
-            material = &order_visual_resource.attack_material;
+            color = order_visual_resource.attack_color;
             if let Order::Move(Awaitable::Queued(mover)) = override_order {
                 waypoints
                     .push((mover.get_target_position().x, mover.get_target_position().y).into());
             }
         }
         orders.get_orders().iter().for_each(|o| {
+            if let Order::AttackMove(attack_move) = o {
+                if orders.override_order.is_none() {
+                    color = order_visual_resource.attack_move_color;
+                }
+                waypoints.push((attack_move.destination.x, attack_move.destination.y).into());
+            }
+            if let Order::Patrol(patrol) = o {
+                let points = patrol.points.iter().cycle().skip(patrol.next);
+                waypoints.extend(
+                    points
+                        .take(patrol.points.len() + 1)
+                        .map(|point| (point.x, point.y).into()),
+                );
+            }
             if let Order::Move(Awaitable::Awaiting(mover)) = o {
                 waypoints
                     .push((mover.get_target_position().x, mover.get_target_position().y).into());
@@ -259,7 +498,7 @@ pub fn order_system_visual(
             .entity(graphic_debug_entity)
             .insert_bundle(GeometryBuilder::build_as(
                 &line,
-                DrawMode::Stroke(StrokeMode::new(Color::BLACK, 10.0)),
+                DrawMode::Stroke(StrokeMode::new(color, 10.0)),
                 Transform::default(),
             ));
     }
@@ -3,3 +3,21 @@ use bevy::prelude::*;
 pub struct SelectionRectVisual {
     pub visual: Entity,
 }
+
+/// Clicks closer than this in time, in seconds, are double clicks or double taps.
+pub const DOUBLE_CLICK_DELAY: f64 = 0.3;
+
+/// Last unit clicked, to detect double clicks.
+#[derive(Default)]
+pub struct LastClick {
+    pub entity: Option<Entity>,
+    pub time: f64,
+}
+
+/// Units bound to the number keys, from 0 to 9.
+#[derive(Default)]
+pub struct ControlGroups {
+    pub groups: [Vec<Entity>; 10],
+    /// Last recalled group, to center the camera on a double tap.
+    pub last_recall: Option<(usize, f64)>,
+}
@@ -1,19 +1,70 @@
 use bevy::prelude::*;
 
-use crate::client::components::*;
+use crate::{
+    client::{
+        command_card::CommandCard,
+        components::*,
+        minimap::Minimap,
+        orders::orders_comp::{CommandMode, TeamResource},
+    },
+    core_game::{
+        components::{RenderSprite, Team},
+        spatial::SpatialIndex,
+    },
+};
 
 use super::{
-    helpers::helper_in_rect, helpers::helper_rect_in_rect, selection_comp::SelectionRectVisual,
+    helpers::helper_in_rect,
+    helpers::helper_rect_in_rect,
+    selection_comp::{ControlGroups, LastClick, SelectionRectVisual, DOUBLE_CLICK_DELAY},
 };
 
+/// Cursor moves shorter than this, in pixels, between press and release are clicks.
+const CLICK_DISTANCE: f32 = 4f32;
+
+fn is_shift_pressed(key_button: &Input<KeyCode>) -> bool {
+    key_button.pressed(KeyCode::LShift) || key_button.pressed(KeyCode::RShift)
+}
+fn is_control_pressed(key_button: &Input<KeyCode>) -> bool {
+    key_button.pressed(KeyCode::LControl) || key_button.pressed(KeyCode::RControl)
+}
+
+/// Box or click selection.
+///
+/// Shift adds boxed units or toggles the clicked one,
+/// Ctrl-click or double click selects the units of the same kind on screen.
 pub fn selection_system(
+    time: Res<Time>,
     cursor_state: Res<MyCursorState>,
     mut selection: ResMut<Selection>,
+    mut last_click: ResMut<LastClick>,
     mouse_button: Res<Input<MouseButton>>,
-    mut query: Query<(Entity, &mut Selectable, &Transform)>,
+    key_button: Res<Input<KeyCode>>,
+    spatial_index: Res<SpatialIndex>,
+    minimap: Res<Minimap>,
+    command_card: Res<CommandCard>,
+    command_mode: Res<CommandMode>,
+    windows: Res<Windows>,
+    main_camera: Res<MainCamera>,
+    q_camera: Query<(&Transform, &OrthographicProjection), Without<Selectable>>,
+    mut query: Query<(
+        Entity,
+        &mut Selectable,
+        &Transform,
+        &RenderSprite,
+        &Team,
+        &Visibility,
+    )>,
 ) {
     if mouse_button.pressed(MouseButton::Left) {
         if matches!(*selection, Selection::Hover(_)) {
+            // The minimap, the command card and commands waiting for a target handle their own clicks.
+            if minimap.contains(cursor_state.ui_position)
+                || command_card.contains(cursor_state.ui_position)
+                || command_mode.is_active()
+            {
+                return;
+            }
             let position = cursor_state.world_position.clone();
             *selection = Selection::OnGoing(SelectionPending {
                 begin_pos: position.clone(),
@@ -29,10 +80,18 @@ pub fn selection_system(
     }
     if let Selection::OnGoing(on_going) = &mut *selection {
         let mouse_pos_end = &cursor_state.world_position;
-        for (_, mut s, _) in query.iter_mut() {
-            s.is_selected = false;
-        }
-        for (_, mut a, b) in query.iter_mut() {
+        let begin = Vec2::new(on_going.begin_pos.x, on_going.begin_pos.y);
+        let end = Vec2::new(mouse_pos_end.x, mouse_pos_end.y);
+        let mut boxed = vec![];
+        for entry in spatial_index.in_rect(begin, end) {
+            let (_, a, b, _, _, visibility) = match query.get(entry.entity) {
+                Ok(selectable) => selectable,
+                Err(_) => continue,
+            };
+            // Units hidden by the fog can't be selected.
+            if !visibility.is_visible {
+                continue;
+            }
             let selectable_position = b.translation;
             let half_size = a.half_size;
             let c1 = Position {
@@ -44,11 +103,54 @@ pub fn selection_system(
                 y: selectable_position.y + half_size,
             };
             if helper_rect_in_rect((&c1, &c2), (&on_going.begin_pos, &mouse_pos_end)) {
-                a.is_selected = true;
+                boxed.push(entry.entity);
+            }
+        }
+
+        let is_click = on_going.begin_pos_ui.distance(on_going.end_pos_ui) < CLICK_DISTANCE;
+        let clicked = if is_click {
+            boxed.first().copied()
+        } else {
+            None
+        };
+        let now = time.seconds_since_startup();
+        let double_click = clicked.is_some()
+            && last_click.entity == clicked
+            && now - last_click.time < DOUBLE_CLICK_DELAY;
+        if is_click {
+            *last_click = LastClick {
+                entity: clicked,
+                time: now,
+            };
+        }
+        if let Some(clicked) = clicked {
+            if is_control_pressed(&key_button) || double_click {
+                boxed = same_kind_on_screen(clicked, &windows, &main_camera, &q_camera, &query);
+            }
+        }
+
+        let shift = is_shift_pressed(&key_button);
+        if !shift {
+            for (_, mut s, _, _, _, _) in query.iter_mut() {
+                s.is_selected = false;
+            }
+        }
+        for entity in boxed {
+            if let Ok((_, mut selectable, _, _, _, _)) = query.get_mut(entity) {
+                // A shift click toggles, a shift box adds.
+                selectable.is_selected = !(shift && is_click && selectable.is_selected);
             }
         }
     }
-    for (e, a, b) in query.iter_mut() {
+    let cursor = Vec2::new(cursor_state.world_position.x, cursor_state.world_position.y);
+    for entry in spatial_index.in_rect(cursor, cursor) {
+        let (e, a, b, _, _, visibility) = match query.get(entry.entity) {
+            Ok(selectable) => selectable,
+            Err(_) => continue,
+        };
+        if !visibility.is_visible {
+            continue;
+        }
         let selectable_position = b.translation;
         let half_size = a.half_size;
         let c1 = Position {
@@ -67,6 +169,139 @@ pub fn selection_system(
     *selection = Selection::Hover(None);
 }
 
+/// Shown units of the same team and `RenderSprite` as `clicked`, within the main camera view.
+fn same_kind_on_screen(
+    clicked: Entity,
+    windows: &Windows,
+    main_camera: &MainCamera,
+    q_camera: &Query<(&Transform, &OrthographicProjection), Without<Selectable>>,
+    query: &Query<(
+        Entity,
+        &mut Selectable,
+        &Transform,
+        &RenderSprite,
+        &Team,
+        &Visibility,
+    )>,
+) -> Vec<Entity> {
+    let (window, (camera, projection)) =
+        match (windows.get_primary(), q_camera.get(main_camera.camera_e)) {
+            (Some(window), Ok(camera)) => (window, camera),
+            _ => return vec![clicked],
+        };
+    let (sprite, team) = match query.get(clicked) {
+        Ok((_, _, _, sprite, team, _)) => (sprite.clone(), team.id),
+        Err(_) => return vec![],
+    };
+    let half_extents = Vec2::new(window.width(), window.height()) * projection.scale / 2f32;
+    let center = camera.translation.truncate();
+    query
+        .iter()
+        .filter(|(_, _, transform, other_sprite, other_team, visibility)| {
+            let offset = (transform.translation.truncate() - center).abs();
+            **other_sprite == sprite
+                && other_team.id == team
+                && visibility.is_visible
+                && offset.cmple(half_extents).all()
+        })
+        .map(|(entity, _, _, _, _, _)| entity)
+        .collect()
+}
+
+/// Ctrl + number assigns the selection to a group, Shift + number adds it,
+/// number alone recalls the group, and twice quickly centers the camera on it.
+pub fn control_group_system(
+    time: Res<Time>,
+    key_button: Res<Input<KeyCode>>,
+    team: Res<TeamResource>,
+    main_camera: Res<MainCamera>,
+    mut control_groups: ResMut<ControlGroups>,
+    mut q_camera: Query<&mut Transform, Without<Selectable>>,
+    mut query: Query<(Entity, &mut Selectable, &Transform, &Team)>,
+) {
+    // Dead units leave their groups.
+    for group in control_groups.groups.iter_mut() {
+        group.retain(|entity| query.get(*entity).is_ok());
+    }
+    const KEYS: [KeyCode; 10] = [
+        KeyCode::Key0,
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let index = match KEYS.iter().position(|key| key_button.just_pressed(*key)) {
+        Some(index) => index,
+        None => return,
+    };
+    if is_control_pressed(&key_button) || is_shift_pressed(&key_button) {
+        let selected = query
+            .iter()
+            .filter(|(_, selectable, _, unit_team)| {
+                selectable.is_selected && unit_team.id == team.team.id
+            })
+            .map(|(entity, _, _, _)| entity);
+        let group = &mut control_groups.groups[index];
+        if is_control_pressed(&key_button) {
+            group.clear();
+        }
+        for entity in selected {
+            if !group.contains(&entity) {
+                group.push(entity);
+            }
+        }
+        return;
+    }
+    let group = control_groups.groups[index].clone();
+    if group.is_empty() {
+        return;
+    }
+    for (entity, mut selectable, _, _) in query.iter_mut() {
+        let is_selected = group.contains(&entity);
+        if selectable.is_selected != is_selected {
+            selectable.is_selected = is_selected;
+        }
+    }
+    let now = time.seconds_since_startup();
+    if let Some((last_index, last_time)) = control_groups.last_recall {
+        if last_index == index && now - last_time < DOUBLE_CLICK_DELAY {
+            let positions: Vec<Vec2> = group
+                .iter()
+                .filter_map(|entity| query.get(*entity).ok())
+                .map(|(_, _, transform, _)| transform.translation.truncate())
+                .collect();
+            let center = positions.iter().sum::<Vec2>() / positions.len().max(1) as f32;
+            if let Ok(mut camera) = q_camera.get_mut(main_camera.camera_e) {
+                camera.translation.x = center.x;
+                camera.translation.y = center.y;
+            }
+        }
+    }
+    control_groups.last_recall = Some((index, now));
+}
+
+/// F2 selects every unit of the player's team.
+pub fn select_army_system(
+    key_button: Res<Input<KeyCode>>,
+    team: Res<TeamResource>,
+    mut query: Query<(&mut Selectable, &Team)>,
+) {
+    if !key_button.just_pressed(KeyCode::F2) {
+        return;
+    }
+    for (mut selectable, unit_team) in query.iter_mut() {
+        let is_selected = unit_team.id == team.team.id;
+        if selectable.is_selected != is_selected {
+            selectable.is_selected = is_selected;
+        }
+    }
+}
+
 pub fn selection_ui_visual(
     rect: Res<SelectionRectVisual>,
     selection: Res<Selection>,
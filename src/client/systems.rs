@@ -7,38 +7,25 @@ use bevy_prototype_lyon::prelude::{DrawMode, FillMode, GeometryBuilder, PathBuil
 use bevy_prototype_lyon::shapes;
 
 use crate::core_game::map::{Wall, WallSize};
+use crate::core_game::save::SaveGameRequest;
+use crate::core_game::units::{UnitDefinition, UnitDefinitions};
 
 use super::{super::core_game::components::*, selection::selection_comp::SelectionRectVisual};
 
 use super::components::*;
 
-pub fn create_render_resource(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let texture_goblin = asset_server.load("units/goblin.png");
-
+pub fn create_render_resource(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    definitions: Res<UnitDefinitions>,
+) {
     let mut render_sprite_visuals = HashMap::new();
-    render_sprite_visuals.insert(
-        RenderSprite::Goblin,
-        RenderSpriteVisual {
-            color: Color::rgb(0.1, 0.9, 0.3),
-            image: texture_goblin,
-        },
-    );
-    let texture_ogre = asset_server.load("units/ogre.png");
-    render_sprite_visuals.insert(
-        RenderSprite::Ogre,
-        RenderSpriteVisual {
-            color: Color::rgb(1.0, 0.5, 0.0),
-            image: texture_ogre,
-        },
-    );
-    let texture_bandit = asset_server.load("units/bandit.png");
-    render_sprite_visuals.insert(
-        RenderSprite::Bandit,
-        RenderSpriteVisual {
-            color: Color::rgb(0.6, 0.6, 0.6),
-            image: texture_bandit,
-        },
-    );
+    for definition in definitions.iter() {
+        render_sprite_visuals.insert(
+            RenderSprite(definition.name.clone()),
+            render_sprite_visual(&asset_server, definition),
+        );
+    }
 
     let color_selection = Color::rgba(1.0, 1.0, 1.0, 1.0);
     let team_colors = vec![
@@ -46,17 +33,49 @@ pub fn create_render_resource(mut commands: Commands, asset_server: Res<AssetSer
         Color::rgba(0.6, 0.6, 0.6, 0.8),
         Color::rgba(1.0, 0.0, 0.0, 0.8),
     ];
+    let color_other_teams = Color::rgba(1.0, 0.0, 1.0, 0.8);
     let color_walls = Color::rgba(1.0, 1.0, 1.0, 1.0);
 
     let render_sprites_resource = RenderResource {
         render_sprite_visuals,
         color_selection,
         team_colors,
+        color_other_teams,
         color_walls,
     };
     commands.insert_resource(render_sprites_resource);
 }
 
+fn render_sprite_visual(
+    asset_server: &AssetServer,
+    definition: &UnitDefinition,
+) -> RenderSpriteVisual {
+    let (r, g, b) = definition.color;
+    RenderSpriteVisual {
+        color: Color::rgb(r, g, b),
+        image: asset_server.load(definition.sprite.as_str()),
+    }
+}
+
+/// Keeps visuals of hot reloaded unit definitions up to date, for units spawned afterwards.
+pub fn render_resource_reload_system(
+    mut events: EventReader<AssetEvent<UnitDefinition>>,
+    assets: Res<Assets<UnitDefinition>>,
+    asset_server: Res<AssetServer>,
+    mut render: ResMut<RenderResource>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            if let Some(definition) = assets.get(handle) {
+                render.render_sprite_visuals.insert(
+                    RenderSprite(definition.name.clone()),
+                    render_sprite_visual(&asset_server, definition),
+                );
+            }
+        }
+    }
+}
+
 pub fn create_camera(mut commands: Commands) {
     let camera = Camera2dBundle::default();
     let e = commands.spawn().insert_bundle(camera).id();
@@ -156,7 +175,7 @@ pub fn adapt_units_for_client(
                 .spawn()
                 .insert_bundle(GeometryBuilder::build_as(
                     &circleShape,
-                    DrawMode::Stroke(StrokeMode::new(render.team_colors[team.id], 3.0 / 20.0)),
+                    DrawMode::Stroke(StrokeMode::new(render.team_color(team.id), 3.0 / 20.0)),
                     Transform::from_translation(Vec2::ZERO.extend(0.1))
                         .with_scale(Vec2::splat(size.0).extend(1.0)),
                 ))
@@ -165,7 +184,7 @@ pub fn adapt_units_for_client(
                 &triangleShape,
                 DrawMode::Outlined {
                     fill_mode: FillMode::color(Color::NONE),
-                    outline_mode: StrokeMode::new(render.team_colors[team.id], 5.0 / 20.0),
+                    outline_mode: StrokeMode::new(render.team_color(team.id), 5.0 / 20.0),
                 },
                 Transform::from_translation(Vec2::ZERO.extend(0.1))
                     .with_scale(Vec2::splat(size.0).extend(1.0)),
@@ -198,6 +217,30 @@ pub fn adapt_units_for_client(
     }
 }
 
+pub fn projectile_visual_setup(
+    mut commands: Commands,
+    render: Res<RenderResource>,
+    query: Query<(Entity, &Projectile), Added<Projectile>>,
+) {
+    let circleShape = shapes::Circle {
+        radius: 3.0,
+        ..Default::default()
+    };
+    for (entity, projectile) in query.iter() {
+        commands
+            .entity(entity)
+            .insert(Visibility::visible())
+            .insert(ComputedVisibility::default())
+            .with_children(|parent| {
+                parent.spawn().insert_bundle(GeometryBuilder::build_as(
+                    &circleShape,
+                    DrawMode::Fill(FillMode::color(render.team_color(projectile.team))),
+                    Transform::from_translation(Vec2::ZERO.extend(0.5)),
+                ));
+            });
+    }
+}
+
 pub fn no_rotation(
     mut q_no_rotation: Query<(Entity, &Parent, &mut Transform), With<NoRotation>>,
     mut q_parent: Query<&mut Transform, Without<NoRotation>>,
@@ -208,34 +251,49 @@ pub fn no_rotation(
     }
 }
 
+pub fn quicksave_system(
+    key_button: Res<Input<KeyCode>>,
+    mut save_request: ResMut<SaveGameRequest>,
+) {
+    if key_button.just_pressed(KeyCode::F5) {
+        save_request.path = Some("quicksave.ron".into());
+    }
+}
+
 /// Adapted from https://github.com/jamadazi/bevy-cookbook/blob/master/bevy-cookbook.md#convert-screen-coordinates-to-world-coordinates
+///
+/// The world position is updated every frame, as the camera may move under a still cursor.
 pub fn mouse_world_position_system(
     mut state: ResMut<MyCursorState>,
     mut ev_cursor: EventReader<CursorMoved>,
     // need to get window dimensions
     wnds: Res<Windows>,
     // query to get camera components
-    q_camera: Query<&Transform>,
+    q_camera: Query<(&Transform, &OrthographicProjection)>,
 ) {
-    let camera_transform = q_camera.get_component::<Transform>(state.camera_e).unwrap();
-
     if let Some(ev) = ev_cursor.iter().last() {
-        let wnd = wnds.get(ev.id).unwrap();
-        let size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
-
-        // the default orthographic projection is in pixels from the center;
-        // just undo the translation
-        let p = ev.position - size / 2.0;
-
-        // apply the camera transform
-        let pos_wld = camera_transform.compute_matrix() * p.extend(0.0).extend(1.0);
-        let position = Position {
-            x: pos_wld.x,
-            y: pos_wld.y,
-        };
-        state.world_position = position;
         state.ui_position = ev.position;
     }
+    let (camera_transform, projection) = match q_camera.get(state.camera_e) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let wnd = match wnds.get_primary() {
+        Some(wnd) => wnd,
+        None => return,
+    };
+    let size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
+
+    // the default orthographic projection is in pixels from the center, times its scale;
+    // just undo the translation and the zoom
+    let p = (state.ui_position - size / 2.0) * projection.scale;
+
+    // apply the camera transform
+    let pos_wld = camera_transform.compute_matrix() * p.extend(0.0).extend(1.0);
+    state.world_position = Position {
+        x: pos_wld.x,
+        y: pos_wld.y,
+    };
 }
 
 // Mod health
@@ -358,7 +416,15 @@ pub mod ability {
     use bevy::prelude::*;
     use bevy_prototype_lyon::{entity::ShapeBundle, prelude::*};
 
-    use crate::{client::components::NoRotation, core_game::components::*};
+    use crate::{
+        client::components::NoRotation,
+        core_game::{
+            abilities::{Abilities, AbilityState},
+            components::*,
+            simulation::SimulationTime,
+            status_effects::{self, StatusEffects},
+        },
+    };
 
     pub struct AbilityVisualResource {
         background: Color,
@@ -421,10 +487,7 @@ pub mod ability {
         mut commands: Commands,
         mut ability_visual_resource: Res<AbilityVisualResource>,
         mut meshes: ResMut<Assets<Mesh>>,
-        mut q_orders: Query<
-            (Entity, &MeleeAbility, &MeleeAbilityState, &UnitSize),
-            Without<AbilityVisual>,
-        >,
+        mut q_orders: Query<(Entity, &Abilities, &AbilityState, &UnitSize), Without<AbilityVisual>>,
     ) {
         for (entity, _, _, size) in q_orders.iter_mut() {
             let sprites =
@@ -458,49 +521,36 @@ pub mod ability {
 
     pub fn ability_visual(
         mut commands: Commands,
-        time: Res<Time>,
+        time: Res<SimulationTime>,
         mut ability_visual_resource: Res<AbilityVisualResource>,
         mut meshes: ResMut<Assets<Mesh>>,
-        mut q_orders: Query<(&MeleeAbility, &MeleeAbilityState, &AbilityVisual, &UnitSize)>,
+        mut q_orders: Query<(
+            &Abilities,
+            &AbilityState,
+            &AbilityVisual,
+            &UnitSize,
+            Option<&StatusEffects>,
+        )>,
     ) {
-        for (ability, state, visual, size) in q_orders.iter_mut() {
-            let sprites = match state {
-                MeleeAbilityState::Ready => Some(create_ability_visual(
-                    &mut ability_visual_resource,
-                    &mut meshes,
-                    size,
-                    0f32,
-                )),
-                MeleeAbilityState::WillAttack(will_attack) => {
-                    let ratio = (time.seconds_since_startup() as f32 - will_attack.start_time)
-                        / ability.time_to_strike;
-                    Some(create_ability_visual(
-                        &mut ability_visual_resource,
-                        &mut meshes,
-                        size,
-                        ratio,
-                    ))
-                }
-                MeleeAbilityState::AttackCooldown(cooldown) => {
-                    let ratio = (time.seconds_since_startup() as f32 - cooldown.start_time)
-                        / ability.cooldown;
-                    Some(create_ability_visual(
-                        &mut ability_visual_resource,
-                        &mut meshes,
-                        size,
-                        1f32 - ratio,
-                    ))
-                }
-                MeleeAbilityState::MotionBufferExceeded => {
-                    let ratio = 0.0;
-                    Some(create_ability_visual(
-                        &mut ability_visual_resource,
-                        &mut meshes,
-                        size,
-                        ratio,
-                    ))
-                }
+        for (abilities, state, visual, size, status_effects) in q_orders.iter_mut() {
+            let now = time.elapsed_seconds();
+            let attack_speed = status_effects::attack_speed_multiplier(status_effects);
+            // Fills up while casting, then empties during the attack cooldown.
+            let ratio = match state.get_casting() {
+                Some(casting) => abilities.0.get(casting.ability).map_or(0f32, |ability| {
+                    (now - casting.start_time) * attack_speed / ability.cast_time.max(f32::EPSILON)
+                }),
+                None => abilities.attack().map_or(0f32, |(index, ability)| {
+                    (state.ready_at(index) - now).max(0f32) * attack_speed
+                        / ability.cooldown.max(f32::EPSILON)
+                }),
             };
+            let sprites = Some(create_ability_visual(
+                &mut ability_visual_resource,
+                &mut meshes,
+                size,
+                ratio,
+            ));
             if let Some(sprites) = sprites {
                 commands.entity(visual.background).insert_bundle(sprites.0);
                 commands.entity(visual.background).insert_bundle(sprites.1);
@@ -508,3 +558,108 @@ pub mod ability {
         }
     }
 }
+
+pub mod status {
+    use bevy::prelude::*;
+    use bevy_prototype_lyon::prelude::*;
+
+    use crate::{
+        client::components::NoRotation,
+        core_game::{
+            components::*,
+            damage::DamageOverTime,
+            status_effects::{Status, StatusEffects},
+        },
+    };
+
+    /// Colors of the status icons, by `icon_index`, damage over time being shown as poison.
+    const ICON_COLORS: [Color; 5] = [
+        Color::YELLOW,
+        Color::rgb(0.3, 0.5, 1.0),
+        Color::rgb(0.2, 0.8, 0.2),
+        Color::ORANGE,
+        Color::rgb(0.9, 0.1, 0.1),
+    ];
+    const ICON_SIZE: f32 = 4f32;
+    const ICON_SPACING: f32 = 5f32;
+
+    fn icon_index(status: &Status) -> usize {
+        match status {
+            Status::Stun => 0,
+            Status::Slow { .. } => 1,
+            Status::Haste { .. } => 3,
+            Status::Empower { .. } => 4,
+        }
+    }
+    const DAMAGE_OVER_TIME_ICON: usize = 2;
+
+    /// One icon per kind of status, shown above the health bar while the unit has it.
+    #[derive(Component)]
+    pub struct StatusVisual {
+        pub icons: Vec<Entity>,
+    }
+
+    fn icon_transform(size: &UnitSize, slot: usize) -> Transform {
+        let x = -10f32 + slot as f32 * ICON_SPACING;
+        Transform::from_translation(Vec3::new(x, size.0 + ICON_SPACING, 2.0))
+    }
+
+    pub fn status_visual_setup(
+        mut commands: Commands,
+        q_units: Query<(Entity, &UnitSize), (With<StatusEffects>, Without<StatusVisual>)>,
+    ) {
+        for (entity, size) in q_units.iter() {
+            let shape = shapes::Rectangle {
+                extents: Vec2::splat(ICON_SIZE),
+                origin: RectangleOrigin::BottomLeft,
+            };
+            let mut icons = vec![];
+            commands.entity(entity).with_children(|parent| {
+                for (index, color) in ICON_COLORS.iter().enumerate() {
+                    let mut icon = GeometryBuilder::build_as(
+                        &shape,
+                        DrawMode::Fill(FillMode::color(*color)),
+                        icon_transform(size, index),
+                    );
+                    icon.visibility.is_visible = false;
+                    icons.push(parent.spawn_bundle(icon).insert(NoRotation).id());
+                }
+            });
+            commands.entity(entity).insert(StatusVisual { icons });
+        }
+    }
+
+    pub fn status_visual(
+        mut commands: Commands,
+        q_units: Query<
+            (
+                &StatusEffects,
+                Option<&DamageOverTime>,
+                &StatusVisual,
+                &UnitSize,
+            ),
+            Or<(Changed<StatusEffects>, Changed<DamageOverTime>)>,
+        >,
+    ) {
+        for (status_effects, damage_over_time, visual, size) in q_units.iter() {
+            let mut active = [false; ICON_COLORS.len()];
+            for effect in status_effects.effects.iter() {
+                active[icon_index(&effect.status)] = true;
+            }
+            active[DAMAGE_OVER_TIME_ICON] = damage_over_time.map_or(false, |damage_over_time| {
+                !damage_over_time.effects.is_empty()
+            });
+            // Active icons are packed to the left.
+            let mut slot = 0;
+            for (index, icon) in visual.icons.iter().enumerate() {
+                commands.entity(*icon).insert(Visibility {
+                    is_visible: active[index],
+                });
+                if active[index] {
+                    commands.entity(*icon).insert(icon_transform(size, slot));
+                    slot += 1;
+                }
+            }
+        }
+    }
+}
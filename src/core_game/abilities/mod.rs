@@ -0,0 +1,541 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    combat_log::AttackInterrupted,
+    components::{Health, OffensiveStats, Projectile, SufferDamage, Team, UnitSize},
+    damage::{DamageKind, DamageOverTime},
+    simulation::{SimulationLabel, SimulationSchedule, SimulationStage, SimulationTime},
+    spatial::SpatialIndex,
+    status_effects::{self, Status, StatusEffects},
+};
+
+pub struct AbilitiesPlugin;
+
+impl Plugin for AbilitiesPlugin {
+    fn build(&self, app: &mut App) {
+        app.stage(SimulationSchedule, |schedule: &mut Schedule| {
+            schedule
+                .add_system_to_stage(
+                    SimulationStage::Update,
+                    ability_system
+                        .label(SimulationLabel::Abilities)
+                        .after(SimulationLabel::Orders),
+                )
+                .add_system_to_stage(
+                    SimulationStage::Update,
+                    mana_system
+                        .label(SimulationLabel::Mana)
+                        .after(SimulationLabel::Abilities),
+                )
+        });
+    }
+}
+
+/// What an ability is cast on.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Targeting {
+    Unit,
+    Point,
+    /// The caster itself, range is ignored.
+    Myself,
+    /// Every unit within `radius` of a point: enemies for harmful effects, allies otherwise.
+    Area {
+        radius: f32,
+    },
+}
+
+/// What happens when the cast completes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AbilityEffect {
+    /// Damages targets by the caster's power, multiplied by `ratio`.
+    Damage {
+        ratio: f32,
+        #[serde(default)]
+        kind: DamageKind,
+    },
+    /// Shoots a `Projectile` at the target, damaging the first enemy on its way by the caster's power.
+    Projectile {
+        speed: f32,
+        #[serde(default)]
+        kind: DamageKind,
+    },
+    /// Damages targets by the caster's power multiplied by `ratio`, spread over `duration` seconds.
+    DamageOverTime {
+        ratio: f32,
+        duration: f32,
+        #[serde(default)]
+        kind: DamageKind,
+    },
+    /// Gives back `amount` health to targets, up to their maximum.
+    Heal { amount: f32 },
+    /// Applies `status` to targets for `duration` seconds.
+    ApplyStatus { status: Status, duration: f32 },
+}
+
+impl AbilityEffect {
+    pub fn is_harmful(&self) -> bool {
+        match self {
+            AbilityEffect::ApplyStatus { status, .. } => status.is_harmful(),
+            AbilityEffect::Heal { .. } => false,
+            _ => self.deals_damage(),
+        }
+    }
+    pub fn deals_damage(&self) -> bool {
+        matches!(
+            self,
+            AbilityEffect::Damage { .. }
+                | AbilityEffect::Projectile { .. }
+                | AbilityEffect::DamageOverTime { .. }
+        )
+    }
+}
+
+/// What cancels a cast before it completes, its cost is not refunded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Interruption {
+    /// When the unit is ordered to stop fighting.
+    pub by_orders: bool,
+    /// When the unit takes damage.
+    pub by_damage: bool,
+}
+impl Default for Interruption {
+    fn default() -> Self {
+        Interruption {
+            by_orders: true,
+            by_damage: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AbilityDefinition {
+    pub name: String,
+    pub targeting: Targeting,
+    pub range: f32,
+    // additional range to account for units movement
+    #[serde(default)]
+    pub motion_buffer_range: f32,
+    /// Seconds between the start of the cast and its effect.
+    pub cast_time: f32,
+    /// Seconds after the effect before the ability can be cast again.
+    pub cooldown: f32,
+    /// `Mana` spent when the cast starts.
+    #[serde(default)]
+    pub cost: f32,
+    #[serde(default)]
+    pub interruption: Interruption,
+    pub effect: AbilityEffect,
+}
+
+/// Abilities of a unit, the first one dealing damage to a unit is its attack.
+#[derive(Component, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Abilities(pub Vec<AbilityDefinition>);
+
+impl Abilities {
+    /// Index and definition of the ability used by the AI to attack.
+    pub fn attack(&self) -> Option<(usize, &AbilityDefinition)> {
+        self.0.iter().enumerate().find(|(_, ability)| {
+            ability.targeting == Targeting::Unit && ability.effect.deals_damage()
+        })
+    }
+}
+
+/// Spent to cast abilities having a cost.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Mana {
+    pub max: f32,
+    pub current: f32,
+    /// Regained per second.
+    pub regeneration: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AbilityTarget {
+    Unit(#[serde(with = "crate::core_game::serialization::entity_index")] Entity),
+    Point(Vec3),
+    Myself,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Casting {
+    /// Index in the unit's `Abilities`.
+    pub ability: usize,
+    pub target: AbilityTarget,
+    pub start_time: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptCause {
+    Orders,
+    Damage,
+    /// The target moved out of range, beyond the motion buffer.
+    TargetOutOfRange,
+    /// The target died.
+    TargetGone,
+    /// The caster is stunned.
+    Stunned,
+}
+
+/// What a unit is casting, and when its abilities are ready again.
+#[derive(Component, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct AbilityState {
+    casting: Option<Casting>,
+    /// Time at which each ability is off cooldown, by index in `Abilities`.
+    ready_at: Vec<f32>,
+    /// The last cast was cancelled because its target went out of range, for a single tick.
+    target_lost: bool,
+}
+
+impl AbilityState {
+    pub fn get_casting(&self) -> Option<&Casting> {
+        self.casting.as_ref()
+    }
+    pub fn is_casting(&self) -> bool {
+        self.casting.is_some()
+    }
+    pub fn is_target_lost(&self) -> bool {
+        self.target_lost
+    }
+    pub fn ready_at(&self, ability: usize) -> f32 {
+        self.ready_at.get(ability).copied().unwrap_or(0f32)
+    }
+    /// Not casting anything, and `ability` is off cooldown.
+    pub fn is_ready(&self, ability: usize, time: f32) -> bool {
+        self.casting.is_none() && time >= self.ready_at(ability)
+    }
+    /// Starts casting `ability` if ready and affordable, spending its cost.
+    pub fn try_cast(
+        &mut self,
+        abilities: &Abilities,
+        ability: usize,
+        target: AbilityTarget,
+        time: f32,
+        mana: Option<&mut Mana>,
+    ) -> Result<(), ()> {
+        let definition = abilities.0.get(ability).ok_or(())?;
+        if !self.is_ready(ability, time) {
+            return Err(());
+        }
+        if definition.cost > 0f32 {
+            let mana = mana.ok_or(())?;
+            if mana.current < definition.cost {
+                return Err(());
+            }
+            mana.current -= definition.cost;
+        }
+        self.casting = Some(Casting {
+            ability,
+            target,
+            start_time: time,
+        });
+        Ok(())
+    }
+    /// Cancels the current cast if its interruption rules allow it, returns the cancelled ability.
+    pub fn interrupt(&mut self, abilities: &Abilities, cause: InterruptCause) -> Option<usize> {
+        let casting = self.casting.as_ref()?;
+        let interruption = &abilities.0.get(casting.ability)?.interruption;
+        let interrupted = match cause {
+            InterruptCause::Orders => interruption.by_orders,
+            InterruptCause::Damage => interruption.by_damage,
+            InterruptCause::TargetOutOfRange
+            | InterruptCause::TargetGone
+            | InterruptCause::Stunned => true,
+        };
+        if !interrupted {
+            return None;
+        }
+        self.target_lost = cause == InterruptCause::TargetOutOfRange;
+        self.casting.take().map(|casting| casting.ability)
+    }
+    fn complete(&mut self, ability: usize, cooldown_end: f32) {
+        if self.ready_at.len() <= ability {
+            self.ready_at.resize(ability + 1, 0f32);
+        }
+        self.ready_at[ability] = cooldown_end;
+        self.casting = None;
+    }
+    /// Forgets the cast and cooldowns of abilities no longer in `abilities`, e.g. on reload.
+    pub fn retain_abilities(&mut self, abilities: &Abilities) {
+        self.ready_at.truncate(abilities.0.len());
+        if let Some(casting) = &self.casting {
+            if casting.ability >= abilities.0.len() {
+                self.casting = None;
+            }
+        }
+    }
+    /// Replaces the entities this state refers to, fails if one of them can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) -> Result<(), ()> {
+        if let Some(Casting {
+            target: AbilityTarget::Unit(target),
+            ..
+        }) = &mut self.casting
+        {
+            *target = mapper(*target).ok_or(())?;
+        }
+        Ok(())
+    }
+    /// Drops the cast, keeping cooldowns.
+    pub fn reset_casting(&mut self) {
+        self.casting = None;
+    }
+}
+
+/// Advances casts, applying their effect once their cast time is elapsed.
+pub fn ability_system(
+    mut commands: Commands,
+    time: Res<SimulationTime>,
+    spatial_index: Res<SpatialIndex>,
+    mut attack_interrupted: EventWriter<AttackInterrupted>,
+    mut q_casters: Query<(
+        Entity,
+        &Transform,
+        &UnitSize,
+        &Team,
+        &Abilities,
+        &mut AbilityState,
+        &OffensiveStats,
+    )>,
+    q_targets: Query<(&Transform, &UnitSize)>,
+    mut q_victims: Query<(
+        &mut Health,
+        &mut SufferDamage,
+        Option<&mut StatusEffects>,
+        Option<&mut DamageOverTime>,
+    )>,
+) {
+    for (entity, transform, size, team, abilities, mut state, offensive_stats) in
+        q_casters.iter_mut()
+    {
+        state.target_lost = false;
+        let casting = match &state.casting {
+            Some(casting) => casting.clone(),
+            None => continue,
+        };
+        let ability = match abilities.0.get(casting.ability) {
+            Some(ability) => ability,
+            None => {
+                state.casting = None;
+                continue;
+            }
+        };
+        let status_effects = q_victims
+            .get(entity)
+            .ok()
+            .and_then(|(_, _, status_effects, _)| status_effects);
+        if status_effects::is_stunned(status_effects) {
+            let cause = InterruptCause::Stunned;
+            if let Some(ability) = state.interrupt(abilities, cause) {
+                attack_interrupted.send(AttackInterrupted {
+                    attacker: entity,
+                    ability,
+                    cause,
+                });
+            }
+            continue;
+        }
+        let attack_speed = status_effects::attack_speed_multiplier(status_effects);
+        let power_ratio = status_effects::power_multiplier(status_effects);
+        let (target_position, target_size) = match casting.target {
+            AbilityTarget::Unit(target) => match q_targets.get(target) {
+                Ok((target_transform, target_size)) => {
+                    (target_transform.translation, target_size.0)
+                }
+                Err(_) => {
+                    let cause = InterruptCause::TargetGone;
+                    if let Some(ability) = state.interrupt(abilities, cause) {
+                        attack_interrupted.send(AttackInterrupted {
+                            attacker: entity,
+                            ability,
+                            cause,
+                        });
+                    }
+                    continue;
+                }
+            },
+            AbilityTarget::Point(point) => (point, 0f32),
+            AbilityTarget::Myself => (transform.translation, 0f32),
+        };
+        let offset = (target_position - transform.translation).truncate();
+        if ability.targeting != Targeting::Myself
+            && offset.length() > ability.range + ability.motion_buffer_range + size.0 + target_size
+        {
+            let cause = InterruptCause::TargetOutOfRange;
+            if let Some(ability) = state.interrupt(abilities, cause) {
+                attack_interrupted.send(AttackInterrupted {
+                    attacker: entity,
+                    ability,
+                    cause,
+                });
+            }
+            continue;
+        }
+        let now = time.elapsed_seconds();
+        if now <= casting.start_time + ability.cast_time / attack_speed {
+            continue;
+        }
+        state.complete(casting.ability, now + ability.cooldown / attack_speed);
+
+        if let AbilityEffect::Projectile { speed, kind } = ability.effect {
+            // Aimed at where the target is now, it may have moved on impact.
+            let direction = offset.try_normalize().unwrap_or(Vec2::X);
+            let start = transform.translation + (direction * size.0).extend(0f32);
+            commands.spawn_bundle((
+                Projectile {
+                    team: team.id,
+                    damage: offensive_stats
+                        .damage(power_ratio, kind)
+                        .with_attacker(entity),
+                    velocity: direction * speed,
+                    remaining_distance: ability.range + target_size,
+                },
+                Transform::from_translation(start),
+                GlobalTransform::from_translation(start),
+            ));
+            continue;
+        }
+        let affected: Vec<Entity> = match (ability.targeting, casting.target) {
+            (Targeting::Area { radius }, _) => spatial_index
+                .in_circle(target_position.truncate(), radius)
+                .filter(|entry| (entry.team != team.id) == ability.effect.is_harmful())
+                .map(|entry| entry.entity)
+                .collect(),
+            (_, AbilityTarget::Unit(target)) => vec![target],
+            (_, AbilityTarget::Myself) => vec![entity],
+            (_, AbilityTarget::Point(_)) => vec![],
+        };
+        for victim in affected {
+            let (mut health, mut suffer_damage, status_effects, damage_over_time) =
+                match q_victims.get_mut(victim) {
+                    Ok(victim) => victim,
+                    Err(_) => continue,
+                };
+            match &ability.effect {
+                AbilityEffect::Damage { ratio, kind } => suffer_damage.new_damage(
+                    offensive_stats
+                        .damage(ratio * power_ratio, *kind)
+                        .with_attacker(entity),
+                ),
+                AbilityEffect::DamageOverTime {
+                    ratio,
+                    duration,
+                    kind,
+                } => {
+                    let total = offensive_stats.power * ratio * power_ratio;
+                    match damage_over_time {
+                        Some(mut damage_over_time) => {
+                            damage_over_time.add(total, *kind, *duration, Some(entity))
+                        }
+                        None => {
+                            let mut damage_over_time = DamageOverTime::default();
+                            damage_over_time.add(total, *kind, *duration, Some(entity));
+                            commands.entity(victim).insert(damage_over_time);
+                        }
+                    }
+                }
+                AbilityEffect::ApplyStatus { status, duration } => match status_effects {
+                    Some(mut status_effects) => {
+                        status_effects.add(status.clone(), *duration, Some(entity))
+                    }
+                    None => {
+                        let mut status_effects = StatusEffects::default();
+                        status_effects.add(status.clone(), *duration, Some(entity));
+                        commands.entity(victim).insert(status_effects);
+                    }
+                },
+                AbilityEffect::Heal { amount } => {
+                    health.current_hp = (health.current_hp + amount).min(health.max_hp)
+                }
+                AbilityEffect::Projectile { .. } => {}
+            }
+        }
+    }
+}
+
+pub fn mana_system(time: Res<SimulationTime>, mut q_mana: Query<&mut Mana>) {
+    for mut mana in q_mana.iter_mut() {
+        if mana.current < mana.max {
+            mana.current = (mana.current + mana.regeneration * time.delta_seconds()).min(mana.max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn abilities() -> Abilities {
+        let ability = |name: &str, cost: f32, effect: AbilityEffect| AbilityDefinition {
+            name: name.to_string(),
+            targeting: Targeting::Unit,
+            range: 5f32,
+            motion_buffer_range: 3f32,
+            cast_time: 0.4,
+            cooldown: 1f32,
+            cost,
+            interruption: Interruption::default(),
+            effect,
+        };
+        Abilities(vec![
+            ability("heal", 10f32, AbilityEffect::Heal { amount: 5f32 }),
+            ability(
+                "strike",
+                0f32,
+                AbilityEffect::Damage {
+                    ratio: 1f32,
+                    kind: DamageKind::Physical,
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn attack_is_first_harmful_unit_ability() {
+        assert_eq!(abilities().attack().map(|(index, _)| index), Some(1));
+    }
+    #[test]
+    fn cast_spends_mana_and_waits_for_cooldown() {
+        let abilities = abilities();
+        let mut mana = Mana {
+            max: 15f32,
+            current: 15f32,
+            regeneration: 0f32,
+        };
+        let mut state = AbilityState::default();
+        assert!(state
+            .try_cast(&abilities, 0, AbilityTarget::Myself, 0f32, None)
+            .is_err());
+        assert!(state
+            .try_cast(&abilities, 0, AbilityTarget::Myself, 0f32, Some(&mut mana))
+            .is_ok());
+        assert_eq!(mana.current, 5f32);
+        // Already casting.
+        assert!(state
+            .try_cast(&abilities, 1, AbilityTarget::Myself, 0f32, None)
+            .is_err());
+        state.complete(0, 1.5);
+        assert!(state.is_ready(1, 1f32));
+        assert!(!state.is_ready(0, 1f32));
+        mana.current = 15f32;
+        assert!(state
+            .try_cast(&abilities, 0, AbilityTarget::Myself, 1.5, Some(&mut mana))
+            .is_ok());
+    }
+    #[test]
+    fn interruption_rules() {
+        let abilities = abilities();
+        let mut state = AbilityState::default();
+        state
+            .try_cast(&abilities, 1, AbilityTarget::Myself, 0f32, None)
+            .unwrap();
+        assert_eq!(state.interrupt(&abilities, InterruptCause::Damage), None);
+        assert!(state.is_casting());
+        assert_eq!(state.interrupt(&abilities, InterruptCause::Orders), Some(1));
+        assert!(!state.is_casting());
+        state
+            .try_cast(&abilities, 1, AbilityTarget::Myself, 0f32, None)
+            .unwrap();
+        state.interrupt(&abilities, InterruptCause::TargetOutOfRange);
+        assert!(state.is_target_lost());
+    }
+}
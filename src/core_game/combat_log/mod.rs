@@ -0,0 +1,219 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+
+use super::{
+    abilities::{AbilityTarget, InterruptCause},
+    damage::DamageKind,
+    simulation::{SimulationSchedule, SimulationStage, SimulationTime},
+};
+
+/// Entries kept in the log, older ones only remain in its statistics.
+const MAX_ENTRIES: usize = 10_000;
+
+pub struct CombatLogPlugin;
+
+impl Plugin for CombatLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageDealt>()
+            .add_event::<UnitDied>()
+            .add_event::<AttackStarted>()
+            .add_event::<AttackInterrupted>()
+            .init_resource::<CombatLog>()
+            .stage(SimulationSchedule, |schedule: &mut Schedule| {
+                schedule.add_stage_after(
+                    SimulationStage::PostUpdate,
+                    CombatLogStage,
+                    SystemStage::single_threaded().with_system(combat_log_system),
+                )
+            });
+    }
+}
+
+/// Records the events of a tick once they are all sent, before the tick advances.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct CombatLogStage;
+
+/// Sent for each resolved hit.
+#[derive(Clone, Debug)]
+pub struct DamageDealt {
+    pub attacker: Option<Entity>,
+    pub victim: Entity,
+    /// Health lost.
+    pub amount: f32,
+    /// Damage prevented by the victim's `Defense`.
+    pub mitigated: f32,
+    pub kind: DamageKind,
+    pub critical: bool,
+}
+
+/// Sent when a unit runs out of health, before it is despawned.
+#[derive(Clone, Debug)]
+pub struct UnitDied {
+    pub entity: Entity,
+    /// Last unit which hurt it.
+    pub killer: Option<Entity>,
+    /// `None` for units without a team.
+    pub team: Option<usize>,
+}
+
+/// Sent when a unit starts casting one of its abilities.
+#[derive(Clone, Debug)]
+pub struct AttackStarted {
+    pub attacker: Entity,
+    /// Index in the attacker's `Abilities`.
+    pub ability: usize,
+    pub target: AbilityTarget,
+}
+
+/// Sent when a cast is cancelled before its effect.
+#[derive(Clone, Debug)]
+pub struct AttackInterrupted {
+    pub attacker: Entity,
+    /// Index in the attacker's `Abilities`.
+    pub ability: usize,
+    pub cause: InterruptCause,
+}
+
+#[derive(Clone, Debug)]
+pub enum CombatEvent {
+    DamageDealt(DamageDealt),
+    UnitDied(UnitDied),
+    AttackStarted(AttackStarted),
+    AttackInterrupted(AttackInterrupted),
+}
+
+#[derive(Clone, Debug)]
+pub struct CombatLogEntry {
+    pub tick: u64,
+    pub event: CombatEvent,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct UnitCombatStats {
+    pub damage_dealt: f32,
+    pub damage_taken: f32,
+    pub kills: u32,
+}
+
+/// Combat events of the current match, with statistics about them.
+///
+/// Entities are the ones of this run, so the log is not saved with the game.
+#[derive(Default)]
+pub struct CombatLog {
+    entries: VecDeque<CombatLogEntry>,
+    units: HashMap<Entity, UnitCombatStats>,
+    /// Units lost by each team.
+    losses: HashMap<usize, u32>,
+}
+
+impl CombatLog {
+    pub fn push(&mut self, tick: u64, event: CombatEvent) {
+        match &event {
+            CombatEvent::DamageDealt(damage) => {
+                if let Some(attacker) = damage.attacker {
+                    self.units.entry(attacker).or_default().damage_dealt += damage.amount;
+                }
+                self.units.entry(damage.victim).or_default().damage_taken += damage.amount;
+            }
+            CombatEvent::UnitDied(death) => {
+                if let Some(killer) = death.killer {
+                    self.units.entry(killer).or_default().kills += 1;
+                }
+                if let Some(team) = death.team {
+                    *self.losses.entry(team).or_default() += 1;
+                }
+            }
+            CombatEvent::AttackStarted(_) | CombatEvent::AttackInterrupted(_) => {}
+        }
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(CombatLogEntry { tick, event });
+    }
+    /// Latest entries, from oldest to newest.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &CombatLogEntry> {
+        self.entries.iter()
+    }
+    /// Entries from `tick` on, e.g. for a kill feed or an AI reacting to recent events.
+    pub fn since(&self, tick: u64) -> impl DoubleEndedIterator<Item = &CombatLogEntry> {
+        let start = self.entries.partition_point(|entry| entry.tick < tick);
+        self.entries.range(start..)
+    }
+    /// Deaths still in the log, from oldest to newest.
+    pub fn deaths(&self) -> impl DoubleEndedIterator<Item = &UnitDied> {
+        self.entries.iter().filter_map(|entry| match &entry.event {
+            CombatEvent::UnitDied(death) => Some(death),
+            _ => None,
+        })
+    }
+    /// Statistics since the beginning of the match.
+    pub fn unit_stats(&self, entity: Entity) -> UnitCombatStats {
+        self.units.get(&entity).cloned().unwrap_or_default()
+    }
+    pub fn team_losses(&self, team: usize) -> u32 {
+        self.losses.get(&team).copied().unwrap_or(0)
+    }
+}
+
+/// Events of the same tick are logged by kind: attacks, then damage, then deaths.
+fn combat_log_system(
+    time: Res<SimulationTime>,
+    mut log: ResMut<CombatLog>,
+    mut attack_started: EventReader<AttackStarted>,
+    mut attack_interrupted: EventReader<AttackInterrupted>,
+    mut damage_dealt: EventReader<DamageDealt>,
+    mut unit_died: EventReader<UnitDied>,
+) {
+    let tick = time.tick();
+    for event in attack_started.iter() {
+        log.push(tick, CombatEvent::AttackStarted(event.clone()));
+    }
+    for event in attack_interrupted.iter() {
+        log.push(tick, CombatEvent::AttackInterrupted(event.clone()));
+    }
+    for event in damage_dealt.iter() {
+        log.push(tick, CombatEvent::DamageDealt(event.clone()));
+    }
+    for event in unit_died.iter() {
+        log.push(tick, CombatEvent::UnitDied(event.clone()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn damage(attacker: u32, victim: u32, amount: f32) -> CombatEvent {
+        CombatEvent::DamageDealt(DamageDealt {
+            attacker: Some(Entity::from_raw(attacker)),
+            victim: Entity::from_raw(victim),
+            amount,
+            mitigated: 0f32,
+            kind: DamageKind::Physical,
+            critical: false,
+        })
+    }
+
+    #[test]
+    fn statistics_and_since() {
+        let mut log = CombatLog::default();
+        log.push(1, damage(0, 1, 4f32));
+        log.push(2, damage(0, 1, 6f32));
+        log.push(
+            2,
+            CombatEvent::UnitDied(UnitDied {
+                entity: Entity::from_raw(1),
+                killer: Some(Entity::from_raw(0)),
+                team: Some(1),
+            }),
+        );
+        let attacker = log.unit_stats(Entity::from_raw(0));
+        assert_eq!(attacker.damage_dealt, 10f32);
+        assert_eq!(attacker.kills, 1);
+        assert_eq!(log.unit_stats(Entity::from_raw(1)).damage_taken, 10f32);
+        assert_eq!(log.team_losses(1), 1);
+        assert_eq!(log.since(2).count(), 2);
+        assert_eq!(log.deaths().count(), 1);
+    }
+}
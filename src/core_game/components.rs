@@ -1,94 +1,136 @@
-use bevy::prelude::{Component, Entity};
+use bevy::prelude::{Component, Entity, Vec2};
+use serde::{Deserialize, Serialize};
 
-#[derive(Component)]
+use super::damage::{default_critical_multiplier, Damage, DamageKind};
+
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct UnitSize(pub f32);
 
-#[derive(Component, PartialEq, Eq, Hash, Debug)]
-/// Useful for client to know which sprite to use
-pub enum RenderSprite {
-    Ogre,
-    Goblin,
-    Bandit,
+/// Identifies a unit across runs, unlike its `Entity`.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct UnitId(pub u32);
+
+/// Gives a new `UnitId` to each spawned unit.
+#[derive(Default)]
+pub struct UnitIds {
+    next: u32,
+}
+
+impl UnitIds {
+    pub fn starting_from(next: u32) -> Self {
+        UnitIds { next }
+    }
+    pub fn peek_next(&self) -> UnitId {
+        UnitId(self.next)
+    }
+    pub fn next(&mut self) -> UnitId {
+        let id = UnitId(self.next);
+        self.next += 1;
+        id
+    }
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+/// Useful for client to know which sprite to use, name of the unit's `UnitDefinition`.
+pub struct RenderSprite(pub String);
+
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct OffensiveStats {
     pub power: f32,
+    /// In [0, 1], chance for hits to be multiplied by `critical_multiplier`.
+    #[serde(default)]
+    pub critical_chance: f32,
+    #[serde(default = "default_critical_multiplier")]
+    pub critical_multiplier: f32,
 }
 
-#[derive(Component, Debug)]
-pub struct MeleeAbility {
-    pub range: f32,
-    // additional range to account for units movement
-    pub motion_buffer_range: f32,
-    pub time_to_strike: f32,
-    pub cooldown: f32,
+impl OffensiveStats {
+    /// A hit of `ratio` times this unit's power, which may be critical.
+    pub fn damage(&self, ratio: f32, kind: DamageKind) -> Damage {
+        Damage::new(self.power * ratio, kind)
+            .with_critical(self.critical_chance, self.critical_multiplier)
+    }
 }
 
-// TODO: use a mod to encapsulate state and structures, so the naming and and their scope is cleaner.
-#[derive(Component, Debug)]
-pub enum MeleeAbilityState {
-    Ready,
-    WillAttack(MeleeAbilityStateWillAttack),
-    AttackCooldown(MeleeAbilityStateCooldown),
-    MotionBufferExceeded,
+/// Flies straight, damaging the first enemy unit on its way, unless a wall is hit before.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Projectile {
+    pub team: usize,
+    pub damage: Damage,
+    pub velocity: Vec2,
+    /// Distance left before falling to the ground, missing its target.
+    pub remaining_distance: f32,
 }
-impl MeleeAbilityState {
-    pub fn interrupt(&mut self) {
-        match self {
-            MeleeAbilityState::Ready => {}
-            MeleeAbilityState::MotionBufferExceeded => {}
-            MeleeAbilityState::WillAttack(_) => {
-                *self = MeleeAbilityState::Ready;
-            }
-            MeleeAbilityState::AttackCooldown(_) => {}
-        }
+
+impl Projectile {
+    /// Replaces the shooter, forgotten if it can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) {
+        self.damage.map_entities(mapper);
     }
 }
 
-#[derive(Component, Debug)]
-pub struct MeleeAbilityStateWillAttack {
-    pub start_time: f32,
-    pub target_entity: Entity,
-}
-#[derive(Component, Debug)]
-pub struct MeleeAbilityStateCooldown {
-    pub start_time: f32,
-}
-#[derive(Component, Debug)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Team {
     pub id: usize,
 }
-#[derive(Component, Debug)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Health {
     pub max_hp: f32,
     pub current_hp: f32,
 }
-#[derive(Component, Default)]
+/// Hits taken during this tick, resolved by the `damage` module.
+#[derive(Component, Clone, Default, Serialize, Deserialize)]
 pub struct SufferDamage {
-    pub amount: Vec<f32>,
+    pub damages: Vec<Damage>,
+    /// Last unit which hurt this one, credited for its death.
+    #[serde(
+        default,
+        with = "crate::core_game::serialization::optional_entity_index"
+    )]
+    pub last_attacker: Option<Entity>,
 }
 
 impl SufferDamage {
-    pub fn new_damage(&mut self, amount: f32) {
-        self.amount.push(amount);
+    pub fn new_damage(&mut self, damage: Damage) {
+        self.damages.push(damage);
+    }
+    /// Replaces the attackers, forgotten if they can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) {
+        for damage in self.damages.iter_mut() {
+            damage.map_entities(mapper);
+        }
+        self.last_attacker = self.last_attacker.and_then(mapper);
     }
 }
 
-#[derive(Component, Clone, Debug)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct SeekEnemyRange {
     pub range: f32,
 }
 
-#[derive(Component, Clone, Debug)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub enum AIUnit {
     Passive,
     SeekEnemy,
     Attack(Attack),
 }
 
-#[derive(Component, Clone, Debug)]
+impl AIUnit {
+    /// Replaces the entities this AI refers to, fails if one of them can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) -> Result<(), ()> {
+        if let AIUnit::Attack(attack) = self {
+            attack.target = mapper(attack.target).ok_or(())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Attack {
+    #[serde(with = "crate::core_game::serialization::entity_index")]
     pub target: Entity,
     pub chase_when_target_too_far: bool,
+    /// Where an idle unit goes back to, through pathfinding, once done attacking.
+    #[serde(default)]
+    pub return_position: Option<Vec3>,
 }
@@ -0,0 +1,331 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    abilities::{Abilities, AbilityState, InterruptCause},
+    combat_log::{AttackInterrupted, DamageDealt},
+    components::{Health, SufferDamage, UnitId},
+    simulation::{SimulationLabel, SimulationSchedule, SimulationStage, SimulationTime},
+};
+
+/// Armor giving half damage to physical hits.
+const ARMOR_SCALE: f32 = 100f32;
+
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut App) {
+        app.stage(SimulationSchedule, |schedule: &mut Schedule| {
+            schedule
+                .add_system_to_stage(
+                    SimulationStage::Update,
+                    damage_over_time_system
+                        .label(SimulationLabel::DamageOverTime)
+                        .after(SimulationLabel::StatusEffects),
+                )
+                .add_stage_after(
+                    SimulationStage::Update,
+                    DamageResolutionStage,
+                    SystemStage::single_threaded().with_system(damage_resolution_system),
+                )
+        });
+    }
+}
+
+/// Damage dealt during `SimulationStage::Update` is applied to health right after.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct DamageResolutionStage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DamageKind {
+    Physical,
+    /// Arrows and such, only half of the armor applies.
+    Piercing,
+    /// Ignores armor.
+    Magic,
+}
+impl Default for DamageKind {
+    fn default() -> Self {
+        DamageKind::Physical
+    }
+}
+
+pub(crate) fn default_critical_multiplier() -> f32 {
+    2f32
+}
+
+/// A hit, before critical strike and mitigation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Damage {
+    pub amount: f32,
+    pub kind: DamageKind,
+    /// In [0, 1], chance for `amount` to be multiplied by `critical_multiplier`.
+    #[serde(default)]
+    pub critical_chance: f32,
+    #[serde(default = "default_critical_multiplier")]
+    pub critical_multiplier: f32,
+    /// Unit dealing the damage, if still alive.
+    #[serde(
+        default,
+        with = "crate::core_game::serialization::optional_entity_index"
+    )]
+    pub attacker: Option<Entity>,
+}
+
+impl Damage {
+    /// A hit which can't be critical.
+    pub fn new(amount: f32, kind: DamageKind) -> Self {
+        Damage {
+            amount,
+            kind,
+            critical_chance: 0f32,
+            critical_multiplier: default_critical_multiplier(),
+            attacker: None,
+        }
+    }
+    pub fn with_attacker(mut self, attacker: Entity) -> Self {
+        self.attacker = Some(attacker);
+        self
+    }
+    /// Replaces the attacker, forgotten if it can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) {
+        self.attacker = self.attacker.and_then(mapper);
+    }
+    pub fn with_critical(mut self, chance: f32, multiplier: f32) -> Self {
+        self.critical_chance = chance;
+        self.critical_multiplier = multiplier;
+        self
+    }
+}
+
+/// Fraction of the damage of each kind which is ignored, in [0, 1].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Resistances {
+    #[serde(default)]
+    pub physical: f32,
+    #[serde(default)]
+    pub piercing: f32,
+    #[serde(default)]
+    pub magic: f32,
+}
+
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Defense {
+    pub armor: f32,
+    #[serde(default)]
+    pub resistances: Resistances,
+}
+
+impl Defense {
+    /// Damage left of `amount` once armor, then resistances are applied.
+    pub fn mitigate(&self, amount: f32, kind: DamageKind) -> f32 {
+        let (armor, resistance) = match kind {
+            DamageKind::Physical => (self.armor, self.resistances.physical),
+            DamageKind::Piercing => (self.armor / 2f32, self.resistances.piercing),
+            DamageKind::Magic => (0f32, self.resistances.magic),
+        };
+        // Negative armor increases damage, down to -ARMOR_SCALE / 2 doubling it.
+        let armor_factor = ARMOR_SCALE / (ARMOR_SCALE + armor.max(-ARMOR_SCALE / 2f32));
+        amount * armor_factor * (1f32 - resistance.clamp(0f32, 1f32))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DamageOverTimeEffect {
+    pub damage_per_second: f32,
+    pub kind: DamageKind,
+    /// Seconds left.
+    pub remaining: f32,
+    #[serde(
+        default,
+        with = "crate::core_game::serialization::optional_entity_index"
+    )]
+    pub attacker: Option<Entity>,
+}
+
+/// Ongoing damage, e.g. poison or burning, turned into `SufferDamage` each tick.
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DamageOverTime {
+    pub effects: Vec<DamageOverTimeEffect>,
+}
+
+impl DamageOverTime {
+    /// Deals `total` damage over `duration` seconds, stacking with current effects.
+    pub fn add(&mut self, total: f32, kind: DamageKind, duration: f32, attacker: Option<Entity>) {
+        self.effects.push(DamageOverTimeEffect {
+            damage_per_second: total / duration.max(f32::EPSILON),
+            kind,
+            remaining: duration,
+            attacker,
+        });
+    }
+    /// Replaces the attackers, forgotten if they can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) {
+        for effect in self.effects.iter_mut() {
+            effect.attacker = effect.attacker.and_then(mapper);
+        }
+    }
+}
+
+/// Deterministic roll in [0, 1), so critical strikes are the same in replays and loaded games.
+///
+/// `hit` counts the previous hits of `attacker` on `victim` during the tick, rather than
+/// all of them, so a roll doesn't depend on the order other units dealt their hits in.
+fn roll(tick: u64, victim: u32, attacker: Option<u32>, hit: u32) -> f32 {
+    let attacker = attacker.map_or(0u64, |id| id as u64 + 1);
+    // SplitMix64 finalizer.
+    let mut z = tick
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        .wrapping_add(((victim as u64) << 32) | hit as u64)
+        ^ attacker.wrapping_mul(0xD6E8_FEB8_6659_FD93);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Health lost to `damage`, what was mitigated, and whether it was critical.
+fn resolve(damage: &Damage, defense: Option<&Defense>, roll: f32) -> (f32, f32, bool) {
+    let critical = roll < damage.critical_chance;
+    let amount = if critical {
+        damage.amount * damage.critical_multiplier
+    } else {
+        damage.amount
+    }
+    .max(0f32);
+    let dealt = defense
+        .map_or(amount, |defense| defense.mitigate(amount, damage.kind))
+        .max(0f32);
+    (dealt, amount - dealt, critical)
+}
+
+pub fn damage_over_time_system(
+    time: Res<SimulationTime>,
+    mut q: Query<(&mut DamageOverTime, &mut SufferDamage)>,
+) {
+    let delta = time.delta_seconds();
+    for (mut damage_over_time, mut suffer_damage) in q.iter_mut() {
+        if damage_over_time.effects.is_empty() {
+            continue;
+        }
+        for effect in damage_over_time.effects.iter_mut() {
+            let duration = delta.min(effect.remaining);
+            suffer_damage.new_damage(Damage {
+                attacker: effect.attacker,
+                ..Damage::new(effect.damage_per_second * duration, effect.kind)
+            });
+            effect.remaining -= delta;
+        }
+        damage_over_time
+            .effects
+            .retain(|effect| effect.remaining > 0f32);
+    }
+}
+
+/// Turns `SufferDamage` entries into health changes.
+pub fn damage_resolution_system(
+    time: Res<SimulationTime>,
+    mut damage_dealt: EventWriter<DamageDealt>,
+    mut attack_interrupted: EventWriter<AttackInterrupted>,
+    mut q: Query<(
+        Entity,
+        Option<&UnitId>,
+        &mut Health,
+        &mut SufferDamage,
+        Option<&Defense>,
+        Option<(&Abilities, &mut AbilityState)>,
+    )>,
+    q_unit_ids: Query<&UnitId>,
+) {
+    let attacker_id = |damage: &Damage| {
+        damage
+            .attacker
+            .and_then(|attacker| q_unit_ids.get(attacker).ok())
+            .map(|id| id.0)
+    };
+    for (entity, unit_id, mut health, mut suffer_damage, defense, abilities) in q.iter_mut() {
+        if suffer_damage.damages.is_empty() {
+            continue;
+        }
+        let unit = unit_id.map_or(entity.id(), |id| id.0);
+        let mut total = 0f32;
+        let mut damages = std::mem::take(&mut suffer_damage.damages);
+        // Hits are resolved in the same order, whichever order they were dealt in.
+        damages.sort_by_cached_key(|damage| (attacker_id(damage), damage.kind as u8));
+        let mut previous_attacker = None;
+        let mut hit = 0;
+        for damage in damages {
+            let attacker = attacker_id(&damage);
+            hit = if previous_attacker == Some(attacker) {
+                hit + 1
+            } else {
+                0
+            };
+            previous_attacker = Some(attacker);
+            let (amount, mitigated, critical) =
+                resolve(&damage, defense, roll(time.tick(), unit, attacker, hit));
+            health.current_hp -= amount;
+            total += amount;
+            if amount > 0f32 && attacker.is_some() {
+                suffer_damage.last_attacker = damage.attacker;
+            }
+            damage_dealt.send(DamageDealt {
+                attacker: damage.attacker,
+                victim: entity,
+                amount,
+                mitigated,
+                kind: damage.kind,
+                critical,
+            });
+        }
+        if total > 0f32 {
+            if let Some((abilities, mut ability_state)) = abilities {
+                if let Some(ability) = ability_state.interrupt(abilities, InterruptCause::Damage) {
+                    attack_interrupted.send(AttackInterrupted {
+                        attacker: entity,
+                        ability,
+                        cause: InterruptCause::Damage,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn armor_and_resistances() {
+        let defense = Defense {
+            armor: ARMOR_SCALE,
+            resistances: Resistances {
+                physical: 0f32,
+                piercing: 0f32,
+                magic: 0.25,
+            },
+        };
+        assert_eq!(defense.mitigate(10f32, DamageKind::Physical), 5f32);
+        assert!(defense.mitigate(10f32, DamageKind::Piercing) > 5f32);
+        assert_eq!(defense.mitigate(10f32, DamageKind::Magic), 7.5);
+    }
+    #[test]
+    fn critical_rolls_are_deterministic() {
+        let damage = Damage::new(10f32, DamageKind::Physical).with_critical(0.25, 3f32);
+        let criticals = (0..1000)
+            .filter(|tick| resolve(&damage, None, roll(*tick, 7, Some(3), 0)).2)
+            .count();
+        assert!((200..300).contains(&criticals));
+        assert_eq!(roll(42, 7, Some(3), 1), roll(42, 7, Some(3), 1));
+        assert_ne!(roll(42, 7, Some(3), 0), roll(42, 7, Some(4), 0));
+        assert_ne!(roll(42, 7, Some(3), 0), roll(42, 7, None, 0));
+        assert_eq!(resolve(&damage, None, 0f32), (30f32, 0f32, true));
+    }
+    #[test]
+    fn damage_over_time_total() {
+        let mut damage_over_time = DamageOverTime::default();
+        damage_over_time.add(12f32, DamageKind::Magic, 3f32, None);
+        assert_eq!(damage_over_time.effects[0].damage_per_second, 4f32);
+    }
+}
@@ -0,0 +1,243 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Space between two units of a formation, on top of their sizes.
+const FORMATION_GAP: f32 = 10f32;
+/// Units side by side in a column.
+const COLUMN_WIDTH: usize = 2;
+
+/// How a group is laid out at the destination of a move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Formation {
+    /// Keeps units relative positions when moving outside of the group, else gathers them.
+    PreserveShape,
+    Line,
+    Column,
+    Box,
+    Wedge,
+}
+impl Default for Formation {
+    fn default() -> Self {
+        Formation::PreserveShape
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FormationMember {
+    pub position: Vec3,
+    /// Radius of the unit, from its `UnitSize`.
+    pub size: f32,
+}
+
+impl Formation {
+    /// Number of units of each row, from front to back.
+    fn row_widths(&self, count: usize) -> Vec<usize> {
+        let mut widths = vec![];
+        let mut remaining = count;
+        let mut row = 0;
+        while remaining > 0 {
+            let width = match self {
+                Formation::PreserveShape | Formation::Line => remaining,
+                Formation::Column => COLUMN_WIDTH,
+                Formation::Box => (count as f32).sqrt().ceil() as usize,
+                Formation::Wedge => row + 1,
+            }
+            .min(remaining);
+            widths.push(width);
+            remaining -= width;
+            row += 1;
+        }
+        widths
+    }
+
+    /// Target of each member going to `destination`, in the same order as `members`.
+    ///
+    /// Bigger units take the front rows, the formation faces the move direction.
+    pub fn targets(&self, members: &[FormationMember], destination: Vec3) -> Vec<Vec3> {
+        if members.len() <= 1 {
+            return vec![destination; members.len()];
+        }
+        if *self == Formation::PreserveShape {
+            return preserve_shape_targets(members, destination);
+        }
+        let center = members
+            .iter()
+            .fold(Vec3::ZERO, |sum, member| sum + member.position)
+            / members.len() as f32;
+        let forward = (destination - center)
+            .truncate()
+            .try_normalize()
+            .unwrap_or(Vec2::Y);
+        let right = Vec2::new(forward.y, -forward.x);
+
+        let mut order: Vec<usize> = (0..members.len()).collect();
+        order.sort_by(|a, b| {
+            members[*b]
+                .size
+                .partial_cmp(&members[*a].size)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        // Slots relative to the front of the formation, as (right, backward) distances.
+        let mut slots = vec![Vec2::ZERO; members.len()];
+        let mut row_offset = 0f32;
+        let mut assigned = 0;
+        for width in self.row_widths(members.len()) {
+            let mut row: Vec<usize> = order[assigned..assigned + width].to_vec();
+            assigned += width;
+            // Units on the left go to the left slots, so paths don't cross.
+            let lateral = |index: &usize| members[*index].position.truncate().dot(right);
+            row.sort_by(|a, b| {
+                lateral(a)
+                    .partial_cmp(&lateral(b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let row_size = row.iter().map(|i| members[*i].size).fold(0f32, f32::max);
+            let row_length: f32 = row
+                .iter()
+                .map(|i| members[*i].size * 2f32 + FORMATION_GAP)
+                .sum::<f32>()
+                - FORMATION_GAP;
+            row_offset += row_size;
+            let mut x = -row_length / 2f32;
+            for index in row {
+                let size = members[index].size;
+                x += size;
+                slots[index] = Vec2::new(x, row_offset);
+                x += size + FORMATION_GAP;
+            }
+            row_offset += row_size + FORMATION_GAP;
+        }
+        // Centers the formation on `destination`.
+        let depth = row_offset - FORMATION_GAP;
+        slots
+            .into_iter()
+            .map(|slot| {
+                let offset = right * slot.x - forward * (slot.y - depth / 2f32);
+                destination + offset.extend(0f32)
+            })
+            .collect()
+    }
+}
+
+/// "Magic box": relative positions are kept if `destination` is outside of the group.
+fn preserve_shape_targets(members: &[FormationMember], destination: Vec3) -> Vec<Vec3> {
+    let mut min = Vec2::new(f32::MAX, f32::MAX);
+    let mut max = Vec2::new(f32::MIN, f32::MIN);
+    for member in members.iter() {
+        min = min.min(member.position.truncate());
+        max = max.max(member.position.truncate());
+    }
+    let target = destination.truncate();
+    if target.cmpge(min).all() && target.cmple(max).all() {
+        return vec![destination; members.len()];
+    }
+    let center = ((min + max) / 2.0).extend(0f32);
+    members
+        .iter()
+        .map(|member| destination + member.position - center)
+        .collect()
+}
+
+/// Speed a group moves at to stay together: the one of its slowest member.
+pub fn group_speed(speeds: impl Iterator<Item = f32>) -> Option<f32> {
+    speeds.fold(None, |slowest: Option<f32>, speed| {
+        Some(slowest.map_or(speed, |slowest| slowest.min(speed)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(x: f32, y: f32, size: f32) -> FormationMember {
+        FormationMember {
+            position: Vec3::new(x, y, 0f32),
+            size,
+        }
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 0.001, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn line_slots_keep_units_sides() {
+        let members = [
+            member(5f32, 0f32, 10f32),
+            member(-5f32, 0f32, 10f32),
+            member(0f32, 0f32, 10f32),
+        ];
+        let targets = Formation::Line.targets(&members, Vec3::new(0f32, 100f32, 0f32));
+        assert_near(targets[0], Vec3::new(30f32, 100f32, 0f32));
+        assert_near(targets[1], Vec3::new(-30f32, 100f32, 0f32));
+        assert_near(targets[2], Vec3::new(0f32, 100f32, 0f32));
+    }
+
+    #[test]
+    fn formation_faces_the_move_direction() {
+        let members = [
+            member(0f32, -5f32, 10f32),
+            member(0f32, 0f32, 10f32),
+            member(0f32, 5f32, 10f32),
+        ];
+        let destination = Vec3::new(100f32, 0f32, 0f32);
+        let targets = Formation::Line.targets(&members, destination);
+        for target in targets.iter() {
+            // The line is across the move direction.
+            assert!((target.x - destination.x).abs() < 0.001);
+        }
+        // Units on the left of the move stay on the left.
+        assert!(targets[2].y > targets[1].y && targets[1].y > targets[0].y);
+    }
+
+    #[test]
+    fn bigger_units_take_the_front_rows() {
+        let members = [
+            member(0f32, 0f32, 5f32),
+            member(10f32, 0f32, 5f32),
+            member(0f32, 10f32, 20f32),
+            member(10f32, 10f32, 5f32),
+        ];
+        let destination = Vec3::new(5f32, 200f32, 0f32);
+        let targets = Formation::Column.targets(&members, destination);
+        let front = targets.iter().map(|t| t.y).fold(f32::MIN, f32::max);
+        assert_eq!(targets[2].y, front);
+        // Two rows of two.
+        let mut rows: Vec<f32> = targets.iter().map(|t| t.y).collect();
+        rows.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(rows[0], rows[1]);
+        assert_eq!(rows[2], rows[3]);
+        assert!(rows[1] < rows[2]);
+    }
+
+    #[test]
+    fn wedge_rows_grow() {
+        assert_eq!(Formation::Wedge.row_widths(6), vec![1, 2, 3]);
+        assert_eq!(Formation::Wedge.row_widths(4), vec![1, 2, 1]);
+        assert_eq!(Formation::Box.row_widths(5), vec![3, 2]);
+        assert_eq!(Formation::Column.row_widths(3), vec![2, 1]);
+    }
+
+    #[test]
+    fn preserve_shape_keeps_offsets_outside_the_group() {
+        let members = [member(0f32, 0f32, 10f32), member(20f32, 0f32, 10f32)];
+        let targets = Formation::PreserveShape.targets(&members, Vec3::new(110f32, 0f32, 0f32));
+        assert_near(targets[0], Vec3::new(100f32, 0f32, 0f32));
+        assert_near(targets[1], Vec3::new(120f32, 0f32, 0f32));
+        let inside = Vec3::new(10f32, 0f32, 0f32);
+        assert_eq!(
+            Formation::PreserveShape.targets(&members, inside),
+            vec![inside; 2]
+        );
+    }
+
+    #[test]
+    fn group_speed_is_the_slowest() {
+        assert_eq!(
+            group_speed([120f32, 80f32, 100f32].into_iter()),
+            Some(80f32)
+        );
+        assert_eq!(group_speed(std::iter::empty()), None);
+    }
+}
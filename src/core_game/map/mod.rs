@@ -9,6 +9,7 @@ use mapgen::{
     XStart, YStart,
 };
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
 #[derive(Component)]
 pub struct Wall;
@@ -23,34 +24,76 @@ pub struct Map {
     pub map: mapgen::Map,
 }
 
-const TILE_SIZE: f32 = 120f32;
+/// Parameters used by `create_map`, the same settings always generate the same map.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapSettings {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+}
+
+impl Default for MapSettings {
+    fn default() -> Self {
+        MapSettings {
+            width: 20,
+            height: 20,
+            seed: 100,
+        }
+    }
+}
+
+pub const TILE_SIZE: f32 = 120f32;
 const HALF_TILE: f32 = TILE_SIZE / 2f32;
-const MAP_SIZE: (usize, usize) = (20, 20);
-const offset_x: f32 = MAP_SIZE.0 as f32 * HALF_TILE;
-const offset_y: f32 = MAP_SIZE.0 as f32 * HALF_TILE;
 
 impl Map {
-    pub fn real_x_at(x: usize) -> f32 {
-        let position_x = x as f32 * TILE_SIZE - offset_x;
+    fn offset_x(&self) -> f32 {
+        self.map.width as f32 * HALF_TILE
+    }
+    fn offset_y(&self) -> f32 {
+        self.map.height as f32 * HALF_TILE
+    }
+
+    pub fn real_x_at(&self, x: usize) -> f32 {
+        let position_x = x as f32 * TILE_SIZE - self.offset_x();
         position_x
     }
-    pub fn real_y_at(y: usize) -> f32 {
-        let position_y = y as f32 * TILE_SIZE - offset_y;
+    pub fn real_y_at(&self, y: usize) -> f32 {
+        let position_y = y as f32 * TILE_SIZE - self.offset_y();
         position_y
     }
 
-    pub fn real_position_at(x: usize, y: usize) -> Vec2 {
-        Vec2::new(Self::real_x_at(x), Self::real_y_at(y))
+    pub fn real_position_at(&self, x: usize, y: usize) -> Vec2 {
+        Vec2::new(self.real_x_at(x), self.real_y_at(y))
     }
 
-    pub fn map_x_at(x: f32) -> usize {
-        let position_x = (x + offset_x) / (TILE_SIZE as f32);
+    pub fn map_x_at(&self, x: f32) -> usize {
+        let position_x = (x + self.offset_x()) / (TILE_SIZE as f32);
         position_x.round() as usize
     }
-    pub fn map_y_at(y: f32) -> usize {
-        let position_y = (y + offset_y) / (TILE_SIZE as f32);
+    pub fn map_y_at(&self, y: f32) -> usize {
+        let position_y = (y + self.offset_y()) / (TILE_SIZE as f32);
         position_y.round() as usize
     }
+
+    /// Bottom left and top right corners of the map.
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        let min = self.real_position_at(0, 0) - Vec2::splat(HALF_TILE);
+        let size = Vec2::new(self.map.width as f32, self.map.height as f32) * TILE_SIZE;
+        (min, min + size)
+    }
+    /// Position in tile units, as used by pathfinding.
+    pub fn tile_position_at(&self, position: Vec2) -> (f32, f32) {
+        (
+            (position.x + self.offset_x()) / TILE_SIZE,
+            (position.y + self.offset_y()) / TILE_SIZE,
+        )
+    }
+    pub fn real_position_from_tile(&self, tile: (f32, f32)) -> Vec2 {
+        Vec2::new(
+            tile.0 * TILE_SIZE - self.offset_x(),
+            tile.1 * TILE_SIZE - self.offset_y(),
+        )
+    }
 }
 
 fn spawn_wall_at(commands: &mut Commands, position: Vec3, size: f32) {
@@ -70,16 +113,16 @@ fn spawn_wall_at(commands: &mut Commands, position: Vec3, size: f32) {
         .insert(collider);
 }
 
-pub fn create_map(mut commands: Commands) {
-    let mut rng = StdRng::seed_from_u64(100);
-    let mut map = MapBuilder::new(MAP_SIZE.0, MAP_SIZE.1)
+pub fn create_map(mut commands: Commands, settings: Res<MapSettings>) {
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let mut map = MapBuilder::new(settings.width, settings.height)
         .with(NoiseGenerator::uniform())
         //.with(filter::MazeBuilder::new())
         .with(filter::CellularAutomata::new())
         .with(AreaStartingPosition::new(XStart::LEFT, YStart::TOP))
         .with(CullUnreachable::new())
         .with(DistantExit::new())
-        .build();
+        .build_with_rng(&mut rng);
     if let Some(starting_point) = map.starting_point {
         let new_room = Rect::new(starting_point.x, starting_point.y, 3, 3);
         map.add_room(new_room);
@@ -94,21 +137,26 @@ pub fn create_map(mut commands: Commands) {
     } else {
         println!("no exit..");
     }
+    spawn_map(&mut commands, map);
+}
 
-    for y in (0..MAP_SIZE.1).rev() {
-        let position_y = y as f32 * TILE_SIZE - offset_y;
-        for x in 0..MAP_SIZE.0 {
-            let position_x = x as f32 * TILE_SIZE - offset_x;
-            let tile_type = map.at(x, y);
+/// Spawns walls for the given map, then inserts it as a resource.
+pub fn spawn_map(commands: &mut Commands, map: mapgen::Map) {
+    let map = Map { map };
+    for y in (0..map.map.height).rev() {
+        let position_y = map.real_y_at(y);
+        for x in 0..map.map.width {
+            let position_x = map.real_x_at(x);
+            let tile_type = map.map.at(x, y);
 
             if tile_type.is_walkable() {
-                if let Some(start) = map.starting_point {
+                if let Some(start) = map.map.starting_point {
                     if start.x == x && start.y == y {
                         print!("S");
                         continue;
                     }
                 }
-                if let Some(exit) = map.exit_point {
+                if let Some(exit) = map.map.exit_point {
                     if exit.x == x && exit.y == y {
                         print!("E");
                         continue;
@@ -117,14 +165,10 @@ pub fn create_map(mut commands: Commands) {
                 print!(" ");
                 continue;
             }
-            spawn_wall_at(
-                &mut commands,
-                Vec3::new(position_x, position_y, 0.0),
-                HALF_TILE,
-            );
+            spawn_wall_at(commands, Vec3::new(position_x, position_y, 0.0), HALF_TILE);
             print!("X");
         }
         println!();
     }
-    commands.insert_resource(Map { map });
+    commands.insert_resource(map);
 }
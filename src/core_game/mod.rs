@@ -1,36 +1,198 @@
-use bevy::prelude::*;
-use bevy_inspector_egui::RegisterInspectable;
+use bevy::{app::AppExit, hierarchy::HierarchyPlugin, prelude::*, transform::TransformPlugin};
 use pathfinding::PathfindingPlugin;
 
+pub mod abilities;
+pub mod combat_log;
 pub mod components;
+pub mod damage;
+pub mod formation;
 pub mod map;
 pub mod orders;
 pub mod pathfinding;
 pub mod physics;
+pub mod replay;
+pub mod save;
+pub mod scenario;
+mod serialization;
+pub mod simulation;
+pub mod spatial;
+pub mod status_effects;
 mod systems;
+pub mod units;
+pub mod vision;
 
-use self::{map::create_map, orders::orders_sys::*};
+use self::{
+    abilities::AbilitiesPlugin,
+    combat_log::CombatLogPlugin,
+    components::{Team, UnitIds},
+    damage::DamagePlugin,
+    map::create_map,
+    orders::{orders_comp::OrderRequests, orders_sys::*},
+    replay::ReplayPlugin,
+    save::{SaveGame, SavePlugin},
+    scenario::{ScenarioPlugin, Teams},
+    simulation::{
+        SimulationLabel, SimulationPlugin, SimulationSchedule, SimulationStage, SimulationTime,
+    },
+    spatial::SpatialPlugin,
+    status_effects::StatusEffectsPlugin,
+    units::UnitsPlugin,
+    vision::VisionPlugin,
+};
 use systems::*;
 
 pub struct CorePlugin;
 
 impl Plugin for CorePlugin {
     fn build(&self, app: &mut App) {
-        use crate::core_game::orders::orders_comp::*;
-        app.register_inspectable::<Speed>();
-        app.register_inspectable::<RotateBeforeMove>();
-        app.register_inspectable::<Mover>();
-
-        app.add_plugin(physics::PhysicsPlugin)
-        .add_plugin(PathfindingPlugin)
-        .add_startup_system_to_stage(StartupStage::PreStartup, create_map)
-        .add_startup_system_to_stage(StartupStage::Startup, create_units)
-        .add_system(order_system)
-        .add_system_to_stage(CoreStage::PostUpdate, ai_system)
-        .add_system(attack_melee_system)
-        .add_system(health_system)
-
+        app.init_resource::<UnitIds>()
+            .init_resource::<OrderRequests>()
+            .add_plugin(SimulationPlugin)
+            .add_plugin(UnitsPlugin)
+            // Replays and saved games override the scenario, so they come first and last.
+            .add_plugin(ReplayPlugin)
+            .add_plugin(ScenarioPlugin)
+            .add_plugin(SavePlugin)
+            .add_plugin(physics::PhysicsPlugin)
+            .add_plugin(PathfindingPlugin)
+            .add_plugin(SpatialPlugin)
+            .add_plugin(VisionPlugin)
+            .add_plugin(AbilitiesPlugin)
+            .add_plugin(StatusEffectsPlugin)
+            .add_plugin(DamagePlugin)
+            .add_plugin(CombatLogPlugin)
+            .stage(SimulationSchedule, |schedule: &mut Schedule| {
+                schedule
+                    .add_system_to_stage(
+                        SimulationStage::PreUpdate,
+                        apply_order_requests.label(SimulationLabel::OrderRequests),
+                    )
+                    .add_system_to_stage(
+                        SimulationStage::Update,
+                        order_system.label(SimulationLabel::Orders),
+                    )
+                    .add_system_to_stage(
+                        SimulationStage::Update,
+                        projectile_system
+                            .label(SimulationLabel::Projectiles)
+                            .after(SimulationLabel::DamageOverTime),
+                    )
+                    .add_system_to_stage(
+                        SimulationStage::PostUpdate,
+                        health_system.label(SimulationLabel::Health),
+                    )
+                    .add_system_to_stage(
+                        SimulationStage::PostUpdate,
+                        ai_system
+                            .label(SimulationLabel::Ai)
+                            .after(SimulationLabel::Health),
+                    )
+            });
         //.add_system(order_system_debug_change)
-            ;
+
+        // A loaded game brings its own map and units.
+        if !app.world.contains_resource::<SaveGame>() {
+            app.add_startup_system_to_stage(StartupStage::PreStartup, create_map)
+                .add_startup_system_to_stage(StartupStage::Startup, create_units);
+        }
+    }
+}
+
+/// Runs the core simulation on top of `MinimalPlugins`, without window nor renderer.
+///
+/// Useful to run battles on CI servers or batch machines.
+pub struct HeadlessCorePlugin;
+
+impl Plugin for HeadlessCorePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SimulationTime::uncapped())
+            // MinimalPlugins doesn't propagate transforms, which rapier and despawn_recursive rely on.
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(CorePlugin)
+            .add_system_to_stage(CoreStage::PostUpdate, battle_end_system);
+    }
+}
+
+/// Exits the app when at most one non neutral team still has units alive.
+fn battle_end_system(teams: Res<Teams>, q_teams: Query<&Team>, mut app_exit: EventWriter<AppExit>) {
+    let mut alive_teams: Vec<usize> = q_teams
+        .iter()
+        .map(|team| team.id)
+        .filter(|id| !teams.is_neutral(*id))
+        .collect();
+    alive_teams.sort_unstable();
+    alive_teams.dedup();
+    if alive_teams.len() > 1 {
+        return;
+    }
+    match alive_teams.first() {
+        Some(winner) => info!("Battle over, winner: team {}", winner),
+        None => info!("Battle over, no unit left."),
+    }
+    app_exit.send(AppExit);
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use components::{Health, UnitId};
+    use orders::orders_comp::Orders;
+
+    pub(crate) const TICKS: u32 = 600;
+
+    /// Headless app with its units spawned, for `ReplayMode` or `LoadGame` inserted beforehand.
+    pub(crate) fn headless_app(configure: impl FnOnce(&mut App)) -> App {
+        let mut app = App::new();
+        configure(&mut app);
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(HeadlessCorePlugin);
+        // Runs the startup systems, spawning the units.
+        app.update();
+        app
+    }
+
+    /// Orders the player's units to the center of the map.
+    pub(crate) fn order_player_units(app: &mut App) {
+        let player_team = app.world.resource::<Teams>().player_team;
+        let units: Vec<Entity> = app
+            .world
+            .query::<(Entity, &Team)>()
+            .iter(&app.world)
+            .filter(|(_, team)| team.id == player_team)
+            .map(|(entity, _)| entity)
+            .collect();
+        let mut order_requests = app.world.resource_mut::<OrderRequests>();
+        for entity in units {
+            order_requests.issue(entity, vec![Orders::order_move(Vec3::ZERO)], false);
+        }
+    }
+
+    /// Position and health of each unit.
+    pub(crate) fn unit_states(app: &mut App) -> Vec<(UnitId, Vec3, f32)> {
+        let mut states: Vec<(UnitId, Vec3, f32)> = app
+            .world
+            .query::<(&UnitId, &Transform, &Health)>()
+            .iter(&app.world)
+            .map(|(id, transform, health)| (*id, transform.translation, health.current_hp))
+            .collect();
+        states.sort_by_key(|(id, _, _)| id.0);
+        states
+    }
+
+    fn run_battle() -> Vec<(UnitId, Vec3, f32)> {
+        let mut app = headless_app(|_| {});
+        order_player_units(&mut app);
+        for _ in 0..TICKS {
+            app.update();
+        }
+        unit_states(&mut app)
+    }
+
+    #[test]
+    fn same_orders_give_same_battle() {
+        let first = run_battle();
+        assert!(!first.is_empty());
+        assert_eq!(first, run_battle());
     }
 }
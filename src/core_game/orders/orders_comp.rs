@@ -1,19 +1,29 @@
-use crate::core_game::components::*;
+use crate::core_game::{abilities::AbilityTarget, components::*};
 use bevy::prelude::*;
 use bevy::{math::Vec3, prelude::Component};
 use bevy_inspector_egui::Inspectable;
+use serde::{Deserialize, Serialize};
 
 // Hide mover to avoid doing bad things, because only
-#[derive(Component, Clone, Debug, Inspectable)]
+#[derive(Component, Clone, Debug, Inspectable, Serialize, Deserialize)]
 pub struct Mover {
     pub(super) target_position: Vec3,
     pub is_target_reached: bool,
+    /// Destination shared by a group, steered towards through its flow field.
+    #[serde(default)]
+    pub(super) flow_field_destination: Option<Vec3>,
+    /// Goes around walls through a [`MoverPath`], recomputed as the target moves.
+    #[serde(default)]
+    pub(super) use_pathfinding: bool,
+    /// Maximum speed, so a group moves at the pace of its slowest unit.
+    #[serde(default)]
+    pub(super) speed_limit: Option<f32>,
 }
-#[derive(Component, Inspectable)]
+#[derive(Component, Clone, Inspectable, Serialize, Deserialize)]
 pub struct RotateBeforeMove {
     pub rotation_speed: f32,
 }
-#[derive(Component, Clone, Debug, Inspectable)]
+#[derive(Component, Clone, Debug, Inspectable, Serialize, Deserialize)]
 pub struct Speed {
     pub speed: f32,
 }
@@ -23,32 +33,141 @@ impl Mover {
         Mover {
             target_position: position,
             is_target_reached: true,
+            flow_field_destination: None,
+            use_pathfinding: false,
+            speed_limit: None,
         }
     }
     pub fn new_to_target(position: Vec3) -> Self {
         Mover {
             target_position: position,
             is_target_reached: false,
+            flow_field_destination: None,
+            use_pathfinding: false,
+            speed_limit: None,
         }
     }
+    /// Moves through the flow field of `destination` until `position` is in sight.
+    pub fn with_flow_field(mut self, destination: Vec3) -> Self {
+        self.flow_field_destination = Some(destination);
+        self
+    }
+    pub fn with_pathfinding(mut self) -> Self {
+        self.use_pathfinding = true;
+        self
+    }
+    pub fn with_speed_limit(mut self, speed_limit: f32) -> Self {
+        self.speed_limit = Some(speed_limit);
+        self
+    }
     pub fn get_target_position(&self) -> &Vec3 {
         &self.target_position
     }
+    pub fn get_flow_field_destination(&self) -> Option<&Vec3> {
+        self.flow_field_destination.as_ref()
+    }
+    pub fn uses_pathfinding(&self) -> bool {
+        self.use_pathfinding
+    }
+    /// Speed to move at, for a unit able to move at `speed`.
+    pub fn limit_speed(&self, speed: f32) -> f32 {
+        self.speed_limit.map_or(speed, |limit| speed.min(limit))
+    }
+}
+
+/// Path of a mover using pathfinding, kept when its `Mover` is replaced by one to the same tile.
+#[derive(Component, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct MoverPath {
+    /// Tile of the target the waypoints lead to, `None` to compute them again.
+    pub(crate) target_tile: Option<(i32, i32)>,
+    pub(crate) waypoints: Vec<Vec3>,
+    /// Where the mover was when it last made progress, to detect it's stuck.
+    pub(crate) progress_position: Vec3,
+    pub(crate) stuck_ticks: u32,
+}
+impl MoverPath {
+    pub fn get_waypoints(&self) -> &Vec<Vec3> {
+        &self.waypoints
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Order {
     Ai(AIUnit),             // effect is instant
     Move(Awaitable<Mover>), // wait for reaching target.
+    Cast(Awaitable<Cast>),  // wait for the cast to complete.
+    /// Interrupts the current cast and stands still, seeking enemies.
+    Stop,
+    /// Attacks enemies in range, but never moves. Never completes.
+    HoldPosition,
+    /// Moves to a point, engaging enemies met on the way before moving on.
+    AttackMove(AttackMove),
+    /// Moves between points in a loop, engaging enemies met on the way. Never completes.
+    Patrol(Patrol),
+    /// Stays close to a friendly unit, engaging enemies met on the way, until it dies.
+    Follow(Follow),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttackMove {
+    pub destination: Vec3,
+    /// Moves there with the rest of its group, else on its own through pathfinding.
+    #[serde(default)]
+    pub mover: Option<Mover>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Patrol {
+    pub points: Vec<Vec3>,
+    /// Index in `points` of the one being moved to.
+    pub next: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Follow {
+    #[serde(with = "crate::core_game::serialization::entity_index")]
+    pub target: Entity,
+}
+
+/// Casts one of the unit's abilities, getting in range first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cast {
+    /// Index in the unit's `Abilities`.
+    pub ability: usize,
+    pub target: AbilityTarget,
+}
+
+impl Order {
+    /// Replaces the entities this order refers to, fails if one of them can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) -> Result<(), ()> {
+        match self {
+            Order::Ai(ai) => ai.map_entities(mapper),
+            Order::Move(_)
+            | Order::Stop
+            | Order::HoldPosition
+            | Order::AttackMove(_)
+            | Order::Patrol(_) => Ok(()),
+            Order::Follow(follow) => {
+                follow.target = mapper(follow.target).ok_or(())?;
+                Ok(())
+            }
+            Order::Cast(Awaitable::Queued(cast) | Awaitable::Awaiting(cast)) => {
+                if let AbilityTarget::Unit(target) = &mut cast.target {
+                    *target = mapper(*target).ok_or(())?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Awaitable<T> {
     Queued(T),
     Awaiting(T),
 }
 
-#[derive(Component, Default, Debug)]
+#[derive(Component, Clone, Default, Debug, Serialize, Deserialize)]
 pub struct Orders {
     pub(super) orders: Vec<Order>,
     pub override_order: Option<Order>,
@@ -64,7 +183,88 @@ impl Orders {
     pub fn get_orders(&self) -> &Vec<Order> {
         &self.orders
     }
+    /// The current order is to hold position, the unit shouldn't move to attack.
+    pub fn is_holding_position(&self) -> bool {
+        matches!(self.orders.first(), Some(Order::HoldPosition))
+    }
+    /// Where the unit ends up once its orders are done, if the last one goes somewhere.
+    ///
+    /// A patrol never ends, its last point is used to queue more patrol points.
+    pub fn last_waypoint(&self) -> Option<Vec3> {
+        let last = self
+            .orders
+            .iter()
+            .rev()
+            .find(|order| !matches!(order, Order::Ai(_)))?;
+        match last {
+            Order::Move(Awaitable::Queued(mover) | Awaitable::Awaiting(mover)) => {
+                Some(*mover.get_target_position())
+            }
+            Order::AttackMove(attack_move) => Some(attack_move.destination),
+            Order::Patrol(patrol) => patrol.points.last().copied(),
+            _ => None,
+        }
+    }
+    /// Destination of the pathfinding move overriding the orders, e.g. back from an AI chase.
+    pub fn returning_to(&self) -> Option<Vec3> {
+        match &self.override_order {
+            Some(Order::Move(Awaitable::Queued(mover) | Awaitable::Awaiting(mover)))
+                if mover.uses_pathfinding() =>
+            {
+                Some(*mover.get_target_position())
+            }
+            _ => None,
+        }
+    }
     pub fn order_move(target: Vec3) -> Order {
         Order::Move(Awaitable::Queued(Mover::new_to_target(target)))
     }
+    pub fn order_move_with(mover: Mover) -> Order {
+        Order::Move(Awaitable::Queued(mover))
+    }
+    /// Replaces the entities these orders refer to, fails if one of them can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) -> Result<(), ()> {
+        for order in self.orders.iter_mut().chain(self.override_order.iter_mut()) {
+            order.map_entities(mapper)?;
+        }
+        Ok(())
+    }
+}
+
+/// Orders issued to a unit, applied at the beginning of the next simulation tick.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrderRequest {
+    #[serde(with = "crate::core_game::serialization::entity_index")]
+    pub entity: Entity,
+    pub orders: Vec<Order>,
+    /// Orders are added after the current ones instead of replacing them.
+    pub queue: bool,
+}
+impl OrderRequest {
+    /// Replaces the entities this request refers to, fails if one of them can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) -> Result<(), ()> {
+        self.entity = mapper(self.entity).ok_or(())?;
+        for order in self.orders.iter_mut() {
+            order.map_entities(mapper)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every order should go through this resource rather than `Orders`, so they can be recorded.
+#[derive(Default)]
+pub struct OrderRequests {
+    pub(crate) requests: Vec<OrderRequest>,
+}
+impl OrderRequests {
+    pub fn issue(&mut self, entity: Entity, orders: Vec<Order>, queue: bool) {
+        self.requests.push(OrderRequest {
+            entity,
+            orders,
+            queue,
+        });
+    }
+    pub fn get_requests(&self) -> &Vec<OrderRequest> {
+        &self.requests
+    }
 }
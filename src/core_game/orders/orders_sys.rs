@@ -1,17 +1,97 @@
-use crate::core_game::components::*;
+use crate::core_game::{
+    abilities::{Abilities, AbilityState, AbilityTarget, InterruptCause, Mana},
+    combat_log::{AttackInterrupted, AttackStarted},
+    components::*,
+    simulation::SimulationTime,
+    status_effects::{self, StatusEffects},
+};
 use bevy::prelude::*;
 
 use super::orders_comp::*;
 
+pub fn apply_order_requests(
+    mut order_requests: ResMut<OrderRequests>,
+    mut query: Query<&mut Orders>,
+) {
+    for request in order_requests.requests.drain(..) {
+        if let Ok(mut orders) = query.get_mut(request.entity) {
+            if request.queue {
+                orders.add_orders(request.orders);
+            } else {
+                orders.replace_orders(request.orders);
+            }
+        }
+    }
+}
+
+/// What orders act upon, besides the `Orders` themselves.
+struct OrderedUnit<'a> {
+    entity: Entity,
+    mover: Mut<'a, Mover>,
+    ai: Mut<'a, AIUnit>,
+    abilities: &'a Abilities,
+    ability_state: Mut<'a, AbilityState>,
+    mana: Option<Mut<'a, Mana>>,
+    transform: &'a Transform,
+    size: &'a UnitSize,
+}
+
 pub fn order_system(
-    mut query: Query<(&mut Orders, &mut Mover, &mut AIUnit, &mut MeleeAbilityState)>,
+    time: Res<SimulationTime>,
+    mut attack_started: EventWriter<AttackStarted>,
+    mut attack_interrupted: EventWriter<AttackInterrupted>,
+    mut query: Query<(
+        Entity,
+        &mut Orders,
+        &mut Mover,
+        &mut AIUnit,
+        &Abilities,
+        &mut AbilityState,
+        Option<&mut Mana>,
+        Option<&StatusEffects>,
+        &Transform,
+        &UnitSize,
+    )>,
+    q_targets: Query<(&Transform, &UnitSize)>,
 ) {
-    for (mut orders, mut mover, mut ai, mut melee_ability_state) in query.iter_mut() {
+    for (
+        entity,
+        mut orders,
+        mover,
+        ai,
+        abilities,
+        ability_state,
+        mana,
+        status_effects,
+        transform,
+        size,
+    ) in query.iter_mut()
+    {
+        // Orders wait for the stun to wear off.
+        if status_effects::is_stunned(status_effects) {
+            continue;
+        }
+        let mut unit = OrderedUnit {
+            entity,
+            mover,
+            ai,
+            abilities,
+            ability_state,
+            mana,
+            transform,
+            size,
+        };
+        let time = time.elapsed_seconds();
         if orders.override_order.is_some() {
             if let Some(order) = orders.override_order.as_mut() {
-                if let Err(not_done) =
-                    execute_order(&order, &mut mover, &mut ai, &mut melee_ability_state)
-                {
+                if let Err(not_done) = execute_order(
+                    &order,
+                    &mut unit,
+                    time,
+                    &q_targets,
+                    &mut attack_started,
+                    &mut attack_interrupted,
+                ) {
                     orders.override_order = not_done;
                 } else {
                     orders.override_order = None;
@@ -21,9 +101,11 @@ pub fn order_system(
         while orders.orders.len() > 0 {
             if let Err(not_done) = execute_order(
                 &orders.orders[0],
-                &mut mover,
-                &mut ai,
-                &mut melee_ability_state,
+                &mut unit,
+                time,
+                &q_targets,
+                &mut attack_started,
+                &mut attack_interrupted,
             ) {
                 if let Some(new_order) = not_done {
                     orders.orders[0] = new_order;
@@ -38,36 +120,343 @@ pub fn order_system(
 // OK means order was fully executed, Err means order is still ongoing.
 type ExecutionResult = Result<(), Option<Order>>;
 
+/// Space kept between a following unit and the unit it follows.
+const FOLLOW_DISTANCE: f32 = 20f32;
+
+fn interrupt_cast(unit: &mut OrderedUnit, attack_interrupted: &mut EventWriter<AttackInterrupted>) {
+    let cause = InterruptCause::Orders;
+    if let Some(ability) = unit.ability_state.interrupt(unit.abilities, cause) {
+        attack_interrupted.send(AttackInterrupted {
+            attacker: unit.entity,
+            ability,
+            cause,
+        });
+    }
+}
+
+fn stand_still(unit: &mut OrderedUnit) {
+    if !unit.mover.is_target_reached {
+        *unit.mover = Mover::new(unit.transform.translation);
+    }
+}
+
+/// Moves with `mover`, only replacing the current one if it goes elsewhere.
+fn move_with(unit: &mut OrderedUnit, mover: &Mover) {
+    if unit.mover.is_target_reached
+        || unit.mover.get_target_position() != mover.get_target_position()
+    {
+        *unit.mover = mover.clone();
+    }
+}
+
+/// Moves to `position` through pathfinding.
+fn move_to(unit: &mut OrderedUnit, position: Vec3) {
+    move_with(unit, &Mover::new_to_target(position).with_pathfinding());
+}
+
 fn execute_order(
     order: &Order,
-    mover: &mut Mut<Mover>,
-    ai: &mut Mut<AIUnit>,
-    melee_ability_state: &mut Mut<MeleeAbilityState>,
+    unit: &mut OrderedUnit,
+    time: f32,
+    q_targets: &Query<(&Transform, &UnitSize)>,
+    attack_started: &mut EventWriter<AttackStarted>,
+    attack_interrupted: &mut EventWriter<AttackInterrupted>,
 ) -> ExecutionResult {
     match order {
         // FIXME: debug with prints, I guess nothing is changing.
         Order::Ai(new_ai) => {
             match new_ai {
-                AIUnit::Passive => {
-                    // TODO: stop current attack
-                    melee_ability_state.interrupt();
-                }
+                AIUnit::Passive => interrupt_cast(unit, attack_interrupted),
                 AIUnit::SeekEnemy => {}
                 AIUnit::Attack(_) => {}
             }
-            **ai = new_ai.clone();
+            *unit.ai = new_ai.clone();
             return Ok(());
         }
         Order::Move(Awaitable::Queued(move_queued)) => {
-            **mover = move_queued.clone();
+            *unit.mover = move_queued.clone();
             return Err(Some(Order::Move(Awaitable::Awaiting(move_queued.clone()))));
         }
         Order::Move(Awaitable::Awaiting(_)) => {
-            if mover.is_target_reached {
+            if unit.mover.is_target_reached {
                 return Ok(());
             } else {
                 return Err(None);
             }
         }
+        Order::Cast(Awaitable::Queued(cast)) => {
+            let definition = match unit.abilities.0.get(cast.ability) {
+                Some(definition) => definition,
+                None => return Ok(()),
+            };
+            let target = match cast.target {
+                AbilityTarget::Unit(target) => match q_targets.get(target) {
+                    Ok((transform, size)) => Some((transform.translation, size.0)),
+                    // target is not valid
+                    Err(_) => return Ok(()),
+                },
+                AbilityTarget::Point(point) => Some((point, 0f32)),
+                AbilityTarget::Myself => None,
+            };
+            if let Some((position, size)) = target {
+                let distance = (position - unit.transform.translation).length();
+                if distance >= definition.range + size + unit.size.0 {
+                    move_to(unit, position);
+                    return Err(None);
+                }
+            }
+            // Waits for the ability to be ready, and affordable.
+            let mana = unit.mana.as_deref_mut();
+            if unit
+                .ability_state
+                .try_cast(unit.abilities, cast.ability, cast.target, time, mana)
+                .is_err()
+            {
+                return Err(None);
+            }
+            attack_started.send(AttackStarted {
+                attacker: unit.entity,
+                ability: cast.ability,
+                target: cast.target,
+            });
+            *unit.mover = Mover::new(unit.transform.translation);
+            return Err(Some(Order::Cast(Awaitable::Awaiting(cast.clone()))));
+        }
+        Order::Cast(Awaitable::Awaiting(_)) => {
+            if unit.ability_state.is_casting() {
+                return Err(None);
+            } else {
+                return Ok(());
+            }
+        }
+        Order::Stop => {
+            interrupt_cast(unit, attack_interrupted);
+            stand_still(unit);
+            *unit.ai = AIUnit::SeekEnemy;
+            return Ok(());
+        }
+        Order::HoldPosition => {
+            // Attacking is left to the AI, which knows the unit holds its position.
+            stand_still(unit);
+            return Err(None);
+        }
+        Order::AttackMove(attack_move) => {
+            // The AI chases enemies met on the way, then the move goes on.
+            if matches!(*unit.ai, AIUnit::Attack(_)) {
+                return Err(None);
+            }
+            if unit.mover.is_target_reached
+                && *unit.mover.get_target_position() == attack_move.destination
+            {
+                return Ok(());
+            }
+            match &attack_move.mover {
+                Some(mover) => move_with(unit, mover),
+                None => move_to(unit, attack_move.destination),
+            }
+            return Err(None);
+        }
+        Order::Patrol(patrol) => {
+            // The AI chases enemies met on the way, then the patrol goes on.
+            if matches!(*unit.ai, AIUnit::Attack(_)) {
+                return Err(None);
+            }
+            let point = match patrol.points.get(patrol.next) {
+                Some(point) => *point,
+                None => return Ok(()),
+            };
+            if unit.mover.is_target_reached && *unit.mover.get_target_position() == point {
+                let mut patrol = patrol.clone();
+                patrol.next = (patrol.next + 1) % patrol.points.len();
+                return Err(Some(Order::Patrol(patrol)));
+            }
+            move_to(unit, point);
+            return Err(None);
+        }
+        Order::Follow(follow) => {
+            let (position, size) = match q_targets.get(follow.target) {
+                Ok((transform, size)) => (transform.translation, size.0),
+                Err(_) => return Ok(()),
+            };
+            if matches!(*unit.ai, AIUnit::Attack(_)) {
+                return Err(None);
+            }
+            let distance = (position - unit.transform.translation).length();
+            if distance > size + unit.size.0 + FOLLOW_DISTANCE {
+                move_to(unit, position);
+            } else {
+                stand_still(unit);
+            }
+            return Err(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert_resource(SimulationTime::default());
+        world.insert_resource(Events::<AttackStarted>::default());
+        world.insert_resource(Events::<AttackInterrupted>::default());
+        world
+    }
+
+    fn spawn_unit(world: &mut World, position: Vec3) -> Entity {
+        world
+            .spawn()
+            .insert_bundle((
+                Orders::default(),
+                Mover::new(position),
+                AIUnit::SeekEnemy,
+                Abilities::default(),
+                AbilityState::default(),
+                Transform::from_translation(position),
+                UnitSize(10f32),
+            ))
+            .id()
+    }
+
+    fn tick(world: &mut World) {
+        SystemStage::single_threaded()
+            .with_system(order_system)
+            .run(world);
+    }
+
+    fn give_orders(world: &mut World, entity: Entity, orders: Vec<Order>) {
+        world
+            .get_mut::<Orders>(entity)
+            .unwrap()
+            .replace_orders(orders);
+    }
+
+    /// Puts the unit where it was going.
+    fn arrive(world: &mut World, entity: Entity) {
+        let target = *world.get::<Mover>(entity).unwrap().get_target_position();
+        *world.get_mut::<Mover>(entity).unwrap() = Mover::new(target);
+        world.get_mut::<Transform>(entity).unwrap().translation = target;
+    }
+
+    #[test]
+    fn stop_stands_still_and_seeks_enemies() {
+        let mut world = world();
+        let target = spawn_unit(&mut world, Vec3::new(200f32, 0f32, 0f32));
+        let unit = spawn_unit(&mut world, Vec3::ZERO);
+        *world.get_mut::<Mover>(unit).unwrap() =
+            Mover::new_to_target(Vec3::new(100f32, 0f32, 0f32));
+        *world.get_mut::<AIUnit>(unit).unwrap() = AIUnit::Attack(Attack {
+            target,
+            chase_when_target_too_far: true,
+            return_position: None,
+        });
+        give_orders(&mut world, unit, vec![Order::Stop]);
+        tick(&mut world);
+        let mover = world.get::<Mover>(unit).unwrap();
+        assert!(mover.is_target_reached);
+        assert_eq!(*mover.get_target_position(), Vec3::ZERO);
+        assert!(matches!(world.get::<AIUnit>(unit), Some(AIUnit::SeekEnemy)));
+        assert!(world.get::<Orders>(unit).unwrap().get_orders().is_empty());
+    }
+
+    #[test]
+    fn hold_position_lasts_until_replaced() {
+        let mut world = world();
+        let unit = spawn_unit(&mut world, Vec3::ZERO);
+        *world.get_mut::<Mover>(unit).unwrap() =
+            Mover::new_to_target(Vec3::new(100f32, 0f32, 0f32));
+        give_orders(
+            &mut world,
+            unit,
+            vec![Order::Ai(AIUnit::SeekEnemy), Order::HoldPosition],
+        );
+        tick(&mut world);
+        tick(&mut world);
+        assert!(world.get::<Orders>(unit).unwrap().is_holding_position());
+        assert!(world.get::<Mover>(unit).unwrap().is_target_reached);
+
+        let destination = Vec3::new(0f32, 100f32, 0f32);
+        give_orders(&mut world, unit, vec![Orders::order_move(destination)]);
+        tick(&mut world);
+        assert!(!world.get::<Orders>(unit).unwrap().is_holding_position());
+        assert_eq!(
+            *world.get::<Mover>(unit).unwrap().get_target_position(),
+            destination
+        );
+    }
+
+    #[test]
+    fn patrol_goes_back_and_forth() {
+        let mut world = world();
+        let (a, b) = (Vec3::ZERO, Vec3::new(100f32, 0f32, 0f32));
+        let unit = spawn_unit(&mut world, a);
+        let patrol = Order::Patrol(Patrol {
+            points: vec![a, b],
+            next: 1,
+        });
+        give_orders(&mut world, unit, vec![patrol]);
+        tick(&mut world);
+        assert_eq!(*world.get::<Mover>(unit).unwrap().get_target_position(), b);
+        arrive(&mut world, unit);
+        // Turns around, then heads back.
+        tick(&mut world);
+        tick(&mut world);
+        let mover = world.get::<Mover>(unit).unwrap();
+        assert!(!mover.is_target_reached);
+        assert_eq!(*mover.get_target_position(), a);
+        assert_eq!(world.get::<Orders>(unit).unwrap().get_orders().len(), 1);
+    }
+
+    #[test]
+    fn follow_keeps_close_until_the_target_dies() {
+        let mut world = world();
+        let leader_position = Vec3::new(200f32, 0f32, 0f32);
+        let leader = spawn_unit(&mut world, leader_position);
+        let unit = spawn_unit(&mut world, Vec3::ZERO);
+        give_orders(
+            &mut world,
+            unit,
+            vec![Order::Follow(Follow { target: leader })],
+        );
+        tick(&mut world);
+        assert_eq!(
+            *world.get::<Mover>(unit).unwrap().get_target_position(),
+            leader_position
+        );
+
+        world.get_mut::<Transform>(unit).unwrap().translation = Vec3::new(180f32, 0f32, 0f32);
+        tick(&mut world);
+        assert!(world.get::<Mover>(unit).unwrap().is_target_reached);
+
+        world.despawn(leader);
+        tick(&mut world);
+        assert!(world.get::<Orders>(unit).unwrap().get_orders().is_empty());
+    }
+
+    #[test]
+    fn last_waypoint_of_queued_orders() {
+        let mut orders = Orders::default();
+        assert_eq!(orders.last_waypoint(), None);
+        let point = Vec3::new(50f32, 50f32, 0f32);
+        orders.add_orders(vec![
+            Order::Ai(AIUnit::SeekEnemy),
+            Order::AttackMove(AttackMove {
+                destination: point,
+                mover: None,
+            }),
+        ]);
+        assert_eq!(orders.last_waypoint(), Some(point));
+        let end = Vec3::new(80f32, 0f32, 0f32);
+        orders.add_orders(vec![
+            Order::Ai(AIUnit::SeekEnemy),
+            Order::Patrol(Patrol {
+                points: vec![point, end],
+                next: 1,
+            }),
+        ]);
+        assert_eq!(orders.last_waypoint(), Some(end));
+        orders.add_orders(vec![Order::HoldPosition]);
+        assert_eq!(orders.last_waypoint(), None);
     }
 }
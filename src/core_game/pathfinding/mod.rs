@@ -1,15 +1,160 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 
+use self::pathfinding_comp::{FlowField, Pos, PosF};
+use super::{
+    map::TILE_SIZE,
+    orders::orders_comp::{Mover, MoverPath},
+    simulation::{SimulationLabel, SimulationSchedule, SimulationStage},
+};
+
+/// How many tiles ahead of a unit the flow field is looked at, to move in straighter lines.
+const FLOW_FIELD_LOOKAHEAD: usize = 4;
+/// A mover not getting further than this from where it was is considered not progressing.
+const STUCK_DISTANCE: f32 = TILE_SIZE / 4f32;
+/// Ticks without progress after which a mover path is computed again.
+const STUCK_TICKS: u32 = 60;
+
 pub struct PathfindingPlugin;
 
 impl Plugin for PathfindingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(system::setup);
+        app.init_resource::<FlowFields>()
+            .add_startup_system(system::setup)
+            .stage(SimulationSchedule, |schedule: &mut Schedule| {
+                schedule
+                    .add_system_to_stage(
+                        SimulationStage::First,
+                        system::update_flow_fields
+                            .label(SimulationLabel::FlowFields)
+                            .after(SimulationLabel::Vision),
+                    )
+                    .add_system_to_stage(
+                        SimulationStage::First,
+                        system::update_mover_paths
+                            .label(SimulationLabel::MoverPaths)
+                            .after(SimulationLabel::FlowFields),
+                    )
+            });
+    }
+}
+
+/// Flow fields of the destinations movers are currently going to, by destination tile.
+///
+/// Fields are computed when a destination appears, and dropped when no mover goes there anymore
+/// or the map changes.
+#[derive(Default)]
+pub struct FlowFields {
+    fields: HashMap<Pos, FlowField>,
+}
+impl FlowFields {
+    pub fn get(&self, destination: &Pos) -> Option<&FlowField> {
+        self.fields.get(destination)
+    }
+}
+
+fn tile_at(world_map: &crate::core_game::map::Map, position: Vec3) -> Pos {
+    (
+        world_map.map_x_at(position.x) as i32,
+        world_map.map_y_at(position.y) as i32,
+    )
+}
+
+/// World position a mover should head to right now.
+///
+/// Goes straight to the mover target when in sight, else follows its path or group flow field.
+pub fn steering_target(
+    map: &pathfinding_comp::Map,
+    world_map: &crate::core_game::map::Map,
+    flow_fields: &FlowFields,
+    position: Vec3,
+    mover: &Mover,
+    path: Option<&MoverPath>,
+    radius: f32,
+) -> Vec3 {
+    let target = *mover.get_target_position();
+    let waypoint = path
+        .filter(|_| mover.uses_pathfinding())
+        .and_then(|path| path.waypoints.first());
+    let destination = mover.get_flow_field_destination();
+    if mover.is_target_reached || !map.is_ready() || (waypoint.is_none() && destination.is_none()) {
+        return target;
+    }
+    let clearance = radius / TILE_SIZE;
+    let from = world_map.tile_position_at(position.truncate());
+    if map.line_of_sight(
+        from,
+        world_map.tile_position_at(target.truncate()),
+        clearance,
+    ) {
+        return target;
+    }
+    if let Some(waypoint) = waypoint {
+        return *waypoint;
+    }
+    let destination = match destination {
+        Some(destination) => destination,
+        None => return target,
+    };
+    let field = match flow_fields.get(&tile_at(world_map, *destination)) {
+        Some(field) => field,
+        None => return target,
+    };
+    let mut at = tile_at(world_map, position);
+    let mut steer_to: Option<Pos> = None;
+    for _ in 0..FLOW_FIELD_LOOKAHEAD {
+        let next = match map.flow_direction(field, at) {
+            Some(next) => next,
+            None => break,
+        };
+        let next_f: PosF = (next.0 as f32, next.1 as f32);
+        if steer_to.is_some() && !map.line_of_sight(from, next_f, clearance) {
+            break;
+        }
+        steer_to = Some(next);
+        at = next;
+    }
+    match steer_to {
+        Some(tile) => world_map
+            .real_position_at(tile.0 as usize, tile.1 as usize)
+            .extend(0f32),
+        None => target,
+    }
+}
+
+/// World positions to go through from `from` to `to`, `to` being the last one.
+///
+/// Falls back to a straight line when no path is found.
+pub fn find_world_waypoints(
+    map: &pathfinding_comp::Map,
+    world_map: &crate::core_game::map::Map,
+    from: Vec3,
+    to: Vec3,
+    radius: f32,
+) -> Vec<Vec3> {
+    if !map.is_ready() {
+        return vec![to];
+    }
+    let start = world_map.tile_position_at(from.truncate());
+    let end = world_map.tile_position_at(to.truncate());
+    match map.find_waypoints(start, end, radius / TILE_SIZE) {
+        Ok(mut waypoints) => {
+            // Last waypoint is `to`, without rounding errors.
+            waypoints.pop();
+            let mut world_waypoints: Vec<Vec3> = waypoints
+                .into_iter()
+                .map(|waypoint| world_map.real_position_from_tile(waypoint).extend(0f32))
+                .collect();
+            world_waypoints.push(to);
+            world_waypoints
+        }
+        Err(()) => vec![to],
     }
 }
 
 pub mod pathfinding_comp {
-    use std::collections::HashMap;
+    use std::{cmp::Ordering, collections::BinaryHeap};
 
     #[derive(Clone, PartialEq)]
     pub enum TileType {
@@ -24,24 +169,74 @@ pub mod pathfinding_comp {
     }
 
     pub type Pos = (i32, i32);
-    type SearchResult = (HashMap<Pos, Option<Pos>>, HashMap<Pos, f32>);
-    #[derive(Default)]
+    /// Position in tile units, tile centers are at integer coordinates.
+    pub type PosF = (f32, f32);
+
+    const DIAGONAL_COST: f32 = std::f32::consts::SQRT_2;
+
+    struct PriorityEntry<T> {
+        priority: f32,
+        // Insertion order, so elements with the same priority come out first in, first out.
+        order: u64,
+        element: T,
+    }
+    impl<T> PartialEq for PriorityEntry<T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+    impl<T> Eq for PriorityEntry<T> {}
+    impl<T> PartialOrd for PriorityEntry<T> {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+    impl<T> Ord for PriorityEntry<T> {
+        // Reversed, as `BinaryHeap` is a max-heap.
+        fn cmp(&self, other: &Self) -> Ordering {
+            other
+                .priority
+                .partial_cmp(&self.priority)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| other.order.cmp(&self.order))
+        }
+    }
+
     struct PriorityQueue<T> {
-        elements: Vec<(f32, T)>,
+        elements: BinaryHeap<PriorityEntry<T>>,
+        next_order: u64,
+    }
+    impl<T> Default for PriorityQueue<T> {
+        fn default() -> Self {
+            Self {
+                elements: BinaryHeap::new(),
+                next_order: 0,
+            }
+        }
     }
     impl<T> PriorityQueue<T> {
         pub fn put(&mut self, new_element: T, priority: f32) {
-            for i in 0..self.elements.len() {
-                if priority >= self.elements[i].0 {
-                    self.elements.insert(i, (priority, new_element));
-                    return;
-                }
-            }
-            self.elements.push((priority, new_element));
+            self.elements.push(PriorityEntry {
+                priority,
+                order: self.next_order,
+                element: new_element,
+            });
+            self.next_order += 1;
         }
         /// Returns lowest priority element
         pub fn get(&mut self) -> Option<T> {
-            Some(self.elements.pop()?.1)
+            Some(self.elements.pop()?.element)
+        }
+    }
+
+    /// Result of [`Map::flow_field`].
+    pub struct FlowField {
+        destination: Pos,
+        costs: Vec<f32>,
+    }
+    impl FlowField {
+        pub fn destination(&self) -> Pos {
+            self.destination
         }
     }
 
@@ -51,7 +246,7 @@ pub mod pathfinding_comp {
         pub(super) height: i32,
     }
     impl Map {
-        pub(super) fn new(width: u32, height: u32) -> Self {
+        pub(crate) fn new(width: u32, height: u32) -> Self {
             Map {
                 tiles: Some(vec![TileType::Free; (width * height) as usize]),
                 width: width as i32,
@@ -66,105 +261,299 @@ pub mod pathfinding_comp {
             (pos.0 + dir.0, pos.1 + dir.1)
         }
 
-        fn neighbors(&self, pos: &Pos) -> [Pos; 4] {
-            const directions: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
-            let mut neighbors = [(0, 0); 4];
-            let mut i = 0;
+        /// Neighbors reachable from `pos` in one move, with the cost of that move.
+        ///
+        /// Diagonal moves can't cut corners: both adjacent tiles have to be free.
+        fn neighbors_octile(&self, pos: &Pos) -> impl Iterator<Item = (Pos, f32)> {
+            const directions: [(i32, i32); 8] = [
+                (0, 1),
+                (1, 0),
+                (0, -1),
+                (-1, 0),
+                (1, 1),
+                (1, -1),
+                (-1, -1),
+                (-1, 1),
+            ];
+            let is_free = |p: &Pos| matches!(self.get_tile(p), Ok(TileType::Free));
+            let mut neighbors = [((0, 0), 0f32); 8];
+            let mut count = 0;
             for dir in directions.iter() {
-                neighbors[i] = Self::to(pos, dir);
-                i += 1;
-            }
-            neighbors
-        }
-
-        fn find_path_rec(&self, path: Vec<Pos>, end: Pos) -> Result<Vec<Pos>, ()> {
-            let current_pos = path.last().unwrap();
-            for dir in self.neighbors(current_pos).iter() {
-                let next_pos = Self::to(current_pos, &dir);
-                if path.contains(&next_pos) {
-                    continue;
-                }
-                if next_pos == end {
-                    return Ok(path);
-                }
-                let next_tile = self.get_tile(&next_pos)?;
-                if next_tile == TileType::Wall {
+                let next = Self::to(pos, dir);
+                if !is_free(&next) {
                     continue;
                 }
-                let mut future_path = path.clone();
-                future_path.push(next_pos.clone());
-                let computed_path = self.find_path_rec(future_path, end);
-                if computed_path.is_ok() {
-                    return computed_path;
+                if dir.0 != 0 && dir.1 != 0 {
+                    if !is_free(&(pos.0 + dir.0, pos.1)) || !is_free(&(pos.0, pos.1 + dir.1)) {
+                        continue;
+                    }
+                    neighbors[count] = (next, DIAGONAL_COST);
+                } else {
+                    neighbors[count] = (next, 1f32);
                 }
+                count += 1;
             }
-            Err(())
+            neighbors.into_iter().take(count)
         }
 
-        fn find_path_breadth(&self, start: Pos, end: Pos) -> Result<SearchResult, SearchResult> {
-            let size = (self.width * self.height) as usize;
+        /// Octile distance, admissible for 8-directional moves.
+        fn heuristic(from: &Pos, to: &Pos) -> f32 {
+            let dx = (from.0 - to.0).abs() as f32;
+            let dy = (from.1 - to.1).abs() as f32;
+            dx.max(dy) + (DIAGONAL_COST - 1f32) * dx.min(dy)
+        }
 
-            // frontier needs to be a priorityQueue : https://www.redblobgames.com/pathfinding/a-star/implementation.html#python-dijkstra
-            let mut frontier = PriorityQueue::<Pos>::default();
-            frontier.put(start, 0f32);
+        fn index(&self, pos: &Pos) -> usize {
+            (pos.0 + pos.1 * self.width) as usize
+        }
 
-            let mut come_from: HashMap<Pos, Option<Pos>> = HashMap::new(); //vec![None; 0];
-            let mut cost_so_far: HashMap<Pos, f32> = HashMap::new();
+        /// Returns for each tile the tile it's reached from, if `end` is reachable.
+        fn find_path_astar(
+            &self,
+            start: Pos,
+            end: Pos,
+            heuristic: impl Fn(&Pos, &Pos) -> f32,
+        ) -> Result<Vec<Option<Pos>>, ()> {
+            let size = (self.width * self.height) as usize;
+            let mut frontier = PriorityQueue::<Pos>::default();
+            frontier.put(start, heuristic(&start, &end));
 
-            come_from.insert(start, None);
-            cost_so_far.insert(start, 0f32);
+            let mut come_from: Vec<Option<Pos>> = vec![None; size];
+            let mut cost_so_far: Vec<f32> = vec![f32::INFINITY; size];
+            cost_so_far[self.index(&start)] = 0f32;
 
             while let Some(current) = frontier.get() {
                 if current == end {
-                    return Ok((come_from, cost_so_far));
+                    return Ok(come_from);
                 }
-                for next in self.neighbors(&current).iter() {
-                    if !matches!(self.get_tile(next), Ok(TileType::Free)) {
-                        continue;
-                    }
-                    let new_cost = cost_so_far.get(&current).unwrap() + 1f32;
-                    if !cost_so_far.contains_key(next) || new_cost < *cost_so_far.get(next).unwrap()
-                    {
-                        cost_so_far.insert(*next, new_cost);
-                        frontier.put(*next, new_cost);
-                        come_from.insert(*next, Some(current));
+                let current_cost = cost_so_far[self.index(&current)];
+                for (next, move_cost) in self.neighbors_octile(&current) {
+                    let new_cost = current_cost + move_cost;
+                    let next_index = self.index(&next);
+                    if new_cost < cost_so_far[next_index] {
+                        cost_so_far[next_index] = new_cost;
+                        frontier.put(next, new_cost + heuristic(&next, &end));
+                        come_from[next_index] = Some(current);
                     }
                 }
             }
-            return Err((come_from, cost_so_far));
+            Err(())
+        }
+
+        /// Shortest path from `start` to `end`, both included, moving in 8 directions.
+        pub fn astar(&self, start: Pos, end: Pos) -> Result<Vec<Pos>, ()> {
+            self.shortest_path(start, end, Self::heuristic)
         }
 
-        fn reconstruct_path(
-            came_from: HashMap<Pos, Option<Pos>>,
+        /// Same as `astar` without heuristic, as a baseline for benches.
+        #[cfg(test)]
+        pub(crate) fn dijkstra(&self, start: Pos, end: Pos) -> Result<Vec<Pos>, ()> {
+            self.shortest_path(start, end, |_, _| 0f32)
+        }
+
+        fn shortest_path(
+            &self,
             start: Pos,
-            goal: Pos,
-        ) -> Vec<Pos> {
-            let mut current = goal;
+            end: Pos,
+            heuristic: impl Fn(&Pos, &Pos) -> f32,
+        ) -> Result<Vec<Pos>, ()> {
+            if self.get_tile(&start).is_err() || !matches!(self.get_tile(&end), Ok(TileType::Free))
+            {
+                return Err(());
+            }
+            let come_from = self.find_path_astar(start, end, heuristic)?;
+            let mut current = end;
             let mut path = vec![];
             while current != start {
                 path.push(current);
-                current = came_from.get(&current).unwrap().unwrap();
+                current = come_from[self.index(&current)].ok_or(())?;
             }
             path.push(start);
             path.reverse();
-            path
+            Ok(path)
+        }
+
+        /// Checks the segment `from` -> `to` only crosses free tiles,
+        /// the starting tile is ignored as the unit is already there.
+        ///
+        /// Passing exactly through a corner requires both tiles around it to be free.
+        fn segment_is_free(&self, from: PosF, to: PosF) -> bool {
+            let is_free = |x: i32, y: i32| matches!(self.get_tile(&(x, y)), Ok(TileType::Free));
+            // Tile `i` covers [i - 0.5, i + 0.5[, shift so it covers [i, i + 1[.
+            let (x0, y0) = (from.0 + 0.5, from.1 + 0.5);
+            let (x1, y1) = (to.0 + 0.5, to.1 + 0.5);
+            let (mut cx, mut cy) = (x0.floor() as i32, y0.floor() as i32);
+            let (ex, ey) = (x1.floor() as i32, y1.floor() as i32);
+            let (dx, dy) = (x1 - x0, y1 - y0);
+            let step_x = if dx > 0f32 { 1 } else { -1 };
+            let step_y = if dy > 0f32 { 1 } else { -1 };
+            let t_delta_x = if dx != 0f32 {
+                1f32 / dx.abs()
+            } else {
+                f32::INFINITY
+            };
+            let t_delta_y = if dy != 0f32 {
+                1f32 / dy.abs()
+            } else {
+                f32::INFINITY
+            };
+            let mut t_max_x = if dx > 0f32 {
+                (cx as f32 + 1f32 - x0) / dx
+            } else if dx < 0f32 {
+                (x0 - cx as f32) / -dx
+            } else {
+                f32::INFINITY
+            };
+            let mut t_max_y = if dy > 0f32 {
+                (cy as f32 + 1f32 - y0) / dy
+            } else if dy < 0f32 {
+                (y0 - cy as f32) / -dy
+            } else {
+                f32::INFINITY
+            };
+            // Guards against floating point errors never reaching the last tile.
+            let max_steps = (ex - cx).abs() + (ey - cy).abs() + 2;
+            for _ in 0..max_steps {
+                if cx == ex && cy == ey {
+                    return true;
+                }
+                if (t_max_x - t_max_y).abs() < f32::EPSILON {
+                    if !is_free(cx + step_x, cy) || !is_free(cx, cy + step_y) {
+                        return false;
+                    }
+                    t_max_x += t_delta_x;
+                    t_max_y += t_delta_y;
+                    cx += step_x;
+                    cy += step_y;
+                } else if t_max_x < t_max_y {
+                    t_max_x += t_delta_x;
+                    cx += step_x;
+                } else {
+                    t_max_y += t_delta_y;
+                    cy += step_y;
+                }
+                if !is_free(cx, cy) {
+                    return false;
+                }
+            }
+            cx == ex && cy == ey
+        }
+
+        /// Checks a unit of radius `clearance` (in tiles) can move straight from `from` to `to`.
+        pub fn line_of_sight(&self, from: PosF, to: PosF, clearance: f32) -> bool {
+            if !self.segment_is_free(from, to) {
+                return false;
+            }
+            let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+            let length = (dx * dx + dy * dy).sqrt();
+            if clearance <= 0f32 || length == 0f32 {
+                return true;
+            }
+            // Also check both sides of the unit.
+            let normal = (-dy / length * clearance, dx / length * clearance);
+            [1f32, -1f32].iter().all(|side| {
+                let offset = (normal.0 * side, normal.1 * side);
+                self.segment_is_free(
+                    (from.0 + offset.0, from.1 + offset.1),
+                    (to.0 + offset.0, to.1 + offset.1),
+                )
+            })
+        }
+
+        /// Removes waypoints which can be skipped by moving in a straight line (string pulling).
+        ///
+        /// Returns waypoints to follow after `start`, the last one being `path`'s last.
+        pub fn smooth_path(&self, start: PosF, path: &[PosF], clearance: f32) -> Vec<PosF> {
+            let mut waypoints = vec![];
+            let mut anchor = start;
+            let mut i = 0;
+            while i < path.len() {
+                let mut furthest = i;
+                for j in (i + 1)..path.len() {
+                    if self.line_of_sight(anchor, path[j], clearance) {
+                        furthest = j;
+                    }
+                }
+                anchor = path[furthest];
+                waypoints.push(anchor);
+                i = furthest + 1;
+            }
+            waypoints
         }
 
-        pub fn dijkstra(&self, start: Pos, end: Pos) -> Result<Vec<Pos>, ()> {
-            if let Ok(raw_res) = self.find_path_breadth(start, end) {
-                let res = Self::reconstruct_path(raw_res.0, start, end);
-                return Ok(res);
+        /// Minimal set of waypoints to go from `start` to `end`, `end` being the last one.
+        pub fn find_waypoints(
+            &self,
+            start: PosF,
+            end: PosF,
+            clearance: f32,
+        ) -> Result<Vec<PosF>, ()> {
+            if self.line_of_sight(start, end, clearance) {
+                return Ok(vec![end]);
             }
-            return Err(());
+            let start_tile = (start.0.round() as i32, start.1.round() as i32);
+            let end_tile = (end.0.round() as i32, end.1.round() as i32);
+            let path = self.astar(start_tile, end_tile)?;
+            // First tile is where the unit already is, last one is replaced by the exact end.
+            let mut points: Vec<PosF> = path
+                .iter()
+                .skip(1)
+                .map(|pos| (pos.0 as f32, pos.1 as f32))
+                .collect();
+            points.pop();
+            points.push(end);
+            Ok(self.smooth_path(start, &points, clearance))
         }
 
-        pub fn find_path(&self, start: Pos, end: Pos) -> Result<Vec<Pos>, ()> {
-            // TODO: make a lot of helper functions to move
-            let path: Vec<Pos>;
-            let current_pos = start;
-            let current_tile = self.get_tile(&current_pos);
+        /// Cost to reach `destination` from every tile, computed once for a whole group.
+        pub fn flow_field(&self, destination: Pos) -> Result<FlowField, ()> {
+            if !matches!(self.get_tile(&destination), Ok(TileType::Free)) {
+                return Err(());
+            }
+            let mut frontier = PriorityQueue::<Pos>::default();
+            frontier.put(destination, 0f32);
+            let mut costs = vec![f32::INFINITY; (self.width * self.height) as usize];
+            costs[self.index(&destination)] = 0f32;
+
+            while let Some(current) = frontier.get() {
+                let current_cost = costs[self.index(&current)];
+                // Moves are symmetric, so reaching `next` from `current` costs the same both ways.
+                for (next, move_cost) in self.neighbors_octile(&current) {
+                    let new_cost = current_cost + move_cost;
+                    let next_index = self.index(&next);
+                    if new_cost < costs[next_index] {
+                        costs[next_index] = new_cost;
+                        frontier.put(next, new_cost);
+                    }
+                }
+            }
+            Ok(FlowField { destination, costs })
+        }
+
+        /// Next tile to go to from `at` to follow `field`,
+        /// `None` when `at` is the destination or can't reach it.
+        pub fn flow_direction(&self, field: &FlowField, at: Pos) -> Option<Pos> {
+            if at == field.destination || self.get_tile(&at).is_err() {
+                return None;
+            }
+            let current_cost = field.costs[self.index(&at)];
+            let mut best: Option<(Pos, f32)> = None;
+            for (next, move_cost) in self.neighbors_octile(&at) {
+                let cost = field.costs[self.index(&next)] + move_cost;
+                if cost.is_finite() && best.map_or(true, |(_, best_cost)| cost < best_cost) {
+                    best = Some((next, cost));
+                }
+            }
+            // A unit pushed into a wall has an infinite cost, any way out is fine.
+            best.filter(|(_, cost)| *cost <= current_cost || current_cost.is_infinite())
+                .map(|(next, _)| next)
+        }
 
-            return self.find_path_rec(vec![start], end);
+        #[cfg(test)]
+        pub(crate) fn set_tile(&mut self, at: &Pos, tile: TileType) {
+            if let Some(tiles) = self.tiles.as_mut() {
+                tiles[(at.0 + at.1 * self.width) as usize] = tile;
+            }
         }
         pub fn get_tile(&self, at: &Pos) -> Result<TileType, ()> {
             if at.0 < 0 || at.0 >= self.width as i32 {
@@ -195,6 +584,114 @@ pub mod pathfinding_comp {
             assert_eq!(pq.get().unwrap(), 3);
         }
 
+        #[test]
+        fn astar_diagonal() {
+            let map = Map::new(5, 5);
+            let path = map.astar((0, 0), (3, 3));
+            assert_eq!(path, Ok(vec![(0, 0), (1, 1), (2, 2), (3, 3)]))
+        }
+        #[test]
+        fn astar_no_corner_cutting() {
+            let mut map = Map::new(3, 3);
+            map.set_tile(&(1, 0), TileType::Wall);
+            let path = map.astar((0, 0), (1, 1)).unwrap();
+            assert_eq!(path, vec![(0, 0), (0, 1), (1, 1)]);
+        }
+        #[test]
+        fn astar_around_wall() {
+            let mut map = Map::new(5, 5);
+            for y in 0..4 {
+                map.set_tile(&(2, y), TileType::Wall);
+            }
+            let path = map.astar((0, 0), (4, 0)).unwrap();
+            assert_eq!(path.first(), Some(&(0, 0)));
+            assert_eq!(path.last(), Some(&(4, 0)));
+            assert!(path.contains(&(2, 4)));
+        }
+        #[test]
+        fn astar_start_out_of_map() {
+            let map = Map::new(3, 3);
+            assert_eq!(map.astar((-1, 0), (2, 2)), Err(()));
+            assert_eq!(map.astar((3, 0), (2, 2)), Err(()));
+            assert_eq!(map.astar((0, 3), (2, 2)), Err(()));
+        }
+        #[test]
+        fn astar_unreachable() {
+            let mut map = Map::new(3, 3);
+            map.set_tile(&(2, 2), TileType::Wall);
+            assert_eq!(map.astar((0, 0), (2, 2)), Err(()));
+            for y in 0..3 {
+                map.set_tile(&(1, y), TileType::Wall);
+            }
+            assert_eq!(map.astar((0, 0), (2, 0)), Err(()));
+        }
+
+        #[test]
+        fn line_of_sight_through_corner() {
+            let mut map = Map::new(3, 3);
+            assert!(map.line_of_sight((0.0, 0.0), (2.0, 2.0), 0.0));
+            map.set_tile(&(1, 0), TileType::Wall);
+            assert!(!map.line_of_sight((0.0, 0.0), (2.0, 2.0), 0.0));
+            assert!(map.line_of_sight((0.0, 0.0), (0.0, 2.0), 0.0));
+        }
+        #[test]
+        fn line_of_sight_clearance() {
+            let mut map = Map::new(5, 3);
+            map.set_tile(&(2, 0), TileType::Wall);
+            assert!(map.line_of_sight((0.0, 1.0), (4.0, 1.0), 0.0));
+            assert!(map.line_of_sight((0.0, 1.0), (4.0, 1.0), 0.3));
+            assert!(!map.line_of_sight((0.0, 1.0), (4.0, 1.0), 0.6));
+        }
+        #[test]
+        fn waypoints_straight() {
+            let map = Map::new(5, 5);
+            let waypoints = map.find_waypoints((0.2, 0.0), (4.0, 3.0), 0.0);
+            assert_eq!(waypoints, Ok(vec![(4.0, 3.0)]));
+        }
+        #[test]
+        fn waypoints_around_wall() {
+            let mut map = Map::new(5, 5);
+            for y in 0..4 {
+                map.set_tile(&(2, y), TileType::Wall);
+            }
+            let start = (0.0, 0.0);
+            let end = (4.0, 0.0);
+            let waypoints = map.find_waypoints(start, end, 0.0).unwrap();
+            assert_eq!(waypoints, vec![(1.0, 4.0), (3.0, 4.0), end]);
+            let mut from = start;
+            for waypoint in waypoints {
+                assert!(map.line_of_sight(from, waypoint, 0.0));
+                from = waypoint;
+            }
+        }
+        #[test]
+        fn flow_field_around_wall() {
+            let mut map = Map::new(5, 5);
+            for y in 0..4 {
+                map.set_tile(&(2, y), TileType::Wall);
+            }
+            let field = map.flow_field((4, 0)).unwrap();
+            let mut at = (0, 0);
+            let mut steps = 0;
+            while let Some(next) = map.flow_direction(&field, at) {
+                assert!(matches!(map.get_tile(&next), Ok(TileType::Free)));
+                at = next;
+                steps += 1;
+            }
+            assert_eq!(at, (4, 0));
+            assert_eq!(steps, map.astar((0, 0), (4, 0)).unwrap().len() - 1);
+        }
+        #[test]
+        fn flow_field_unreachable() {
+            let mut map = Map::new(3, 3);
+            for y in 0..3 {
+                map.set_tile(&(1, y), TileType::Wall);
+            }
+            let field = map.flow_field((2, 0)).unwrap();
+            assert_eq!(map.flow_direction(&field, (0, 0)), None);
+            assert!(map.flow_field((1, 0)).is_err());
+        }
+
         #[test]
         fn simple_line() {
             let map = Map::new(1, 5);
@@ -202,23 +699,172 @@ pub mod pathfinding_comp {
             assert_eq!(path, Ok(vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 4),]))
         }
         #[test]
-        fn empty_map_clockwise() {
-            let map = Map::new(5, 5);
-            let path = map.dijkstra((1, 1), (3, 3));
-            assert_eq!(path, Ok(vec![(1, 1), (1, 2), (1, 3), (2, 3), (3, 3),]))
+        fn dijkstra_as_short_as_astar() {
+            let mut map = Map::new(5, 5);
+            for y in 0..4 {
+                map.set_tile(&(2, y), TileType::Wall);
+            }
+            let path = map.dijkstra((0, 0), (4, 0)).unwrap();
+            assert_eq!(path.len(), map.astar((0, 0), (4, 0)).unwrap().len());
         }
-        #[test]
-        fn empty_map_counter_clockwise() {
-            let map = Map::new(5, 5);
-            let path = map.dijkstra((3, 3), (1, 1));
-            assert_eq!(path, Ok(vec![(3, 3), (3, 2), (3, 1), (2, 1), (1, 1),]))
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+
+    use super::pathfinding_comp::{Map, Pos, TileType};
+    use test::Bencher;
+
+    /// Map with vertical walls every 4 columns, each with a single gap alternating top and bottom.
+    fn serpentine_map(size: u32) -> Map {
+        let mut map = Map::new(size, size);
+        for x in (2..size as i32 - 1).step_by(4) {
+            let gap = if (x / 4) % 2 == 0 { size as i32 - 1 } else { 0 };
+            for y in 0..size as i32 {
+                if y != gap {
+                    map.set_tile(&(x, y), TileType::Wall);
+                }
+            }
         }
+        map
+    }
+
+    fn corners(size: u32) -> (Pos, Pos) {
+        ((0, 0), (size as i32 - 1, size as i32 - 1))
+    }
+
+    #[bench]
+    fn dijkstra_empty_20(b: &mut Bencher) {
+        let map = Map::new(20, 20);
+        let (start, end) = corners(20);
+        b.iter(|| map.dijkstra(start, end));
+    }
+    #[bench]
+    fn astar_empty_20(b: &mut Bencher) {
+        let map = Map::new(20, 20);
+        let (start, end) = corners(20);
+        b.iter(|| map.astar(start, end));
+    }
+    #[bench]
+    fn dijkstra_empty_100(b: &mut Bencher) {
+        let map = Map::new(100, 100);
+        let (start, end) = corners(100);
+        b.iter(|| map.dijkstra(start, end));
+    }
+    #[bench]
+    fn astar_empty_100(b: &mut Bencher) {
+        let map = Map::new(100, 100);
+        let (start, end) = corners(100);
+        b.iter(|| map.astar(start, end));
+    }
+    #[bench]
+    fn dijkstra_serpentine_100(b: &mut Bencher) {
+        let map = serpentine_map(100);
+        let (start, end) = corners(100);
+        b.iter(|| map.dijkstra(start, end));
+    }
+    #[bench]
+    fn astar_serpentine_100(b: &mut Bencher) {
+        let map = serpentine_map(100);
+        let (start, end) = corners(100);
+        b.iter(|| map.astar(start, end));
+    }
+    #[bench]
+    fn flow_field_serpentine_100(b: &mut Bencher) {
+        let map = serpentine_map(100);
+        let (_, end) = corners(100);
+        b.iter(|| map.flow_field(end));
     }
 }
 
 mod system {
     use super::pathfinding_comp::{Map, TileType};
     use super::*;
+    use crate::core_game::components::UnitSize;
+
+    /// Keeps paths of movers using pathfinding up to date.
+    ///
+    /// A path is computed again when the target moves to another tile or the mover is stuck.
+    pub(super) fn update_mover_paths(
+        map: Res<Map>,
+        world_map: Res<crate::core_game::map::Map>,
+        mut q_movers: Query<(&Mover, &mut MoverPath, &Transform, &UnitSize)>,
+    ) {
+        if !map.is_ready() {
+            return;
+        }
+        for (mover, mut path, transform, size) in q_movers.iter_mut() {
+            if !mover.uses_pathfinding() || mover.is_target_reached {
+                if path.target_tile.is_some() {
+                    *path = MoverPath::default();
+                }
+                continue;
+            }
+            let position = transform.translation;
+            let target = *mover.get_target_position();
+            if (position - path.progress_position).length() > STUCK_DISTANCE {
+                path.progress_position = position;
+                path.stuck_ticks = 0;
+            } else {
+                path.stuck_ticks += 1;
+            }
+            let target_tile = tile_at(&world_map, target);
+            if path.target_tile != Some(target_tile) || path.stuck_ticks >= STUCK_TICKS {
+                path.waypoints = find_world_waypoints(&map, &world_map, position, target, size.0);
+                path.target_tile = Some(target_tile);
+                path.progress_position = position;
+                path.stuck_ticks = 0;
+            }
+            // Skip waypoints as soon as the next one is in sight.
+            let clearance = size.0 / TILE_SIZE;
+            let from = world_map.tile_position_at(position.truncate());
+            while path.waypoints.len() > 1
+                && ((path.waypoints[0] - position).length() < STUCK_DISTANCE
+                    || map.line_of_sight(
+                        from,
+                        world_map.tile_position_at(path.waypoints[1].truncate()),
+                        clearance,
+                    ))
+            {
+                path.waypoints.remove(0);
+            }
+        }
+    }
+
+    pub(super) fn update_flow_fields(
+        map: Res<Map>,
+        world_map: Res<crate::core_game::map::Map>,
+        mut flow_fields: ResMut<FlowFields>,
+        q_movers: Query<&Mover>,
+    ) {
+        if map.is_changed() {
+            flow_fields.fields.clear();
+        }
+        if !map.is_ready() {
+            return;
+        }
+        let mut destinations: Vec<Pos> = q_movers
+            .iter()
+            .filter(|mover| !mover.is_target_reached)
+            .filter_map(|mover| mover.get_flow_field_destination())
+            .map(|destination| tile_at(&world_map, *destination))
+            .collect();
+        destinations.sort_unstable();
+        destinations.dedup();
+        flow_fields
+            .fields
+            .retain(|destination, _| destinations.binary_search(destination).is_ok());
+        for destination in destinations {
+            if flow_fields.fields.contains_key(&destination) {
+                continue;
+            }
+            if let Ok(field) = map.flow_field(destination) {
+                flow_fields.fields.insert(destination, field);
+            }
+        }
+    }
 
     pub(super) fn setup(mut commands: Commands, map: Res<crate::core_game::map::Map>) {
         let pathfinding_map = Map {
@@ -0,0 +1,427 @@
+//! Reciprocal collision avoidance between units (ORCA), adapted from the RVO2 library.
+//!
+//! Each unit picks the velocity closest to its desired one which doesn't collide
+//! with its neighbours within `TIME_HORIZON`, assuming they do their half of the work.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::core_game::{
+    components::UnitSize, orders::orders_comp::Speed, simulation::SIMULATION_STEP,
+    spatial::SpatialIndex,
+};
+
+/// How far ahead in seconds collisions are avoided.
+const TIME_HORIZON: f32 = 1f32;
+const MAX_NEIGHBORS: usize = 10;
+const EPSILON: f32 = 0.00001;
+
+/// Velocity a unit wants to move at, adjusted by avoidance before being applied to its `Velocity`.
+#[derive(Component, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct DesiredVelocity {
+    pub linvel: Vec2,
+    /// Doesn't move out of the way of others, e.g. while attacking.
+    pub hold: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Agent {
+    position: Vec2,
+    velocity: Vec2,
+    desired_velocity: Vec2,
+    radius: f32,
+    max_speed: f32,
+    hold: bool,
+}
+
+/// Half-plane of allowed velocities, on the left of `direction`.
+#[derive(Clone, Debug)]
+struct Line {
+    point: Vec2,
+    direction: Vec2,
+}
+
+pub fn avoidance_update(
+    spatial_index: Res<SpatialIndex>,
+    mut query: Query<(
+        Entity,
+        &Transform,
+        &UnitSize,
+        &Speed,
+        &DesiredVelocity,
+        &mut Velocity,
+    )>,
+) {
+    let agents: Vec<Agent> = query
+        .iter()
+        .map(|(_, transform, size, speed, desired, velocity)| Agent {
+            position: transform.translation.truncate(),
+            velocity: velocity.linvel,
+            desired_velocity: desired.linvel,
+            radius: size.0,
+            max_speed: speed.speed,
+            hold: desired.hold,
+        })
+        .collect();
+    let indices: HashMap<Entity, usize> = query
+        .iter()
+        .enumerate()
+        .map(|(index, (entity, ..))| (entity, index))
+        .collect();
+    let largest = largest_agent(&agents);
+    let new_velocities: Vec<Vec2> = (0..agents.len())
+        .map(|index| {
+            let candidates = candidates(&spatial_index, &agents, &indices, largest, index);
+            compute_new_velocity(&agents, index, candidates)
+        })
+        .collect();
+    for ((.., mut velocity), new_velocity) in query.iter_mut().zip(new_velocities) {
+        velocity.linvel = new_velocity;
+    }
+}
+
+/// Largest radius and max speed among `agents`.
+fn largest_agent(agents: &[Agent]) -> (f32, f32) {
+    agents.iter().fold((0f32, 0f32), |max, agent| {
+        (max.0.max(agent.radius), max.1.max(agent.max_speed))
+    })
+}
+
+/// Agents the spatial index finds within reach of agent `index` before `TIME_HORIZON`, by index.
+///
+/// The index was built at the beginning of the tick, so the search accounts for a step of movement.
+fn candidates(
+    spatial_index: &SpatialIndex,
+    agents: &[Agent],
+    indices: &HashMap<Entity, usize>,
+    (max_radius, max_speed): (f32, f32),
+    index: usize,
+) -> Vec<usize> {
+    let agent = &agents[index];
+    let range = agent.radius
+        + max_radius
+        + (agent.max_speed + max_speed) * TIME_HORIZON
+        + 2f32 * max_speed * SIMULATION_STEP;
+    let mut candidates: Vec<usize> = spatial_index
+        .in_circle(agent.position, range)
+        .filter_map(|entry| indices.get(&entry.entity).copied())
+        .collect();
+    // Same order as a search through all agents, whatever the cells.
+    candidates.sort_unstable();
+    candidates
+}
+
+/// Closest agents among `candidates` within reach of agent `index` before `TIME_HORIZON`.
+fn neighbors(
+    agents: &[Agent],
+    index: usize,
+    candidates: impl IntoIterator<Item = usize>,
+) -> Vec<&Agent> {
+    let agent = &agents[index];
+    let mut neighbors: Vec<(f32, &Agent)> = candidates
+        .into_iter()
+        .filter(|other_index| *other_index != index)
+        .map(|other_index| &agents[other_index])
+        .filter_map(|other| {
+            let range =
+                agent.radius + other.radius + (agent.max_speed + other.max_speed) * TIME_HORIZON;
+            let distance_squared = agent.position.distance_squared(other.position);
+            (distance_squared < range * range).then(|| (distance_squared, other))
+        })
+        .collect();
+    neighbors.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    neighbors
+        .into_iter()
+        .take(MAX_NEIGHBORS)
+        .map(|(_, other)| other)
+        .collect()
+}
+
+fn compute_new_velocity(
+    agents: &[Agent],
+    index: usize,
+    candidates: impl IntoIterator<Item = usize>,
+) -> Vec2 {
+    let agent = &agents[index];
+    if agent.hold || agent.max_speed <= 0f32 {
+        return agent.desired_velocity;
+    }
+    let inv_time_horizon = 1f32 / TIME_HORIZON;
+    let lines: Vec<Line> = neighbors(agents, index, candidates)
+        .into_iter()
+        .map(|other| {
+            let relative_position = other.position - agent.position;
+            let relative_velocity = agent.velocity - other.velocity;
+            let distance_squared = relative_position.length_squared();
+            let combined_radius = agent.radius + other.radius;
+            let combined_radius_squared = combined_radius * combined_radius;
+
+            let (direction, u) = if distance_squared > combined_radius_squared {
+                // No collision yet.
+                let w = relative_velocity - inv_time_horizon * relative_position;
+                let w_length_squared = w.length_squared();
+                let dot_product = w.dot(relative_position);
+                if dot_product < 0f32
+                    && dot_product * dot_product > combined_radius_squared * w_length_squared
+                {
+                    // Project on the cut-off circle.
+                    let w_length = w_length_squared.sqrt();
+                    let unit_w = w / w_length;
+                    (
+                        Vec2::new(unit_w.y, -unit_w.x),
+                        (combined_radius * inv_time_horizon - w_length) * unit_w,
+                    )
+                } else {
+                    // Project on the closest leg.
+                    let leg = (distance_squared - combined_radius_squared).sqrt();
+                    let direction = if relative_position.perp_dot(w) > 0f32 {
+                        Vec2::new(
+                            relative_position.x * leg - relative_position.y * combined_radius,
+                            relative_position.x * combined_radius + relative_position.y * leg,
+                        ) / distance_squared
+                    } else {
+                        -Vec2::new(
+                            relative_position.x * leg + relative_position.y * combined_radius,
+                            -relative_position.x * combined_radius + relative_position.y * leg,
+                        ) / distance_squared
+                    };
+                    (
+                        direction,
+                        relative_velocity.dot(direction) * direction - relative_velocity,
+                    )
+                }
+            } else {
+                // Already colliding, get apart within a single step.
+                let inv_time_step = 1f32 / SIMULATION_STEP;
+                let w = relative_velocity - inv_time_step * relative_position;
+                let w_length = w.length().max(EPSILON);
+                let unit_w = w / w_length;
+                (
+                    Vec2::new(unit_w.y, -unit_w.x),
+                    (combined_radius * inv_time_step - w_length) * unit_w,
+                )
+            };
+            // Units holding their position won't help, so take the whole avoidance.
+            let responsibility = if other.hold { 1f32 } else { 0.5f32 };
+            Line {
+                point: agent.velocity + responsibility * u,
+                direction,
+            }
+        })
+        .collect();
+
+    let mut new_velocity = Vec2::ZERO;
+    let line_fail = linear_program_2(
+        &lines,
+        agent.max_speed,
+        agent.desired_velocity,
+        false,
+        &mut new_velocity,
+    );
+    if line_fail < lines.len() {
+        linear_program_3(&lines, line_fail, agent.max_speed, &mut new_velocity);
+    }
+    new_velocity
+}
+
+/// Optimizes on the line `line_no`, constrained by the previous lines and a `radius` circle.
+fn linear_program_1(
+    lines: &[Line],
+    line_no: usize,
+    radius: f32,
+    opt_velocity: Vec2,
+    direction_opt: bool,
+    result: &mut Vec2,
+) -> bool {
+    let line = &lines[line_no];
+    let dot_product = line.point.dot(line.direction);
+    let discriminant = dot_product * dot_product + radius * radius - line.point.length_squared();
+    if discriminant < 0f32 {
+        // The maximum speed circle fully invalidates this line.
+        return false;
+    }
+    let sqrt_discriminant = discriminant.sqrt();
+    let mut t_left = -dot_product - sqrt_discriminant;
+    let mut t_right = -dot_product + sqrt_discriminant;
+
+    for other in lines[..line_no].iter() {
+        let denominator = line.direction.perp_dot(other.direction);
+        let numerator = other.direction.perp_dot(line.point - other.point);
+        if denominator.abs() <= EPSILON {
+            // Parallel lines.
+            if numerator < 0f32 {
+                return false;
+            }
+            continue;
+        }
+        let t = numerator / denominator;
+        if denominator >= 0f32 {
+            t_right = t_right.min(t);
+        } else {
+            t_left = t_left.max(t);
+        }
+        if t_left > t_right {
+            return false;
+        }
+    }
+
+    *result = if direction_opt {
+        if opt_velocity.dot(line.direction) > 0f32 {
+            line.point + t_right * line.direction
+        } else {
+            line.point + t_left * line.direction
+        }
+    } else {
+        let t = line.direction.dot(opt_velocity - line.point);
+        line.point + t.clamp(t_left, t_right) * line.direction
+    };
+    true
+}
+
+/// Returns the index of the first line which couldn't be satisfied, `lines.len()` on success.
+fn linear_program_2(
+    lines: &[Line],
+    radius: f32,
+    opt_velocity: Vec2,
+    direction_opt: bool,
+    result: &mut Vec2,
+) -> usize {
+    *result = if direction_opt {
+        // `opt_velocity` is a unit direction here.
+        opt_velocity * radius
+    } else if opt_velocity.length_squared() > radius * radius {
+        opt_velocity.normalize() * radius
+    } else {
+        opt_velocity
+    };
+    for (i, line) in lines.iter().enumerate() {
+        if line.direction.perp_dot(line.point - *result) > 0f32 {
+            let previous_result = *result;
+            if !linear_program_1(lines, i, radius, opt_velocity, direction_opt, result) {
+                *result = previous_result;
+                return i;
+            }
+        }
+    }
+    lines.len()
+}
+
+/// When constraints can't all be satisfied, minimizes the maximum penetration into them.
+fn linear_program_3(lines: &[Line], begin_line: usize, radius: f32, result: &mut Vec2) {
+    let mut distance = 0f32;
+    for i in begin_line..lines.len() {
+        if lines[i].direction.perp_dot(lines[i].point - *result) <= distance {
+            continue;
+        }
+        let mut projected_lines = vec![];
+        for j in 0..i {
+            let determinant = lines[i].direction.perp_dot(lines[j].direction);
+            let point = if determinant.abs() <= EPSILON {
+                if lines[i].direction.dot(lines[j].direction) > 0f32 {
+                    // Same direction.
+                    continue;
+                }
+                0.5f32 * (lines[i].point + lines[j].point)
+            } else {
+                lines[i].point
+                    + (lines[j].direction.perp_dot(lines[i].point - lines[j].point) / determinant)
+                        * lines[i].direction
+            };
+            projected_lines.push(Line {
+                point,
+                direction: (lines[j].direction - lines[i].direction).normalize_or_zero(),
+            });
+        }
+        let previous_result = *result;
+        let direction = Vec2::new(-lines[i].direction.y, lines[i].direction.x);
+        if linear_program_2(&projected_lines, radius, direction, true, result)
+            < projected_lines.len()
+        {
+            // Should not happen, the result is already in the feasible region of this program.
+            *result = previous_result;
+        }
+        distance = lines[i].direction.perp_dot(lines[i].point - *result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core_game::spatial::SpatialEntry;
+
+    fn agent(position: Vec2, desired_velocity: Vec2) -> Agent {
+        Agent {
+            position,
+            velocity: desired_velocity,
+            desired_velocity,
+            radius: 10f32,
+            max_speed: 100f32,
+            hold: false,
+        }
+    }
+
+    #[test]
+    fn alone_keeps_desired_velocity() {
+        let agents = vec![agent(Vec2::ZERO, Vec2::new(50f32, 0f32))];
+        assert_eq!(
+            compute_new_velocity(&agents, 0, 0..agents.len()),
+            Vec2::new(50f32, 0f32)
+        );
+    }
+    #[test]
+    fn head_on_agents_deviate_to_opposite_sides() {
+        let agents = vec![
+            agent(Vec2::new(-50f32, 0f32), Vec2::new(100f32, 0f32)),
+            agent(Vec2::new(50f32, 0f32), Vec2::new(-100f32, 0f32)),
+        ];
+        let a = compute_new_velocity(&agents, 0, 0..agents.len());
+        let b = compute_new_velocity(&agents, 1, 0..agents.len());
+        assert!(a.y.abs() > 1f32);
+        assert!(a.y * b.y < 0f32);
+        assert!(a.length() <= 100f32 + EPSILON && b.length() <= 100f32 + EPSILON);
+    }
+    #[test]
+    fn holding_agent_is_avoided() {
+        let mut obstacle = agent(Vec2::new(50f32, 0f32), Vec2::ZERO);
+        obstacle.hold = true;
+        let agents = vec![
+            agent(Vec2::new(-50f32, 0f32), Vec2::new(100f32, 0f32)),
+            obstacle,
+        ];
+        assert_eq!(
+            compute_new_velocity(&agents, 1, 0..agents.len()),
+            Vec2::ZERO
+        );
+        assert!(compute_new_velocity(&agents, 0, 0..agents.len()).y.abs() > 1f32);
+    }
+    #[test]
+    fn spatial_index_finds_the_same_neighbors() {
+        let agents: Vec<Agent> = (0..30)
+            .map(|i| {
+                let position = Vec2::new((i % 6) as f32 * 25f32, (i / 6) as f32 * 40f32);
+                agent(position, Vec2::new(100f32 - i as f32 * 7f32, 30f32))
+            })
+            .collect();
+        let mut spatial_index = SpatialIndex::new(32f32);
+        spatial_index.rebuild(agents.iter().enumerate().map(|(i, agent)| SpatialEntry {
+            entity: Entity::from_raw(i as u32),
+            position: agent.position,
+            radius: agent.radius,
+            team: 0,
+        }));
+        let indices: HashMap<Entity, usize> = (0..agents.len())
+            .map(|i| (Entity::from_raw(i as u32), i))
+            .collect();
+        let largest = largest_agent(&agents);
+        for index in 0..agents.len() {
+            let candidates = candidates(&spatial_index, &agents, &indices, largest, index);
+            assert_eq!(
+                compute_new_velocity(&agents, index, candidates),
+                compute_new_velocity(&agents, index, 0..agents.len())
+            );
+        }
+    }
+}
@@ -1,21 +1,73 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use self::physics_syst::*;
+use self::{avoidance::avoidance_update, physics_syst::*};
+use super::simulation::{SimulationLabel, SimulationSchedule, SimulationStage};
 
+pub mod avoidance;
 mod physics_syst;
 
+pub(crate) use physics_syst::unit_physics;
+
 pub const PHYSICS_PIXEL_PER_METER: f32 = 20f32;
 
 pub struct PhysicsPlugin;
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(
-            PHYSICS_PIXEL_PER_METER,
-        ))
+        // Rapier systems are stepped by the simulation schedule rather than each frame.
+        app.add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(PHYSICS_PIXEL_PER_METER)
+                .with_default_system_setup(false),
+        )
         .add_startup_system(physics_setup)
-        .add_system_to_stage(CoreStage::PreUpdate, mover_update)
-        .add_system_to_stage(CoreStage::PostUpdate, physics_init);
+        .stage(SimulationSchedule, |schedule: &mut Schedule| {
+            schedule
+                .add_system_to_stage(
+                    SimulationStage::PreUpdate,
+                    mover_update
+                        .label(SimulationLabel::Movers)
+                        .after(SimulationLabel::OrderRequests),
+                )
+                .add_system_to_stage(
+                    SimulationStage::Update,
+                    avoidance_update
+                        .label(SimulationLabel::Avoidance)
+                        .after(SimulationLabel::Projectiles),
+                )
+                .add_system_to_stage(
+                    SimulationStage::PostUpdate,
+                    physics_init
+                        .label(SimulationLabel::PhysicsInit)
+                        .after(SimulationLabel::Ai),
+                )
+                .add_stage_after(
+                    SimulationStage::PostUpdate,
+                    PhysicsStages::SyncBackend,
+                    SystemStage::single_threaded().with_system_set(
+                        RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::SyncBackend),
+                    ),
+                )
+                .add_stage_after(
+                    PhysicsStages::SyncBackend,
+                    PhysicsStages::StepSimulation,
+                    SystemStage::single_threaded().with_system_set(
+                        RapierPhysicsPlugin::<NoUserData>::get_systems(
+                            PhysicsStages::StepSimulation,
+                        ),
+                    ),
+                )
+                .add_stage_after(
+                    PhysicsStages::StepSimulation,
+                    PhysicsStages::Writeback,
+                    SystemStage::single_threaded().with_system_set(
+                        RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::Writeback),
+                    ),
+                )
+        })
+        .add_system_set_to_stage(
+            CoreStage::Last,
+            RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::DetectDespawn),
+        );
     }
 }
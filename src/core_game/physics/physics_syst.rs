@@ -5,8 +5,15 @@ use bevy_rapier2d::{
     rapier::{dynamics::IntegrationParameters, math::Vector},
 };
 
-use super::PHYSICS_PIXEL_PER_METER;
-use crate::core_game::{components::*, orders::orders_comp::*};
+use super::{avoidance::DesiredVelocity, PHYSICS_PIXEL_PER_METER};
+use crate::core_game::{
+    abilities::{AbilityState, AbilityTarget},
+    components::*,
+    orders::orders_comp::*,
+    pathfinding::{pathfinding_comp, steering_target, FlowFields},
+    simulation::{SimulationTime, SIMULATION_STEP},
+    status_effects::{self, StatusEffects},
+};
 
 #[derive(Component)]
 pub struct PhysicsInitialized;
@@ -16,10 +23,27 @@ pub fn physics_setup(
     mut context: ResMut<RapierContext>,
 ) {
     configuration.gravity = Default::default();
+    // One rapier step per simulation tick, independent of the frame duration.
+    configuration.timestep_mode = TimestepMode::Fixed {
+        dt: SIMULATION_STEP,
+        substeps: 1,
+    };
 
     context.integration_parameters.erp = 0.8;
 }
 
+/// Rapier components of a unit moving at `velocity`.
+pub(crate) fn unit_physics(size: &UnitSize, velocity: Velocity) -> impl Bundle {
+    (
+        RigidBody::Dynamic,
+        velocity,
+        DesiredVelocity::default(),
+        Collider::ball(size.0),
+        LockedAxes::ROTATION_LOCKED,
+        PhysicsInitialized,
+    )
+}
+
 pub fn physics_init(
     mut commands: Commands,
     q: Query<(Entity, &UnitSize, &Transform), Without<PhysicsInitialized>>,
@@ -27,12 +51,11 @@ pub fn physics_init(
     for (e, size, transform) in q.iter() {
         commands
             .entity(e)
-            .insert(RigidBody::Dynamic)
-            .insert(Velocity::zero())
-            .insert(Collider::ball(size.0))
-            .insert(LockedAxes::ROTATION_LOCKED)
-            .insert(Transform::from_translation(transform.translation))
-            .insert(PhysicsInitialized);
+            .insert_bundle(unit_physics(size, Velocity::zero()))
+            .insert(
+                Transform::from_translation(transform.translation)
+                    .with_rotation(transform.rotation),
+            );
     }
 }
 
@@ -51,25 +74,54 @@ fn rotate_towards(current_rotation: Quat, direction: Vec3, max_rotation: f32) ->
 }
 
 pub fn mover_update(
-    time: Res<Time>,
+    time: Res<SimulationTime>,
+    map: Res<pathfinding_comp::Map>,
+    world_map: Res<crate::core_game::map::Map>,
+    flow_fields: Res<FlowFields>,
     mut query: Query<(
         Entity,
         &mut Mover,
         &Speed,
-        &mut Velocity,
-        Option<&MeleeAbilityState>,
+        &mut DesiredVelocity,
+        &UnitSize,
+        Option<&MoverPath>,
+        Option<&AbilityState>,
         Option<&RotateBeforeMove>,
+        Option<&StatusEffects>,
     )>,
     mut q_target: Query<&mut Transform>,
 ) {
-    for (e, mut mover, speed, mut velocity, melee_state, rotate_before_move) in query.iter_mut() {
-        if let Some(MeleeAbilityState::WillAttack(will_attack)) = melee_state {
-            velocity.linvel = Vec2::new(0.0, 0.0);
+    for (
+        e,
+        mut mover,
+        speed,
+        mut desired,
+        size,
+        path,
+        ability_state,
+        rotate_before_move,
+        status_effects,
+    ) in query.iter_mut()
+    {
+        desired.hold = false;
+        if status_effects::is_stunned(status_effects) {
+            desired.linvel = Vec2::new(0.0, 0.0);
+            desired.hold = true;
+            continue;
+        }
+        if let Some(casting) = ability_state.and_then(|state| state.get_casting()) {
+            desired.linvel = Vec2::new(0.0, 0.0);
+            desired.hold = true;
             if let Some(rotation) = rotate_before_move {
-                if let Ok(target_position) =
-                    q_target.get_component::<Transform>(will_attack.target_entity)
-                {
-                    let target_position = target_position.translation;
+                let target_position = match casting.target {
+                    AbilityTarget::Unit(target) => q_target
+                        .get_component::<Transform>(target)
+                        .ok()
+                        .map(|transform| transform.translation),
+                    AbilityTarget::Point(point) => Some(point),
+                    AbilityTarget::Myself => None,
+                };
+                if let Some(target_position) = target_position {
                     if let Ok(mut transform) = q_target.get_component_mut::<Transform>(e) {
                         let offset = target_position - transform.translation;
                         if let Some(new_rotation) = rotate_towards(
@@ -87,16 +139,25 @@ pub fn mover_update(
         if mover.is_target_reached {
             //continue;
         }
-        let target = mover.get_target_position();
+        let target = *mover.get_target_position();
 
         if let Ok(mut transform) = q_target.get_component_mut::<Transform>(e) {
-            let mut offset = *target - transform.translation;
-            let offset_distance = offset.length();
-            if offset_distance < 2.0 {
+            if (target - transform.translation).length() < 2.0 {
                 mover.is_target_reached = true;
-                velocity.linvel = Default::default();
+                desired.linvel = Default::default();
                 continue;
             }
+            let steer_to = steering_target(
+                &map,
+                &world_map,
+                &flow_fields,
+                transform.translation,
+                &mover,
+                path,
+                size.0,
+            );
+            let mut offset = steer_to - transform.translation;
+            let offset_distance = offset.length();
 
             if let Some(rotation) = rotate_before_move {
                 if let Some(new_rotation) = rotate_towards(
@@ -105,27 +166,29 @@ pub fn mover_update(
                     rotation.rotation_speed * time.delta_seconds(),
                 ) {
                     transform.rotation = new_rotation;
-                    velocity.linvel = Default::default();
+                    desired.linvel = Default::default();
                     continue;
                 }
             }
-            if speed.speed == 0.0 {
+            let speed =
+                mover.limit_speed(speed.speed * status_effects::speed_multiplier(status_effects));
+            if speed == 0.0 {
                 continue;
             }
             offset = offset.normalize();
-            let distance_to_move = speed.speed * time.delta_seconds_f64() as f32;
+            let distance_to_move = speed * time.delta_seconds();
             offset *= f32::min(distance_to_move, offset_distance);
 
             // If no physics:
             // let new_position: Isometry<f32> = Isometry::new(bevy_rapier2d::na::Vector2::new(new_position.x,new_position.y), Default::default());
             // transform.translation = new_position;
             // Else:
-            let mut speed_to_apply = speed.speed;
-            let distance_in_a_frame = speed.speed * 1.0 / 60.0;
+            let mut speed_to_apply = speed;
+            let distance_in_a_frame = speed * SIMULATION_STEP;
             if offset_distance < distance_in_a_frame {
-                //speed_to_apply = offset.length() * 1.0 / 60.0;
+                //speed_to_apply = offset.length() * SIMULATION_STEP;
             }
-            velocity.linvel = Vec2::new(offset.x, offset.y).normalize() * speed_to_apply;
+            desired.linvel = Vec2::new(offset.x, offset.y).normalize() * speed_to_apply;
         }
 
         // TO CHECK: old code had to wake up that
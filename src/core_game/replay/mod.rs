@@ -0,0 +1,285 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use bevy::{app::AppExit, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    components::UnitId,
+    map::MapSettings,
+    orders::orders_comp::{OrderRequest, OrderRequests},
+    scenario::ScenarioName,
+    simulation::{SimulationLabel, SimulationSchedule, SimulationStage, SimulationTime},
+    units::{DefinitionChanges, UnitDefinition, UnitDefinitions},
+};
+
+/// Inserted before adding `CorePlugin` to record or play a replay.
+pub enum ReplayMode {
+    Record(PathBuf),
+    Play(PathBuf),
+}
+
+/// Everything needed to reproduce a match: the scenario, the map settings,
+/// unit definitions when it started, orders issued and unit definitions hot reloaded at each tick.
+///
+/// Entities are stored as their `UnitId`.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Replay {
+    pub scenario: String,
+    pub map: MapSettings,
+    pub entries: Vec<ReplayEntry>,
+    #[serde(default)]
+    pub definitions: Vec<UnitDefinition>,
+    #[serde(default)]
+    pub definition_changes: Vec<DefinitionChange>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplayEntry {
+    pub tick: u64,
+    pub request: OrderRequest,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DefinitionChange {
+    pub tick: u64,
+    pub definition: UnitDefinition,
+}
+
+impl Replay {
+    pub fn load(path: &PathBuf) -> Result<Replay, ()> {
+        let content = fs::read_to_string(path).map_err(|_| ())?;
+        ron::from_str(&content).map_err(|_| ())
+    }
+    pub fn save(&self, path: &PathBuf) -> Result<(), ()> {
+        let content =
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new()).map_err(|_| ())?;
+        fs::write(path, content).map_err(|_| ())
+    }
+}
+
+pub struct ReplayRecorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+pub struct ReplayPlayer {
+    replay: Replay,
+    next_entry: usize,
+    next_definition_change: usize,
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match app.world.remove_resource::<ReplayMode>() {
+            Some(ReplayMode::Record(path)) => {
+                let mut definitions: Vec<UnitDefinition> = app
+                    .world
+                    .resource::<UnitDefinitions>()
+                    .iter()
+                    .cloned()
+                    .collect();
+                definitions.sort_by(|a, b| a.name.cmp(&b.name));
+                app.insert_resource(ReplayRecorder {
+                    path,
+                    replay: Replay {
+                        definitions,
+                        ..default()
+                    },
+                })
+                .stage(SimulationSchedule, |schedule: &mut Schedule| {
+                    schedule.add_system_to_stage(
+                        SimulationStage::First,
+                        record_inputs
+                            .label(SimulationLabel::Inputs)
+                            .before(SimulationLabel::Definitions),
+                    )
+                })
+                .add_system_to_stage(CoreStage::Last, save_replay_on_exit);
+            }
+            Some(ReplayMode::Play(path)) => {
+                let replay = Replay::load(&path).expect("Could not load replay file.");
+                // Units are spawned as recorded, even if their files changed since.
+                let mut definitions = app.world.resource_mut::<UnitDefinitions>();
+                for definition in replay.definitions.iter() {
+                    definitions.insert(definition.clone());
+                }
+                app.insert_resource(ScenarioName(replay.scenario.clone()))
+                    .insert_resource(replay.map.clone())
+                    .insert_resource(ReplayPlayer {
+                        replay,
+                        next_entry: 0,
+                        next_definition_change: 0,
+                    })
+                    .stage(SimulationSchedule, |schedule: &mut Schedule| {
+                        schedule.add_system_to_stage(
+                            SimulationStage::First,
+                            play_inputs
+                                .label(SimulationLabel::Inputs)
+                                .before(SimulationLabel::Definitions),
+                        )
+                    });
+            }
+            None => {}
+        }
+    }
+}
+
+fn record_inputs(
+    time: Res<SimulationTime>,
+    order_requests: Res<OrderRequests>,
+    definition_changes: Res<DefinitionChanges>,
+    mut recorder: ResMut<ReplayRecorder>,
+    q_units: Query<&UnitId>,
+) {
+    for definition in definition_changes.definitions.iter() {
+        recorder.replay.definition_changes.push(DefinitionChange {
+            tick: time.tick(),
+            definition: definition.clone(),
+        });
+    }
+    let to_unit_id = |entity: Entity| -> Option<Entity> {
+        q_units.get(entity).ok().map(|id| Entity::from_raw(id.0))
+    };
+    for request in order_requests.get_requests() {
+        let mut request = request.clone();
+        if request.map_entities(&to_unit_id).is_err() {
+            continue;
+        }
+        recorder.replay.entries.push(ReplayEntry {
+            tick: time.tick(),
+            request,
+        });
+    }
+}
+
+fn play_inputs(
+    time: Res<SimulationTime>,
+    mut order_requests: ResMut<OrderRequests>,
+    mut definition_changes: ResMut<DefinitionChanges>,
+    mut player: ResMut<ReplayPlayer>,
+    q_units: Query<(Entity, &UnitId)>,
+) {
+    // Orders and definition changes only come from the replay while it plays.
+    order_requests.requests.clear();
+    definition_changes.definitions.clear();
+    while let Some(change) = player
+        .replay
+        .definition_changes
+        .get(player.next_definition_change)
+    {
+        if change.tick > time.tick() {
+            break;
+        }
+        definition_changes
+            .definitions
+            .push(change.definition.clone());
+        player.next_definition_change += 1;
+    }
+
+    let units: HashMap<u32, Entity> = q_units.iter().map(|(e, id)| (id.0, e)).collect();
+    let from_unit_id = |entity: Entity| -> Option<Entity> { units.get(&entity.id()).copied() };
+    while let Some(entry) = player.replay.entries.get(player.next_entry) {
+        if entry.tick > time.tick() {
+            break;
+        }
+        let mut request = entry.request.clone();
+        player.next_entry += 1;
+        if request.map_entities(&from_unit_id).is_err() {
+            continue;
+        }
+        order_requests.requests.push(request);
+    }
+}
+
+fn save_replay_on_exit(
+    mut app_exit: EventReader<AppExit>,
+    scenario_name: Res<ScenarioName>,
+    map_settings: Res<MapSettings>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    if app_exit.iter().next().is_none() {
+        return;
+    }
+    recorder.replay.scenario = scenario_name.0.clone();
+    recorder.replay.map = map_settings.clone();
+    match recorder.replay.save(&recorder.path) {
+        Ok(()) => info!("Replay saved to {:?}", recorder.path),
+        Err(()) => error!("Could not save replay to {:?}", recorder.path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core_game::{
+        components::RenderSprite,
+        orders::orders_comp::Speed,
+        test::{headless_app, order_player_units, unit_states, TICKS},
+    };
+
+    #[test]
+    fn replay_reproduces_recorded_match() {
+        let path = std::env::temp_dir().join("rtas_test.replay.ron");
+        let mut recording = headless_app(|app| {
+            app.insert_resource(ReplayMode::Record(path.clone()));
+        });
+        order_player_units(&mut recording);
+        for _ in 0..TICKS {
+            recording.update();
+        }
+        let recorded = unit_states(&mut recording);
+        // As `save_replay_on_exit` does.
+        let scenario = recording.world.resource::<ScenarioName>().0.clone();
+        let map = recording.world.resource::<MapSettings>().clone();
+        let mut recorder = recording.world.resource_mut::<ReplayRecorder>();
+        assert!(!recorder.replay.entries.is_empty());
+        recorder.replay.scenario = scenario;
+        recorder.replay.map = map;
+        recorder.replay.save(&path).unwrap();
+
+        let mut playing = headless_app(|app| {
+            app.insert_resource(ReplayMode::Play(path.clone()));
+        });
+        for _ in 0..TICKS {
+            playing.update();
+        }
+        assert_eq!(recorded, unit_states(&mut playing));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replay_plays_with_recorded_definitions() {
+        let path = std::env::temp_dir().join("rtas_test_definitions.replay.ron");
+        let mut recording = headless_app(|app| {
+            app.insert_resource(ReplayMode::Record(path.clone()));
+        });
+        let scenario = recording.world.resource::<ScenarioName>().0.clone();
+        let map = recording.world.resource::<MapSettings>().clone();
+        let mut recorder = recording.world.resource_mut::<ReplayRecorder>();
+        assert!(!recorder.replay.definitions.is_empty());
+        // As if the files changed between the recording and its playback.
+        let definition = &mut recorder.replay.definitions[0];
+        definition.speed = 1f32;
+        let name = definition.name.clone();
+        recorder.replay.scenario = scenario;
+        recorder.replay.map = map;
+        recorder.replay.save(&path).unwrap();
+
+        let mut playing = headless_app(|app| {
+            app.insert_resource(ReplayMode::Play(path.clone()));
+        });
+        let definitions = playing.world.resource::<UnitDefinitions>();
+        assert_eq!(definitions.get(&name).unwrap().speed, 1f32);
+        let speeds: Vec<f32> = playing
+            .world
+            .query::<(&RenderSprite, &Speed)>()
+            .iter(&playing.world)
+            .filter(|(sprite, _)| sprite.0 == name)
+            .map(|(_, speed)| speed.speed)
+            .collect();
+        assert!(speeds.iter().all(|speed| *speed == 1f32));
+        let _ = fs::remove_file(&path);
+    }
+}
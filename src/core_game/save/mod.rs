@@ -0,0 +1,509 @@
+use std::{collections::HashMap, fmt, fs, io, path::PathBuf};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::Velocity;
+use mapgen::{geometry::Point, TileType};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    abilities::{Abilities, AbilityState, Mana},
+    components::*,
+    damage::{DamageOverTime, Defense},
+    map::{spawn_map, Map, MapSettings},
+    orders::orders_comp::*,
+    physics::{avoidance::DesiredVelocity, unit_physics},
+    scenario::Teams,
+    simulation::SimulationTime,
+    status_effects::StatusEffects,
+    vision::{Sight, Vision},
+};
+
+/// Inserted before adding `CorePlugin` to start from a saved game instead of a new one.
+pub struct LoadGame(pub PathBuf);
+
+/// Set `path` to save the game at the end of the current frame.
+#[derive(Default)]
+pub struct SaveGameRequest {
+    pub path: Option<PathBuf>,
+}
+
+/// Full state of the core simulation.
+///
+/// Entities are stored as their `UnitId`.
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    pub map_settings: MapSettings,
+    pub teams: Teams,
+    pub tick: u64,
+    pub map: SavedMap,
+    pub next_unit_id: u32,
+    pub units: Vec<SavedUnit>,
+    #[serde(default)]
+    pub projectiles: Vec<SavedProjectile>,
+    /// Orders issued but not applied yet.
+    #[serde(default)]
+    pub order_requests: Vec<OrderRequest>,
+    /// Tiles explored by each team.
+    #[serde(default)]
+    pub explored: Vec<(usize, Vec<bool>)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedProjectile {
+    pub translation: Vec3,
+    pub projectile: Projectile,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedMap {
+    pub width: usize,
+    pub height: usize,
+    pub walkable: Vec<bool>,
+    pub starting_point: Option<(usize, usize)>,
+    pub exit_point: Option<(usize, usize)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedUnit {
+    pub id: UnitId,
+    pub render_sprite: RenderSprite,
+    pub size: UnitSize,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    #[serde(default)]
+    pub linear_velocity: Vec2,
+    #[serde(default)]
+    pub desired_velocity: DesiredVelocity,
+    pub team: Team,
+    pub speed: Speed,
+    pub rotate_before_move: RotateBeforeMove,
+    pub mover: Mover,
+    #[serde(default)]
+    pub mover_path: MoverPath,
+    pub orders: Orders,
+    pub ai_unit: AIUnit,
+    pub seek_enemy_range: SeekEnemyRange,
+    #[serde(default)]
+    pub sight: Sight,
+    pub abilities: Abilities,
+    pub ability_state: AbilityState,
+    #[serde(default)]
+    pub mana: Option<Mana>,
+    pub offensive_stats: OffensiveStats,
+    pub health: Health,
+    #[serde(default)]
+    pub defense: Defense,
+    pub suffer_damage: SufferDamage,
+    #[serde(default)]
+    pub damage_over_time: DamageOverTime,
+    #[serde(default)]
+    pub status_effects: StatusEffects,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, ron::Error),
+    Serialize(PathBuf, ron::Error),
+    Write(PathBuf, io::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Read(path, error) | SaveError::Write(path, error) => {
+                write!(f, "{:?}: {}", path, error)
+            }
+            SaveError::Parse(path, error) | SaveError::Serialize(path, error) => {
+                write!(f, "{:?}: {}", path, error)
+            }
+        }
+    }
+}
+
+impl SaveGame {
+    pub fn load(path: &PathBuf) -> Result<SaveGame, SaveError> {
+        let content =
+            fs::read_to_string(path).map_err(|error| SaveError::Read(path.clone(), error))?;
+        ron::from_str(&content).map_err(|error| SaveError::Parse(path.clone(), error))
+    }
+    pub fn save(&self, path: &PathBuf) -> Result<(), SaveError> {
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .map_err(|error| SaveError::Serialize(path.clone(), error))?;
+        fs::write(path, content).map_err(|error| SaveError::Write(path.clone(), error))
+    }
+}
+
+impl SavedUnit {
+    /// Remaps every entity this unit refers to.
+    ///
+    /// References which can't be mapped (e.g. a dead target) reset the unit to its idle state.
+    fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) {
+        if self.orders.map_entities(mapper).is_err() {
+            self.orders = Orders::default();
+            self.mover = Mover::new(self.translation);
+            self.mover_path = MoverPath::default();
+        }
+        if self.ai_unit.map_entities(mapper).is_err() {
+            self.ai_unit = AIUnit::SeekEnemy;
+        }
+        if self.ability_state.map_entities(mapper).is_err() {
+            self.ability_state.reset_casting();
+        }
+        self.suffer_damage.map_entities(mapper);
+        self.damage_over_time.map_entities(mapper);
+        self.status_effects.map_entities(mapper);
+    }
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SaveGameRequest>()
+            .add_system_to_stage(CoreStage::Last, save_game_system);
+        if let Some(LoadGame(path)) = app.world.remove_resource::<LoadGame>() {
+            let save = match SaveGame::load(&path) {
+                Ok(save) => save,
+                Err(error) => {
+                    // A new game starts from the scenario instead.
+                    error!("Could not load saved game {}", error);
+                    return;
+                }
+            };
+            app.insert_resource(save.map_settings.clone())
+                .insert_resource(save.teams.clone())
+                .insert_resource(save)
+                .add_startup_system_to_stage(StartupStage::PreStartup, load_map)
+                .add_startup_system_to_stage(StartupStage::Startup, load_units);
+        }
+    }
+}
+
+fn save_game_system(
+    mut request: ResMut<SaveGameRequest>,
+    map_settings: Res<MapSettings>,
+    teams: Res<Teams>,
+    time: Res<SimulationTime>,
+    unit_ids: Res<UnitIds>,
+    map: Res<Map>,
+    vision: Res<Vision>,
+    order_requests: Res<OrderRequests>,
+    q_ids: Query<&UnitId>,
+    q_projectiles: Query<(&Transform, &Projectile)>,
+    q_units: Query<(
+        (
+            &UnitId,
+            &RenderSprite,
+            &UnitSize,
+            &Transform,
+            Option<&Velocity>,
+            Option<&DesiredVelocity>,
+            &Team,
+            &Speed,
+        ),
+        (
+            &RotateBeforeMove,
+            &Mover,
+            &MoverPath,
+            &Orders,
+            &AIUnit,
+            &SeekEnemyRange,
+            &Sight,
+        ),
+        (
+            &Abilities,
+            &AbilityState,
+            Option<&Mana>,
+            &OffensiveStats,
+            &Health,
+            &Defense,
+            &SufferDamage,
+            &DamageOverTime,
+            &StatusEffects,
+        ),
+    )>,
+) {
+    let path = match request.path.take() {
+        Some(path) => path,
+        None => return,
+    };
+    let to_unit_id = |entity: Entity| -> Option<Entity> {
+        q_ids.get(entity).ok().map(|id| Entity::from_raw(id.0))
+    };
+
+    let map = &map.map;
+    let saved_map = SavedMap {
+        width: map.width,
+        height: map.height,
+        walkable: map.tiles.iter().map(|t| t.is_walkable()).collect(),
+        starting_point: map.starting_point.map(|p| (p.x, p.y)),
+        exit_point: map.exit_point.map(|p| (p.x, p.y)),
+    };
+    let units = q_units
+        .iter()
+        .map(
+            |(
+                (id, render_sprite, size, transform, velocity, desired_velocity, team, speed),
+                (rotate_before_move, mover, mover_path, orders, ai_unit, seek_enemy_range, sight),
+                (
+                    abilities,
+                    ability_state,
+                    mana,
+                    offensive_stats,
+                    health,
+                    defense,
+                    suffer_damage,
+                    damage_over_time,
+                    status_effects,
+                ),
+            )| {
+                let mut unit = SavedUnit {
+                    id: *id,
+                    render_sprite: render_sprite.clone(),
+                    size: size.clone(),
+                    translation: transform.translation,
+                    rotation: transform.rotation,
+                    linear_velocity: velocity.map_or(Vec2::ZERO, |velocity| velocity.linvel),
+                    desired_velocity: desired_velocity.cloned().unwrap_or_default(),
+                    team: team.clone(),
+                    speed: speed.clone(),
+                    rotate_before_move: rotate_before_move.clone(),
+                    mover: mover.clone(),
+                    mover_path: mover_path.clone(),
+                    orders: orders.clone(),
+                    ai_unit: ai_unit.clone(),
+                    seek_enemy_range: seek_enemy_range.clone(),
+                    sight: sight.clone(),
+                    abilities: abilities.clone(),
+                    ability_state: ability_state.clone(),
+                    mana: mana.cloned(),
+                    offensive_stats: offensive_stats.clone(),
+                    health: health.clone(),
+                    defense: defense.clone(),
+                    suffer_damage: suffer_damage.clone(),
+                    damage_over_time: damage_over_time.clone(),
+                    status_effects: status_effects.clone(),
+                };
+                unit.map_entities(&to_unit_id);
+                unit
+            },
+        )
+        .collect();
+    let save = SaveGame {
+        map_settings: map_settings.clone(),
+        teams: teams.clone(),
+        tick: time.tick(),
+        map: saved_map,
+        next_unit_id: unit_ids.peek_next().0,
+        units,
+        projectiles: q_projectiles
+            .iter()
+            .map(|(transform, projectile)| {
+                let mut projectile = projectile.clone();
+                projectile.map_entities(&to_unit_id);
+                SavedProjectile {
+                    translation: transform.translation,
+                    projectile,
+                }
+            })
+            .collect(),
+        order_requests: order_requests
+            .get_requests()
+            .iter()
+            .filter_map(|request| {
+                let mut request = request.clone();
+                request.map_entities(&to_unit_id).ok()?;
+                Some(request)
+            })
+            .collect(),
+        explored: vision.explored(),
+    };
+    match save.save(&path) {
+        Ok(()) => info!("Game saved to {:?}", path),
+        Err(error) => error!("Could not save game {}", error),
+    }
+}
+
+fn load_map(mut commands: Commands, save: Res<SaveGame>) {
+    let saved_map = &save.map;
+    let mut map = mapgen::Map::new(saved_map.width, saved_map.height);
+    for y in 0..saved_map.height {
+        for x in 0..saved_map.width {
+            if saved_map.walkable[x + y * saved_map.width] {
+                map.set_tile(x, y, TileType::Floor);
+            }
+        }
+    }
+    map.starting_point = saved_map.starting_point.map(|(x, y)| Point::new(x, y));
+    map.exit_point = saved_map.exit_point.map(|(x, y)| Point::new(x, y));
+    spawn_map(&mut commands, map);
+    commands.insert_resource(Vision::from_explored(
+        saved_map.width,
+        saved_map.height,
+        &save.explored,
+    ));
+}
+
+fn load_units(
+    mut commands: Commands,
+    save: Res<SaveGame>,
+    mut time: ResMut<SimulationTime>,
+    mut order_requests: ResMut<OrderRequests>,
+) {
+    time.restore_tick(save.tick);
+    commands.insert_resource(UnitIds::starting_from(save.next_unit_id));
+
+    let entities: HashMap<u32, Entity> = save
+        .units
+        .iter()
+        .map(|unit| (unit.id.0, commands.spawn().id()))
+        .collect();
+    let from_unit_id = |entity: Entity| -> Option<Entity> { entities.get(&entity.id()).copied() };
+    for unit in save.units.iter() {
+        let mut unit = unit.clone();
+        unit.map_entities(&from_unit_id);
+        let transform = Transform::from_translation(unit.translation).with_rotation(unit.rotation);
+        // Right away rather than by `physics_init`, so the first tick moves units as if never saved.
+        let physics = unit_physics(
+            &unit.size,
+            Velocity {
+                linvel: unit.linear_velocity,
+                angvel: 0f32,
+            },
+        );
+        commands
+            .entity(entities[&unit.id.0])
+            .insert_bundle((
+                unit.id,
+                unit.render_sprite,
+                unit.size,
+                transform,
+                GlobalTransform::from(transform),
+                unit.team,
+                unit.speed,
+                unit.rotate_before_move,
+                unit.mover,
+                unit.mover_path,
+                unit.orders,
+            ))
+            .insert_bundle((
+                unit.ai_unit,
+                unit.seek_enemy_range,
+                unit.sight,
+                unit.abilities,
+                unit.ability_state,
+                unit.offensive_stats,
+                unit.health,
+                unit.defense,
+                unit.suffer_damage,
+                unit.damage_over_time,
+                unit.status_effects,
+            ));
+        if let Some(mana) = unit.mana {
+            commands.entity(entities[&unit.id.0]).insert(mana);
+        }
+        commands
+            .entity(entities[&unit.id.0])
+            .insert_bundle(physics)
+            .insert(unit.desired_velocity);
+    }
+    for request in save.order_requests.iter() {
+        let mut request = request.clone();
+        if request.map_entities(&from_unit_id).is_ok() {
+            order_requests.requests.push(request);
+        }
+    }
+    for saved in save.projectiles.iter() {
+        let mut projectile = saved.projectile.clone();
+        projectile.map_entities(&from_unit_id);
+        commands.spawn_bundle((
+            projectile,
+            Transform::from_translation(saved.translation),
+            GlobalTransform::from_translation(saved.translation),
+        ));
+    }
+    commands.remove_resource::<SaveGame>();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core_game::test::{headless_app, order_player_units, unit_states, TICKS};
+
+    /// Ticks run after saving.
+    const TICKS_AFTER_SAVE: u32 = 60;
+
+    fn save(app: &mut App, path: &PathBuf) {
+        app.world.resource_mut::<SaveGameRequest>().path = Some(path.clone());
+        // Right now rather than at the end of the next frame, so order requests stay pending.
+        SystemStage::single_threaded()
+            .with_system(save_game_system)
+            .run(&mut app.world);
+    }
+
+    #[test]
+    fn loaded_game_goes_on_as_saved_one() {
+        let path = std::env::temp_dir().join("rtas_test.save.ron");
+        let mut app = headless_app(|_| {});
+        order_player_units(&mut app);
+        // Saves while a unit is casting.
+        let mut ticks = 0;
+        while !app
+            .world
+            .query::<&AbilityState>()
+            .iter(&app.world)
+            .any(|state| state.is_casting())
+        {
+            assert!(ticks < TICKS, "no unit started casting");
+            app.update();
+            ticks += 1;
+        }
+        // And a request is waiting for the next tick.
+        let player_team = app.world.resource::<Teams>().player_team;
+        let (unit, _) = app
+            .world
+            .query::<(Entity, &Team)>()
+            .iter(&app.world)
+            .find(|(_, team)| team.id == player_team)
+            .unwrap();
+        let destination = Vec3::new(100f32, 100f32, 0f32);
+        app.world.resource_mut::<OrderRequests>().issue(
+            unit,
+            vec![Orders::order_move(destination)],
+            false,
+        );
+        save(&mut app, &path);
+
+        // Loading runs a tick along with the startup systems.
+        let mut loaded = headless_app(|app| {
+            app.insert_resource(LoadGame(path.clone()));
+        });
+        app.update();
+        for _ in 1..TICKS_AFTER_SAVE {
+            app.update();
+            loaded.update();
+        }
+        assert_eq!(
+            app.world.resource::<SimulationTime>().tick(),
+            loaded.world.resource::<SimulationTime>().tick()
+        );
+        let (expected, states) = (unit_states(&mut app), unit_states(&mut loaded));
+        assert_eq!(expected.len(), states.len());
+        for ((id, position, health), (loaded_id, loaded_position, loaded_health)) in
+            expected.into_iter().zip(states)
+        {
+            assert_eq!(id, loaded_id);
+            assert_eq!(health, loaded_health, "{:?}", id);
+            // Rapier contacts are not saved, so collisions may be solved slightly differently.
+            assert!(
+                (position - loaded_position).length() < 1f32,
+                "{:?}: {} != {}",
+                id,
+                position,
+                loaded_position
+            );
+        }
+        let _ = fs::remove_file(&path);
+    }
+}
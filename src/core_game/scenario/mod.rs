@@ -0,0 +1,226 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    map::MapSettings,
+    units::{assets_path, UnitDefinitions},
+};
+
+const SCENARIOS_FOLDER: &str = "scenarios";
+const SCENARIO_EXTENSION: &str = "scenario.ron";
+const DEFAULT_SCENARIO: &str = "default";
+
+/// Name of the scenario to play, from `assets/scenarios/<name>.scenario.ron`.
+///
+/// Inserted before adding `CorePlugin` to select another scenario than the default one.
+#[derive(Clone, Debug)]
+pub struct ScenarioName(pub String);
+
+/// Starting state of a match.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scenario {
+    pub map: MapSettings,
+    pub teams: Vec<TeamDefinition>,
+    /// Team controlled by the local player.
+    pub player_team: usize,
+    pub armies: Vec<ArmyDefinition>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TeamKind {
+    Human,
+    Ai,
+    /// Doesn't seek enemies, and isn't sought by them, but can still be ordered to attack.
+    Neutral,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TeamDefinition {
+    pub id: usize,
+    pub kind: TeamKind,
+}
+
+/// Units of the same type spawned in a row, centered on `at`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ArmyDefinition {
+    pub team: usize,
+    /// Name of the `UnitDefinition`.
+    pub unit: String,
+    pub count: u32,
+    pub at: SpawnLocation,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum SpawnLocation {
+    Tile(usize, usize),
+    StartingPoint,
+    ExitPoint,
+}
+
+/// Teams of the current match.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Teams {
+    pub teams: Vec<TeamDefinition>,
+    pub player_team: usize,
+}
+
+impl Teams {
+    pub fn kind(&self, team_id: usize) -> TeamKind {
+        self.teams
+            .iter()
+            .find(|team| team.id == team_id)
+            .map(|team| team.kind)
+            .unwrap_or(TeamKind::Ai)
+    }
+    pub fn is_neutral(&self, team_id: usize) -> bool {
+        self.kind(team_id) == TeamKind::Neutral
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, ron::Error),
+    /// The player or an army refers to a team missing from `teams`.
+    UnknownTeam(PathBuf, usize),
+    /// An army of units without definition.
+    UnknownUnit(PathBuf, String),
+    /// An army spawned on a tile outside of the map.
+    TileOutOfMap(PathBuf, usize, usize),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Read(path, error) => write!(f, "{:?}: {}", path, error),
+            ScenarioError::Parse(path, error) => write!(f, "{:?}: {}", path, error),
+            ScenarioError::UnknownTeam(path, team) => {
+                write!(f, "{:?}: team {} is not in the scenario teams", path, team)
+            }
+            ScenarioError::UnknownUnit(path, unit) => {
+                write!(f, "{:?}: no definition for unit {:?}", path, unit)
+            }
+            ScenarioError::TileOutOfMap(path, x, y) => {
+                write!(f, "{:?}: tile ({}, {}) is outside of the map", path, x, y)
+            }
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(name: &str, definitions: &UnitDefinitions) -> Result<Scenario, ScenarioError> {
+        let path = assets_path()
+            .join(SCENARIOS_FOLDER)
+            .join(format!("{}.{}", name, SCENARIO_EXTENSION));
+        let content =
+            fs::read_to_string(&path).map_err(|error| ScenarioError::Read(path.clone(), error))?;
+        Scenario::parse(path, &content, definitions)
+    }
+    /// Reads the scenario of the file at `path`, checking what it refers to exists.
+    fn parse(
+        path: PathBuf,
+        content: &str,
+        definitions: &UnitDefinitions,
+    ) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario =
+            ron::from_str(content).map_err(|error| ScenarioError::Parse(path.clone(), error))?;
+        let used_teams = scenario
+            .armies
+            .iter()
+            .map(|army| army.team)
+            .chain([scenario.player_team]);
+        for team in used_teams {
+            if !scenario
+                .teams
+                .iter()
+                .any(|definition| definition.id == team)
+            {
+                return Err(ScenarioError::UnknownTeam(path, team));
+            }
+        }
+        for army in scenario.armies.iter() {
+            if let SpawnLocation::Tile(x, y) = army.at {
+                if x >= scenario.map.width || y >= scenario.map.height {
+                    return Err(ScenarioError::TileOutOfMap(path, x, y));
+                }
+            }
+            if definitions.get(&army.unit).is_none() {
+                return Err(ScenarioError::UnknownUnit(path, army.unit.clone()));
+            }
+        }
+        Ok(scenario)
+    }
+}
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        let name = match app.world.get_resource::<ScenarioName>() {
+            Some(name) => name.clone(),
+            None => ScenarioName(DEFAULT_SCENARIO.to_string()),
+        };
+        let definitions = app.world.resource::<UnitDefinitions>();
+        let scenario = Scenario::load(&name.0, definitions)
+            .unwrap_or_else(|error| panic!("Could not load scenario {}", error));
+        // Map settings may already come from a replay.
+        if !app.world.contains_resource::<MapSettings>() {
+            app.insert_resource(scenario.map.clone());
+        }
+        app.insert_resource(Teams {
+            teams: scenario.teams.clone(),
+            player_team: scenario.player_team,
+        })
+        .insert_resource(scenario)
+        .insert_resource(name);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn definitions() -> UnitDefinitions {
+        let mut definitions = UnitDefinitions::default();
+        definitions.insert(
+            ron::from_str(
+                r#"(name: "goblin", size: 20.0, speed: 200.0, rotation_speed: 720.0,
+                seek_enemy_range: 200.0, max_hp: 20.0, power: 2.0, abilities: [],
+                sprite: "units/goblin.png", color: (1.0, 1.0, 1.0))"#,
+            )
+            .unwrap(),
+        );
+        definitions
+    }
+    fn parse(armies: &str) -> Result<Scenario, ScenarioError> {
+        let content = format!(
+            "(map: (width: 30, height: 30, seed: 7), teams: [(id: 0, kind: Ai), (id: 1, kind: Human)],
+            player_team: 1, armies: [{}])",
+            armies
+        );
+        Scenario::parse(PathBuf::from("test.scenario.ron"), &content, &definitions())
+    }
+
+    #[test]
+    fn valid_scenario() {
+        let scenario = parse(r#"(team: 0, unit: "goblin", count: 3, at: Tile(29, 0))"#).unwrap();
+        assert_eq!(scenario.armies.len(), 1);
+    }
+    #[test]
+    fn unknown_team() {
+        let result = parse(r#"(team: 2, unit: "goblin", count: 3, at: StartingPoint)"#);
+        assert!(matches!(result, Err(ScenarioError::UnknownTeam(_, 2))));
+    }
+    #[test]
+    fn unknown_unit() {
+        let result = parse(r#"(team: 0, unit: "dragon", count: 1, at: ExitPoint)"#);
+        assert!(matches!(result, Err(ScenarioError::UnknownUnit(_, unit)) if unit == "dragon"));
+    }
+    #[test]
+    fn tile_out_of_map() {
+        let result = parse(r#"(team: 0, unit: "goblin", count: 1, at: Tile(3, 30))"#);
+        assert!(matches!(result, Err(ScenarioError::TileOutOfMap(_, 3, 30))));
+    }
+}
@@ -0,0 +1,37 @@
+//! Serde helpers for core types which can't derive their serialization.
+
+/// Serializes an `Entity` by its index only.
+///
+/// Used for entities remapped to their `UnitId` before being written,
+/// as raw entities are not stable from one run to another.
+pub mod entity_index {
+    use bevy::prelude::Entity;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(entity.id())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+        Ok(Entity::from_raw(u32::deserialize(deserializer)?))
+    }
+}
+
+/// Same as `entity_index`, for an optional entity.
+pub mod optional_entity_index {
+    use bevy::prelude::Entity;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        entity: &Option<Entity>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        entity.map(|entity| entity.id()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Entity>, D::Error> {
+        Ok(Option::<u32>::deserialize(deserializer)?.map(Entity::from_raw))
+    }
+}
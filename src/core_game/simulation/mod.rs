@@ -0,0 +1,140 @@
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+
+/// Duration of a simulation tick, in seconds.
+pub const SIMULATION_STEP: f32 = 1f32 / 60f32;
+
+/// The core simulation runs in its own schedule, stepped at a fixed rate,
+/// so the same inputs always give the same outcome, whatever the frame rate.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct SimulationSchedule;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub enum SimulationStage {
+    /// Inputs for this tick, from players or replays.
+    First,
+    PreUpdate,
+    Update,
+    PostUpdate,
+    Last,
+}
+
+/// Systems of the simulation, run one after the other within their stage.
+///
+/// Many of them write to the same components (`SufferDamage`, `Mover`, `AbilityState`...),
+/// so their order is fixed for a tick to always give the same outcome.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum SimulationLabel {
+    // `SimulationStage::First`
+    /// Orders and definition changes recorded to or played from a replay.
+    Inputs,
+    /// Hot reloaded unit definitions.
+    Definitions,
+    SpatialIndex,
+    Vision,
+    FlowFields,
+    MoverPaths,
+    // `SimulationStage::PreUpdate`
+    OrderRequests,
+    Movers,
+    // `SimulationStage::Update`
+    Orders,
+    Abilities,
+    Mana,
+    StatusEffects,
+    DamageOverTime,
+    Projectiles,
+    Avoidance,
+    // `SimulationStage::PostUpdate`
+    Health,
+    Ai,
+    PhysicsInit,
+}
+
+/// Simulation clock, to be used instead of `Time` by anything affecting the simulation.
+#[derive(Debug)]
+pub struct SimulationTime {
+    tick: u64,
+    /// When false, the simulation is stepped once per frame, as fast as the app runs.
+    pub realtime: bool,
+    accumulator: f64,
+    looping: bool,
+}
+
+impl Default for SimulationTime {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            realtime: true,
+            accumulator: 0f64,
+            looping: false,
+        }
+    }
+}
+
+impl SimulationTime {
+    /// Steps the simulation once per frame, without waiting for real time.
+    pub fn uncapped() -> Self {
+        Self {
+            realtime: false,
+            ..Default::default()
+        }
+    }
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    /// Resumes the simulation from a given tick, when loading a saved game.
+    pub fn restore_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+    pub fn delta_seconds(&self) -> f32 {
+        SIMULATION_STEP
+    }
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.tick as f32 * SIMULATION_STEP
+    }
+}
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationTime>();
+        app.add_stage_after(
+            CoreStage::Update,
+            SimulationSchedule,
+            Schedule::default()
+                .with_run_criteria(simulation_run_criteria)
+                // Single threaded, so systems run in the order given by their `SimulationLabel`.
+                .with_stage(SimulationStage::First, SystemStage::single_threaded())
+                .with_stage(SimulationStage::PreUpdate, SystemStage::single_threaded())
+                .with_stage(SimulationStage::Update, SystemStage::single_threaded())
+                .with_stage(SimulationStage::PostUpdate, SystemStage::single_threaded())
+                .with_stage(
+                    SimulationStage::Last,
+                    SystemStage::single_threaded().with_system(advance_tick),
+                ),
+        );
+    }
+}
+
+/// Same idea as `FixedTimestep`, but can be switched to one step per frame for headless runs.
+fn simulation_run_criteria(time: Res<Time>, mut sim_time: ResMut<SimulationTime>) -> ShouldRun {
+    if !sim_time.realtime {
+        return ShouldRun::Yes;
+    }
+    if !sim_time.looping {
+        sim_time.accumulator += time.delta_seconds_f64();
+    }
+    if sim_time.accumulator >= SIMULATION_STEP as f64 {
+        sim_time.accumulator -= SIMULATION_STEP as f64;
+        sim_time.looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        sim_time.looping = false;
+        ShouldRun::No
+    }
+}
+
+fn advance_tick(mut sim_time: ResMut<SimulationTime>) {
+    sim_time.tick += 1;
+}
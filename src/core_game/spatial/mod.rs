@@ -0,0 +1,372 @@
+use bevy::prelude::*;
+
+use super::{
+    components::{Team, UnitSize},
+    map::TILE_SIZE,
+    simulation::{SimulationLabel, SimulationSchedule, SimulationStage},
+};
+
+pub struct SpatialPlugin;
+
+impl Plugin for SpatialPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SpatialIndex::new(TILE_SIZE)).stage(
+            SimulationSchedule,
+            |schedule: &mut Schedule| {
+                schedule.add_system_to_stage(
+                    SimulationStage::First,
+                    update_spatial_index.label(SimulationLabel::SpatialIndex),
+                )
+            },
+        );
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpatialEntry {
+    pub entity: Entity,
+    pub position: Vec2,
+    pub radius: f32,
+    pub team: usize,
+}
+
+/// Units bucketed in a uniform grid, rebuilt at the beginning of each simulation tick.
+///
+/// Entries may refer to units despawned since, check they still exist when it matters.
+pub struct SpatialIndex {
+    cell_size: f32,
+    /// Cell of the grid bottom left corner, the grid only covers indexed units.
+    origin: (i32, i32),
+    width: i32,
+    height: i32,
+    /// For each cell, where its entries start in `by_cell`; one more for the end of the last.
+    cell_starts: Vec<usize>,
+    by_cell: Vec<usize>,
+    entries: Vec<SpatialEntry>,
+}
+
+impl SpatialIndex {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialIndex {
+            cell_size,
+            origin: (0, 0),
+            width: 0,
+            height: 0,
+            cell_starts: vec![0],
+            by_cell: vec![],
+            entries: vec![],
+        }
+    }
+
+    fn cell_at(&self, position: Vec2) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cell_index(&self, cell: (i32, i32)) -> usize {
+        ((cell.0 - self.origin.0) + (cell.1 - self.origin.1) * self.width) as usize
+    }
+
+    /// Replaces all entries, keeping their order for ties.
+    pub fn rebuild(&mut self, entries: impl IntoIterator<Item = SpatialEntry>) {
+        self.entries.clear();
+        self.entries.extend(entries);
+        let cells: Vec<(i32, i32)> = self
+            .entries
+            .iter()
+            .map(|entry| self.cell_at(entry.position))
+            .collect();
+        let min = cells.iter().fold((i32::MAX, i32::MAX), |min, cell| {
+            (min.0.min(cell.0), min.1.min(cell.1))
+        });
+        let max = cells.iter().fold((i32::MIN, i32::MIN), |max, cell| {
+            (max.0.max(cell.0), max.1.max(cell.1))
+        });
+        if cells.is_empty() {
+            self.width = 0;
+            self.height = 0;
+        } else {
+            self.origin = min;
+            self.width = max.0 - min.0 + 1;
+            self.height = max.1 - min.1 + 1;
+        }
+
+        // Counting sort of the entries by cell.
+        let cell_count = (self.width * self.height) as usize;
+        self.cell_starts.clear();
+        self.cell_starts.resize(cell_count + 1, 0);
+        for cell in cells.iter() {
+            let index = self.cell_index(*cell);
+            self.cell_starts[index + 1] += 1;
+        }
+        for i in 0..cell_count {
+            self.cell_starts[i + 1] += self.cell_starts[i];
+        }
+        let mut next = self.cell_starts.clone();
+        self.by_cell.clear();
+        self.by_cell.resize(self.entries.len(), 0);
+        for (entry_index, cell) in cells.iter().enumerate() {
+            let index = self.cell_index(*cell);
+            self.by_cell[next[index]] = entry_index;
+            next[index] += 1;
+        }
+    }
+
+    /// Bounds of the cells overlapping the `min` -> `max` rectangle grown by `margin`,
+    /// clamped to the grid.
+    fn cell_bounds(&self, min: Vec2, max: Vec2, margin: f32) -> ((i32, i32), (i32, i32)) {
+        let (min_x, min_y) = self.cell_at(min - Vec2::splat(margin));
+        let (max_x, max_y) = self.cell_at(max + Vec2::splat(margin));
+        (
+            (min_x.max(self.origin.0), min_y.max(self.origin.1)),
+            (
+                max_x.min(self.origin.0 + self.width - 1),
+                max_y.min(self.origin.1 + self.height - 1),
+            ),
+        )
+    }
+
+    fn cell(&self, cell: (i32, i32)) -> &[usize] {
+        let index = self.cell_index(cell);
+        &self.by_cell[self.cell_starts[index]..self.cell_starts[index + 1]]
+    }
+
+    fn cells_around(&self, min: Vec2, max: Vec2, margin: f32) -> impl Iterator<Item = &[usize]> {
+        let ((min_x, min_y), (max_x, max_y)) = self.cell_bounds(min, max, margin);
+        (min_y..=max_y)
+            .flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+            .map(move |cell| self.cell(cell))
+    }
+
+    /// Entries in cells overlapping the `min` -> `max` rectangle, grown by `margin`.
+    fn candidates(&self, min: Vec2, max: Vec2, margin: f32) -> impl Iterator<Item = &SpatialEntry> {
+        self.cells_around(min, max, margin)
+            .flatten()
+            .map(|index| &self.entries[*index])
+    }
+
+    fn max_radius(&self) -> f32 {
+        self.entries.iter().map(|e| e.radius).fold(0f32, f32::max)
+    }
+
+    /// Units whose center is within `radius` of `center`.
+    pub fn in_circle(&self, center: Vec2, radius: f32) -> impl Iterator<Item = &SpatialEntry> {
+        self.candidates(center, center, radius)
+            .filter(move |entry| entry.position.distance_squared(center) <= radius * radius)
+    }
+
+    /// Units whose bounds (a square of `radius` half size) touch the rectangle between two corners.
+    pub fn in_rect(&self, corner_1: Vec2, corner_2: Vec2) -> impl Iterator<Item = &SpatialEntry> {
+        let min = corner_1.min(corner_2);
+        let max = corner_1.max(corner_2);
+        self.candidates(min, max, self.max_radius())
+            .filter(move |entry| {
+                let radius = Vec2::splat(entry.radius);
+                (entry.position + radius).cmpge(min).all()
+                    && (entry.position - radius).cmple(max).all()
+            })
+    }
+
+    /// Closest unit within `radius` of `center` accepted by `filter`.
+    ///
+    /// Ties are broken by insertion order, so results don't depend on the grid layout.
+    pub fn nearest(
+        &self,
+        center: Vec2,
+        radius: f32,
+        filter: impl Fn(&SpatialEntry) -> bool,
+    ) -> Option<&SpatialEntry> {
+        let mut best: Option<(f32, usize)> = None;
+        let ((min_x, min_y), (max_x, max_y)) = self.cell_bounds(center, center, radius);
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                for index in self.cell((x, y)) {
+                    let entry = &self.entries[*index];
+                    let distance = entry.position.distance_squared(center);
+                    if distance > radius * radius {
+                        continue;
+                    }
+                    let is_better = best.map_or(true, |(best_distance, best_index)| {
+                        distance < best_distance
+                            || (distance == best_distance && *index < best_index)
+                    });
+                    if is_better && filter(entry) {
+                        best = Some((distance, *index));
+                    }
+                }
+            }
+        }
+        best.map(|(_, index)| &self.entries[index])
+    }
+
+    /// Closest unit within `radius` not in `team`, and not in one of the `ignored` teams.
+    pub fn nearest_enemy(
+        &self,
+        center: Vec2,
+        radius: f32,
+        team: usize,
+        ignored: impl Fn(usize) -> bool,
+    ) -> Option<&SpatialEntry> {
+        self.nearest(center, radius, |entry| {
+            entry.team != team && !ignored(entry.team)
+        })
+    }
+}
+
+fn update_spatial_index(
+    mut index: ResMut<SpatialIndex>,
+    q_units: Query<(Entity, &Transform, &UnitSize, &Team)>,
+) {
+    index.rebuild(
+        q_units
+            .iter()
+            .map(|(entity, transform, size, team)| SpatialEntry {
+                entity,
+                position: transform.translation.truncate(),
+                radius: size.0,
+                team: team.id,
+            }),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn index_with(units: &[(f32, f32, usize)]) -> SpatialIndex {
+        let mut index = SpatialIndex::new(100f32);
+        index.rebuild(
+            units
+                .iter()
+                .enumerate()
+                .map(|(i, (x, y, team))| SpatialEntry {
+                    entity: Entity::from_raw(i as u32),
+                    position: Vec2::new(*x, *y),
+                    radius: 10f32,
+                    team: *team,
+                }),
+        );
+        index
+    }
+
+    #[test]
+    fn nearest_enemy_in_range() {
+        let index = index_with(&[
+            (0.0, 0.0, 0),
+            (50.0, 0.0, 0),
+            (-250.0, 0.0, 1),
+            (180.0, 0.0, 1),
+        ]);
+        let found = index.nearest_enemy(Vec2::ZERO, 200f32, 0, |_| false);
+        assert_eq!(found.map(|e| e.entity), Some(Entity::from_raw(3)));
+        assert!(index
+            .nearest_enemy(Vec2::ZERO, 150f32, 0, |_| false)
+            .is_none());
+        assert!(index
+            .nearest_enemy(Vec2::ZERO, 200f32, 0, |team| team == 1)
+            .is_none());
+    }
+    #[test]
+    fn rect_touches_bounds() {
+        let index = index_with(&[(0.0, 0.0, 0), (-105.0, 0.0, 0), (300.0, 300.0, 0)]);
+        let mut found: Vec<u32> = index
+            .in_rect(Vec2::new(-95.0, 5.0), Vec2::new(5.0, -5.0))
+            .map(|e| e.entity.id())
+            .collect();
+        found.sort();
+        assert_eq!(found, vec![0, 1]);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use test::Bencher;
+
+    /// `count` units of 2 teams, with the same density whatever the count.
+    fn units(count: usize) -> Vec<SpatialEntry> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let side = (count as f32).sqrt() * 100f32;
+        (0..count)
+            .map(|i| SpatialEntry {
+                entity: Entity::from_raw(i as u32),
+                position: Vec2::new(rng.gen_range(0f32..side), rng.gen_range(0f32..side)),
+                radius: 20f32,
+                team: i % 2,
+            })
+            .collect()
+    }
+
+    fn index_of(units: &[SpatialEntry]) -> SpatialIndex {
+        let mut index = SpatialIndex::new(TILE_SIZE);
+        index.rebuild(units.iter().cloned());
+        index
+    }
+
+    fn bench_rebuild(b: &mut Bencher, count: usize) {
+        let units = units(count);
+        let mut index = index_of(&units);
+        b.iter(|| index.rebuild(units.iter().cloned()));
+    }
+
+    /// What `ai_system` does: every unit seeks its closest enemy.
+    fn bench_seek_all(b: &mut Bencher, count: usize) {
+        let units = units(count);
+        let index = index_of(&units);
+        b.iter(|| {
+            units
+                .iter()
+                .filter_map(|unit| index.nearest_enemy(unit.position, 200f32, unit.team, |_| false))
+                .count()
+        });
+    }
+
+    #[bench]
+    fn rebuild_1k(b: &mut Bencher) {
+        bench_rebuild(b, 1_000);
+    }
+    #[bench]
+    fn rebuild_10k(b: &mut Bencher) {
+        bench_rebuild(b, 10_000);
+    }
+    #[bench]
+    fn seek_all_1k(b: &mut Bencher) {
+        bench_seek_all(b, 1_000);
+    }
+    #[bench]
+    fn seek_all_10k(b: &mut Bencher) {
+        bench_seek_all(b, 10_000);
+    }
+    #[bench]
+    fn seek_all_brute_force_1k(b: &mut Bencher) {
+        let units = units(1_000);
+        b.iter(|| {
+            units
+                .iter()
+                .filter_map(|unit| {
+                    units
+                        .iter()
+                        .filter(|other| other.team != unit.team)
+                        .map(|other| (other.position.distance_squared(unit.position), other))
+                        .filter(|(distance, _)| *distance <= 200f32 * 200f32)
+                        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+                })
+                .count()
+        });
+    }
+    #[bench]
+    fn rect_selection_10k(b: &mut Bencher) {
+        let units = units(10_000);
+        let index = index_of(&units);
+        b.iter(|| {
+            index
+                .in_rect(Vec2::new(1000f32, 1000f32), Vec2::new(2000f32, 1600f32))
+                .count()
+        });
+    }
+}
@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::simulation::{SimulationLabel, SimulationSchedule, SimulationStage, SimulationTime};
+
+pub struct StatusEffectsPlugin;
+
+impl Plugin for StatusEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.stage(SimulationSchedule, |schedule: &mut Schedule| {
+            schedule.add_system_to_stage(
+                SimulationStage::Update,
+                status_effects_system
+                    .label(SimulationLabel::StatusEffects)
+                    .after(SimulationLabel::Mana),
+            )
+        });
+    }
+}
+
+/// Temporary modification of a unit, effects of the same kind stack.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Status {
+    /// Can't move, cast nor follow orders, interrupts the current cast.
+    Stun,
+    /// Fraction of speed lost, slows multiply each other.
+    Slow { ratio: f32 },
+    /// Fraction added to attack speed: cast times and cooldowns are divided by `1 + ratio`.
+    Haste { ratio: f32 },
+    /// Fraction added to power.
+    Empower { ratio: f32 },
+}
+
+impl Status {
+    pub fn is_harmful(&self) -> bool {
+        match self {
+            Status::Stun | Status::Slow { .. } => true,
+            Status::Haste { .. } | Status::Empower { .. } => false,
+        }
+    }
+    /// Same kind of status, whatever its values.
+    pub fn same_kind(&self, other: &Status) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatusEffect {
+    pub status: Status,
+    /// Seconds left.
+    pub remaining: f32,
+    /// Unit which applied it.
+    #[serde(
+        default,
+        with = "crate::core_game::serialization::optional_entity_index"
+    )]
+    pub source: Option<Entity>,
+}
+
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+}
+
+impl StatusEffects {
+    pub fn add(&mut self, status: Status, duration: f32, source: Option<Entity>) {
+        self.effects.push(StatusEffect {
+            status,
+            remaining: duration,
+            source,
+        });
+    }
+    pub fn is_stunned(&self) -> bool {
+        self.effects
+            .iter()
+            .any(|effect| effect.status == Status::Stun)
+    }
+    pub fn speed_multiplier(&self) -> f32 {
+        self.effects
+            .iter()
+            .fold(1f32, |multiplier, effect| match effect.status {
+                Status::Slow { ratio } => multiplier * (1f32 - ratio.clamp(0f32, 1f32)),
+                _ => multiplier,
+            })
+    }
+    /// Cast times and cooldowns are divided by it.
+    pub fn attack_speed_multiplier(&self) -> f32 {
+        self.effects
+            .iter()
+            .fold(1f32, |multiplier, effect| match effect.status {
+                Status::Haste { ratio } => multiplier + ratio,
+                _ => multiplier,
+            })
+            .max(f32::EPSILON)
+    }
+    pub fn power_multiplier(&self) -> f32 {
+        self.effects
+            .iter()
+            .fold(1f32, |multiplier, effect| match effect.status {
+                Status::Empower { ratio } => multiplier + ratio,
+                _ => multiplier,
+            })
+            .max(0f32)
+    }
+    /// Replaces the sources, forgotten if they can't be mapped.
+    pub fn map_entities(&mut self, mapper: &impl Fn(Entity) -> Option<Entity>) {
+        for effect in self.effects.iter_mut() {
+            effect.source = effect.source.and_then(mapper);
+        }
+    }
+}
+
+/// Multipliers of an optional `StatusEffects`, for units which may not have any.
+pub fn speed_multiplier(status_effects: Option<&StatusEffects>) -> f32 {
+    status_effects.map_or(1f32, StatusEffects::speed_multiplier)
+}
+pub fn attack_speed_multiplier(status_effects: Option<&StatusEffects>) -> f32 {
+    status_effects.map_or(1f32, StatusEffects::attack_speed_multiplier)
+}
+pub fn power_multiplier(status_effects: Option<&StatusEffects>) -> f32 {
+    status_effects.map_or(1f32, StatusEffects::power_multiplier)
+}
+pub fn is_stunned(status_effects: Option<&StatusEffects>) -> bool {
+    status_effects.map_or(false, StatusEffects::is_stunned)
+}
+
+/// Expires effects, damage over time is dealt by the `damage` module.
+pub fn status_effects_system(time: Res<SimulationTime>, mut q: Query<&mut StatusEffects>) {
+    let delta = time.delta_seconds();
+    for mut status_effects in q.iter_mut() {
+        if status_effects.effects.is_empty() {
+            continue;
+        }
+        for effect in status_effects.effects.iter_mut() {
+            effect.remaining -= delta;
+        }
+        status_effects
+            .effects
+            .retain(|effect| effect.remaining > 0f32);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn effects_stack() {
+        let mut status_effects = StatusEffects::default();
+        status_effects.add(Status::Slow { ratio: 0.5 }, 1f32, None);
+        status_effects.add(Status::Slow { ratio: 0.5 }, 2f32, None);
+        status_effects.add(Status::Haste { ratio: 0.25 }, 2f32, None);
+        status_effects.add(Status::Haste { ratio: 0.25 }, 2f32, None);
+        assert_eq!(status_effects.speed_multiplier(), 0.25);
+        assert_eq!(status_effects.attack_speed_multiplier(), 1.5);
+        assert_eq!(status_effects.power_multiplier(), 1f32);
+        assert!(!status_effects.is_stunned());
+        status_effects.add(Status::Stun, 1f32, None);
+        assert!(status_effects.is_stunned());
+        assert!(Status::Slow { ratio: 0.1 }.same_kind(&Status::Slow { ratio: 0.9 }));
+    }
+}
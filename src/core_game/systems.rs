@@ -1,5 +1,21 @@
-use super::{components::*, map::Map, orders::orders_comp::*, physics::PHYSICS_PIXEL_PER_METER};
+use super::{
+    abilities::{Abilities, AbilityEffect, AbilityState, AbilityTarget, Mana},
+    combat_log::{AttackStarted, UnitDied},
+    components::*,
+    damage::{DamageOverTime, Defense},
+    map::{Map, Wall},
+    orders::orders_comp::*,
+    pathfinding::pathfinding_comp,
+    physics::PHYSICS_PIXEL_PER_METER,
+    scenario::{Scenario, SpawnLocation, Teams},
+    simulation::SimulationTime,
+    spatial::{SpatialEntry, SpatialIndex},
+    status_effects::{self, StatusEffects},
+    units::{UnitDefinition, UnitDefinitions},
+    vision::{Sight, Vision},
+};
 use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
 
 // Bundles
 #[derive(Bundle)]
@@ -11,177 +27,145 @@ pub struct UnitBundle {
 
     // should be added after (for all units having "Speed")
     mover: Mover,
+    mover_path: MoverPath,
     rotate_before_move: RotateBeforeMove,
     speed: Speed,
     team: Team,
     ai_unit: AIUnit,
     seek_enemy_range: SeekEnemyRange,
-    melee_ability: MeleeAbility,
-    // should be added after (for all units having "MeleeAbility")
-    melee_ability_state: MeleeAbilityState,
+    sight: Sight,
+    abilities: Abilities,
+    // should be added after (for all units having "Abilities")
+    ability_state: AbilityState,
     offensive_stats: OffensiveStats,
     health: Health,
+    defense: Defense,
     // should be added after (for all units having "Health")
     suffer_damage: SufferDamage,
+    damage_over_time: DamageOverTime,
+    status_effects: StatusEffects,
     // should be added after (for all units having "Mover")
     orders: Orders,
 }
-pub fn create_bandit_unit(team: Team, position: Vec3) -> UnitBundle {
+pub fn create_unit(definition: &UnitDefinition, team: Team, position: Vec3) -> UnitBundle {
     UnitBundle {
-        size: UnitSize(20f32),
+        size: UnitSize(definition.size),
         transform: Transform::from_translation(position),
         global_transform: GlobalTransform::from_translation(position),
-        render_sprite: RenderSprite::Bandit,
+        render_sprite: RenderSprite(definition.name.clone()),
         mover: Mover::new(position),
+        mover_path: MoverPath::default(),
         rotate_before_move: RotateBeforeMove {
-            rotation_speed: 700f32,
+            rotation_speed: definition.rotation_speed,
         },
-        speed: Speed { speed: 160f32 },
-        team,
-        ai_unit: AIUnit::SeekEnemy,
-        seek_enemy_range: SeekEnemyRange { range: 200f32 },
-        melee_ability: MeleeAbility {
-            range: 100f32,
-            motion_buffer_range: 3f32,
-            time_to_strike: 0.4f32,
-            cooldown: 0.2f32,
-        },
-        offensive_stats: OffensiveStats { power: 4f32 },
-        melee_ability_state: MeleeAbilityState::Ready,
-        health: Health {
-            max_hp: 10f32,
-            current_hp: 10f32,
-        },
-        suffer_damage: SufferDamage::default(),
-        orders: Orders::default(),
-    }
-}
-pub fn create_goblin_unit(team: Team, position: Vec3) -> UnitBundle {
-    UnitBundle {
-        size: UnitSize(20f32),
-        transform: Transform::from_translation(position),
-        global_transform: GlobalTransform::from_translation(position),
-        render_sprite: RenderSprite::Goblin,
-        mover: Mover::new(position),
-        rotate_before_move: RotateBeforeMove {
-            rotation_speed: 720f32,
+        speed: Speed {
+            speed: definition.speed,
         },
-        speed: Speed { speed: 200f32 },
         team,
         ai_unit: AIUnit::SeekEnemy,
-        seek_enemy_range: SeekEnemyRange { range: 200f32 },
-        melee_ability: MeleeAbility {
-            range: 5f32,
-            motion_buffer_range: 3f32,
-            time_to_strike: 0.2f32,
-            cooldown: 0.2f32,
+        seek_enemy_range: SeekEnemyRange {
+            range: definition.seek_enemy_range,
         },
-        offensive_stats: OffensiveStats { power: 2f32 },
-        melee_ability_state: MeleeAbilityState::Ready,
-        health: Health {
-            max_hp: 20f32,
-            current_hp: 20f32,
-        },
-        suffer_damage: SufferDamage::default(),
-        orders: Orders::default(),
-    }
-}
-pub fn create_ogre_unit(team: Team, position: Vec3) -> UnitBundle {
-    UnitBundle {
-        size: UnitSize(40f32),
-        transform: Transform::from_translation(position),
-        global_transform: GlobalTransform::from_translation(position),
-        render_sprite: RenderSprite::Ogre,
-        mover: Mover::new(position),
-        rotate_before_move: RotateBeforeMove {
-            rotation_speed: 90f32,
+        sight: Sight {
+            range: definition.sight_range,
         },
-        speed: Speed { speed: 50f32 },
-        team,
-        ai_unit: AIUnit::SeekEnemy,
-        seek_enemy_range: SeekEnemyRange { range: 200f32 },
-        melee_ability: MeleeAbility {
-            range: 10f32,
-            motion_buffer_range: 3f32,
-            time_to_strike: 1.2f32,
-            cooldown: 0.34f32,
+        abilities: Abilities(definition.abilities.clone()),
+        offensive_stats: OffensiveStats {
+            power: definition.power,
+            critical_chance: definition.critical_chance,
+            critical_multiplier: definition.critical_multiplier,
         },
-        offensive_stats: OffensiveStats { power: 13f32 },
-        melee_ability_state: MeleeAbilityState::Ready,
+        ability_state: AbilityState::default(),
         health: Health {
-            max_hp: 250f32,
-            current_hp: 250f32,
+            max_hp: definition.max_hp,
+            current_hp: definition.max_hp,
         },
+        defense: definition.defense(),
         suffer_damage: SufferDamage::default(),
+        damage_over_time: DamageOverTime::default(),
+        status_effects: StatusEffects::default(),
         orders: Orders::default(),
     }
 }
 
-pub fn create_units(mut commands: Commands, map: Res<Map>) {
-    const OFFSET_POSITION: f32 = 40f32;
-    const NB_GOBLINS: u32 = 5;
-    const NB_BANDITS: u32 = 5;
-    for i in 0..NB_GOBLINS {
-        let position = Vec3::new(
-            (i as f32 - (NB_GOBLINS as f32) / 2f32) * OFFSET_POSITION,
-            0.0,
-            0.0,
-        );
-        commands
-            .spawn()
-            .insert_bundle(create_goblin_unit(Team { id: 0 }, position));
-    }
-    if let Some(start) = map.map.starting_point {
-        let real_start = Map::real_position_at(start.x, start.y);
-        for i in 0..NB_BANDITS {
+pub fn create_units(
+    mut commands: Commands,
+    mut unit_ids: ResMut<UnitIds>,
+    definitions: Res<UnitDefinitions>,
+    scenario: Res<Scenario>,
+    map: Res<Map>,
+) {
+    for army in scenario.armies.iter() {
+        // Already checked by `Scenario::load`.
+        let definition = match definitions.get(&army.unit) {
+            Some(definition) => definition,
+            None => {
+                warn!("Unknown unit for {:?}", army);
+                continue;
+            }
+        };
+        let tile = match army.at {
+            SpawnLocation::Tile(x, y) => Some((x, y)),
+            SpawnLocation::StartingPoint => map.map.starting_point.map(|p| (p.x, p.y)),
+            SpawnLocation::ExitPoint => map.map.exit_point.map(|p| (p.x, p.y)),
+        };
+        let center = match tile {
+            Some((x, y)) => map.real_position_at(x, y),
+            None => {
+                warn!("No spawn location for {:?}", army);
+                continue;
+            }
+        };
+        let offset_position = definition.size * 2f32;
+        for i in 0..army.count {
             let position = Vec3::new(
-                (i as f32 - (NB_BANDITS as f32) / 2f32) * OFFSET_POSITION + real_start.x,
-                real_start.y,
+                (i as f32 - (army.count as f32) / 2f32) * offset_position + center.x,
+                center.y,
                 0.0,
             );
-
-            let id = commands
-                .spawn()
-                .insert_bundle(create_bandit_unit(Team { id: 2 }, position))
-                .id();
-            dbg!(id);
+            let mut unit = commands.spawn();
+            unit.insert_bundle(create_unit(definition, Team { id: army.team }, position))
+                .insert(unit_ids.next());
+            if let Some(mana) = definition.mana() {
+                unit.insert(mana);
+            }
         }
     }
-    const OFFSET_POSITION_OGRE: f32 = 100f32;
-    const NB_OGRES: u32 = 1;
-    for i in 0..NB_OGRES {
-        let ogre_position = Vec3::new(
-            (i as f32 - (NB_OGRES as f32) / 2f32) * OFFSET_POSITION_OGRE,
-            -300.0,
-            0.0,
-        );
-        commands
-            .spawn()
-            .insert_bundle(create_ogre_unit(Team { id: 1 }, ogre_position));
-    }
 }
 
 pub fn ai_system(
-    time: Res<Time>,
+    time: Res<SimulationTime>,
+    teams: Res<Teams>,
+    spatial_index: Res<SpatialIndex>,
+    pathfinding_map: Res<pathfinding_comp::Map>,
+    map: Res<Map>,
+    vision: Res<Vision>,
+    mut attack_started: EventWriter<AttackStarted>,
     mut ais: Query<(
+        Entity,
         &Team,
         &SeekEnemyRange,
         &mut AIUnit,
         &mut Orders,
-        &MeleeAbility,
-        &mut MeleeAbilityState,
+        &Abilities,
+        &mut AbilityState,
+        Option<&mut Mana>,
+        Option<&StatusEffects>,
         &Transform,
         &UnitSize,
     )>,
     attackable: Query<(&Team, &Transform, Entity, &UnitSize)>,
 ) {
     for (
+        entity,
         a_team,
         seek_enemy_range,
         mut ai,
         mut a_orders,
-        melee_ability,
-        mut melee_state,
+        abilities,
+        mut ability_state,
+        mut mana,
+        a_status_effects,
         a_transform,
         a_size,
     ) in ais.iter_mut()
@@ -189,25 +173,42 @@ pub fn ai_system(
         if matches!(*ai, AIUnit::Passive) {
             continue;
         }
-        if matches!(*melee_state, MeleeAbilityState::WillAttack(_)) {
+        if ability_state.is_casting() || status_effects::is_stunned(a_status_effects) {
             continue;
         }
+        let (attack_index, attack) = match abilities.attack() {
+            Some(attack) => attack,
+            None => continue,
+        };
         let a_position = a_transform.translation;
+        // Units holding their position only attack what they can reach from there.
+        let holding = a_orders.is_holding_position();
+        let in_reach = |entry: &SpatialEntry| {
+            !holding
+                || (entry.position - a_position.truncate()).length()
+                    < attack.range + entry.radius + a_size.0
+        };
         let mut new_ai: Option<AIUnit> = None;
-        if matches!(*ai, AIUnit::SeekEnemy) {
-            let mut closest_distance = f32::MAX;
-            for (b_team, b_transform, b_entity, _) in attackable.iter() {
-                if a_team.id == b_team.id {
-                    continue;
-                }
-                let new_distance = (a_position - b_transform.translation).length();
-                if new_distance <= seek_enemy_range.range && new_distance < closest_distance {
-                    closest_distance = new_distance;
-                    new_ai = Some(AIUnit::Attack(Attack {
-                        target: b_entity.clone(),
-                        chase_when_target_too_far: false,
-                    }));
-                }
+        if matches!(*ai, AIUnit::SeekEnemy) && !teams.is_neutral(a_team.id) {
+            let closest =
+                spatial_index.nearest(a_position.truncate(), seek_enemy_range.range, |entry| {
+                    entry.team != a_team.id
+                        && !teams.is_neutral(entry.team)
+                        && vision.is_visible(&map, a_team.id, entry.position)
+                        && in_reach(entry)
+                        && attackable.get(entry.entity).is_ok()
+                });
+            if let Some(closest) = closest {
+                // Idle units go back where they were, even if pulled away by several enemies.
+                let return_position = a_orders
+                    .get_orders()
+                    .is_empty()
+                    .then(|| a_orders.returning_to().unwrap_or(a_position));
+                new_ai = Some(AIUnit::Attack(Attack {
+                    target: closest.entity,
+                    chase_when_target_too_far: false,
+                    return_position,
+                }));
             }
         }
         if let Some(new_ai) = new_ai {
@@ -220,123 +221,173 @@ pub fn ai_system(
                 if !ai_attacker.chase_when_target_too_far {
                     let new_distance = (a_position - target_transform.translation).length();
                     if new_distance > seek_enemy_range.range {
-                        *ai = AIUnit::SeekEnemy;
+                        stop_attacking(&mut ai, &mut a_orders);
                         continue;
                     }
                 }
-                if matches!(*melee_state, MeleeAbilityState::MotionBufferExceeded) {
+                if ability_state.is_target_lost() {
                     if !ai_attacker.chase_when_target_too_far {
-                        *ai = AIUnit::SeekEnemy;
+                        stop_attacking(&mut ai, &mut a_orders);
                         continue;
                     }
                 }
                 let size = attackable
                     .get_component::<UnitSize>(ai_attacker.target)
                     .unwrap();
-                if (target_transform.translation - a_position).length()
-                    < melee_ability.range + size.0 + a_size.0
-                {
-                    if matches!(*melee_state, MeleeAbilityState::Ready) {
+                let in_range = (target_transform.translation - a_position).length()
+                    < attack.range + size.0 + a_size.0;
+                // No use shooting into a wall, get closer instead.
+                let in_sight = !matches!(attack.effect, AbilityEffect::Projectile { .. })
+                    || !pathfinding_map.is_ready()
+                    || pathfinding_map.line_of_sight(
+                        map.tile_position_at(a_position.truncate()),
+                        map.tile_position_at(target_transform.translation.truncate()),
+                        0f32,
+                    );
+                if in_range && in_sight {
+                    let cast = ability_state.try_cast(
+                        abilities,
+                        attack_index,
+                        AbilityTarget::Unit(ai_attacker.target),
+                        time.elapsed_seconds(),
+                        mana.as_deref_mut(),
+                    );
+                    if cast.is_ok() {
+                        attack_started.send(AttackStarted {
+                            attacker: entity,
+                            ability: attack_index,
+                            target: AbilityTarget::Unit(ai_attacker.target),
+                        });
                         a_orders.override_order = Some(Order::Move(Awaitable::Queued(
                             Mover::new_to_target(a_transform.translation),
                         )));
-                        *melee_state = MeleeAbilityState::WillAttack(MeleeAbilityStateWillAttack {
-                            start_time: time.time_since_startup().as_secs_f32(),
-                            target_entity: ai_attacker.target.clone(),
-                        });
                     }
+                } else if holding {
+                    *ai = AIUnit::SeekEnemy;
                 } else {
                     // FIXME: if the override_order is already at this value, we shouldn't update it (target is not moving), so:
                     // - we don't trigger a modification on the Orders.
                     // - and orders are not redrawn
                     a_orders.override_order = Some(Order::Move(Awaitable::Queued(
-                        Mover::new_to_target(target_transform.translation),
+                        Mover::new_to_target(target_transform.translation).with_pathfinding(),
                     )));
                 }
             } else {
-                *ai = AIUnit::SeekEnemy;
+                stop_attacking(&mut ai, &mut a_orders);
             }
         }
     }
 }
 
-pub fn attack_melee_system(
-    time: Res<Time>,
-    mut q: Query<(
-        &Transform,
-        &MeleeAbility,
-        &mut MeleeAbilityState,
-        &OffensiveStats,
-        &UnitSize,
-    )>,
-    mut q_victim: Query<(&Transform, &UnitSize, &mut SufferDamage)>,
+/// Goes back to seeking enemies, and to the return position of the attack if any.
+fn stop_attacking(ai: &mut AIUnit, orders: &mut Orders) {
+    if let AIUnit::Attack(Attack {
+        return_position: Some(position),
+        ..
+    }) = ai
+    {
+        orders.override_order = Some(Orders::order_move_with(
+            Mover::new_to_target(*position).with_pathfinding(),
+        ));
+    }
+    *ai = AIUnit::SeekEnemy;
+}
+
+/// Moves projectiles, they stop at the first wall or enemy collider they cross.
+pub fn projectile_system(
+    mut commands: Commands,
+    time: Res<SimulationTime>,
+    context: Res<RapierContext>,
+    mut q_projectiles: Query<(Entity, &mut Projectile, &mut Transform)>,
+    q_walls: Query<&Wall>,
+    q_teams: Query<&Team>,
+    mut q_victims: Query<&mut SufferDamage>,
 ) {
-    for (transform, ability, mut state, offensive_stats, size) in q.iter_mut() {
-        // TODO: use an additional "recovering" state, (+ Client: spawn particles ; floating text for damage)
-        match &*state {
-            MeleeAbilityState::Ready => {}
-            MeleeAbilityState::WillAttack(attack_state) => {
-                if let Ok(a_transform) =
-                    q_victim.get_component::<Transform>(attack_state.target_entity)
-                {
-                    // Check if still in range
-                    let a_size = if let Ok(a_size) =
-                        q_victim.get_component::<UnitSize>(attack_state.target_entity)
-                    {
-                        a_size.0
-                    } else {
-                        0f32
-                    };
-                    let distance = (a_transform.translation - transform.translation).length();
-                    if distance > ability.range + ability.motion_buffer_range + size.0 + a_size {
-                        *state = MeleeAbilityState::MotionBufferExceeded;
-                        return;
-                    }
-                } else {
-                    // target is not valid
-                    *state = MeleeAbilityState::Ready;
-                    return;
-                }
-                let time = time.time_since_startup().as_secs_f32();
-                if time > attack_state.start_time + ability.time_to_strike {
-                    if let Ok(mut suffer_damage) =
-                        q_victim.get_component_mut::<SufferDamage>(attack_state.target_entity)
-                    {
-                        suffer_damage.new_damage(offensive_stats.power);
-                        *state = MeleeAbilityState::AttackCooldown(MeleeAbilityStateCooldown {
-                            start_time: time,
-                        });
-                    } else {
-                        // target is not valid
-                        *state = MeleeAbilityState::Ready;
-                        return;
-                    }
-                }
-            }
-            MeleeAbilityState::MotionBufferExceeded => {
-                *state = MeleeAbilityState::Ready;
-            }
-            MeleeAbilityState::AttackCooldown(cooldown) => {
-                if time.time_since_startup().as_secs_f32() > cooldown.start_time + ability.cooldown
-                {
-                    *state = MeleeAbilityState::Ready;
-                }
+    for (entity, mut projectile, mut transform) in q_projectiles.iter_mut() {
+        let step_length = (projectile.velocity.length() * time.delta_seconds())
+            .min(projectile.remaining_distance);
+        let step = projectile.velocity.normalize_or_zero() * step_length;
+        let team = projectile.team;
+        let can_hit = |hit: Entity| {
+            q_walls.get(hit).is_ok() || q_teams.get(hit).map_or(false, |t| t.id != team)
+        };
+        let hit = context.cast_ray(
+            transform.translation.truncate(),
+            step,
+            1f32,
+            true,
+            QueryFilter::new().predicate(&can_hit),
+        );
+        if let Some((hit, _)) = hit {
+            if let Ok(mut suffer_damage) = q_victims.get_mut(hit) {
+                suffer_damage.new_damage(projectile.damage.clone());
             }
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation += step.extend(0f32);
+        projectile.remaining_distance -= step_length;
+        if projectile.remaining_distance <= 0f32 {
+            commands.entity(entity).despawn();
         }
     }
 }
 
+/// Despawns units left without health once damage is resolved.
 pub fn health_system(
     mut commands: Commands,
-    mut q: Query<(Entity, &mut Health, &mut SufferDamage)>,
+    mut unit_died: EventWriter<UnitDied>,
+    q: Query<(Entity, &Health, &SufferDamage, Option<&Team>)>,
+    q_units: Query<(), With<UnitId>>,
 ) {
-    for (entity, mut health, mut suffer_damage) in q.iter_mut() {
-        while suffer_damage.amount.len() > 0 {
-            health.current_hp -= suffer_damage.amount.last().unwrap();
-            suffer_damage.amount.pop();
-        }
+    for (entity, health, suffer_damage, team) in q.iter() {
         if health.current_hp <= 0f32 {
+            // The killer may have died since.
+            let killer = suffer_damage
+                .last_attacker
+                .filter(|killer| q_units.get(*killer).is_ok());
+            unit_died.send(UnitDied {
+                entity,
+                killer,
+                team: team.map(|team| team.id),
+            });
             commands.entity(entity).despawn_recursive();
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn attack(return_position: Option<Vec3>) -> AIUnit {
+        AIUnit::Attack(Attack {
+            target: Entity::from_raw(1),
+            chase_when_target_too_far: false,
+            return_position,
+        })
+    }
+
+    #[test]
+    fn return_after_chase_uses_pathfinding() {
+        let home = Vec3::new(100f32, 50f32, 0f32);
+        let mut ai = attack(Some(home));
+        let mut orders = Orders::default();
+        stop_attacking(&mut ai, &mut orders);
+        assert!(matches!(ai, AIUnit::SeekEnemy));
+        assert_eq!(orders.returning_to(), Some(home));
+        match &orders.override_order {
+            Some(Order::Move(Awaitable::Queued(mover))) => assert!(mover.uses_pathfinding()),
+            _ => panic!("expected a move back"),
+        }
+    }
+
+    #[test]
+    fn no_return_without_return_position() {
+        let mut ai = attack(None);
+        let mut orders = Orders::default();
+        stop_attacking(&mut ai, &mut orders);
+        assert!(matches!(ai, AIUnit::SeekEnemy));
+        assert!(orders.override_order.is_none());
+    }
+}
@@ -0,0 +1,379 @@
+//! Unit definitions, from `assets/units/*.unit.ron`.
+//!
+//! Definitions are read from the file system when the plugin is built instead of through
+//! the `AssetServer`, so they are available to startup systems, also in headless apps
+//! without one. When there is an `AssetServer`, it only watches them for hot reload.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    abilities::{Abilities, AbilityDefinition, AbilityState, Mana},
+    components::*,
+    damage::{default_critical_multiplier, Defense, Resistances},
+    orders::orders_comp::*,
+    simulation::{SimulationLabel, SimulationSchedule, SimulationStage},
+    vision::{default_sight_range, Sight},
+};
+
+const UNITS_FOLDER: &str = "units";
+const UNIT_EXTENSION: &str = "unit.ron";
+
+/// Archetype of a unit, loaded from `assets/units/*.unit.ron`.
+#[derive(Serialize, Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "0f1b7a5e-3c3d-4c8e-9a57-5b8d0fbc2f11"]
+pub struct UnitDefinition {
+    /// Also used as the unit's `RenderSprite`.
+    pub name: String,
+    pub size: f32,
+    pub speed: f32,
+    pub rotation_speed: f32,
+    pub seek_enemy_range: f32,
+    /// Distance the unit reveals around itself for its team.
+    #[serde(default = "default_sight_range")]
+    pub sight_range: f32,
+    pub max_hp: f32,
+    pub power: f32,
+    #[serde(default)]
+    pub critical_chance: f32,
+    #[serde(default = "default_critical_multiplier")]
+    pub critical_multiplier: f32,
+    #[serde(default)]
+    pub armor: f32,
+    #[serde(default)]
+    pub resistances: Resistances,
+    /// The first harmful one targeting units is used to attack.
+    pub abilities: Vec<AbilityDefinition>,
+    /// Units without mana can only cast abilities without cost.
+    #[serde(default)]
+    pub max_mana: f32,
+    /// Mana regained per second.
+    #[serde(default)]
+    pub mana_regeneration: f32,
+    /// Only used by the client, relative to the assets folder.
+    pub sprite: String,
+    /// Only used by the client, tint applied to the sprite.
+    pub color: (f32, f32, f32),
+}
+
+impl UnitDefinition {
+    pub fn defense(&self) -> Defense {
+        Defense {
+            armor: self.armor,
+            resistances: self.resistances.clone(),
+        }
+    }
+    /// Full mana of a new unit, if it has some.
+    pub fn mana(&self) -> Option<Mana> {
+        (self.max_mana > 0f32).then(|| Mana {
+            max: self.max_mana,
+            current: self.max_mana,
+            regeneration: self.mana_regeneration,
+        })
+    }
+}
+
+/// Every known unit definition, by name.
+#[derive(Default)]
+pub struct UnitDefinitions {
+    definitions: HashMap<String, UnitDefinition>,
+    // Kept to receive hot reload events.
+    handles: Vec<Handle<UnitDefinition>>,
+}
+
+impl UnitDefinitions {
+    pub fn get(&self, name: &str) -> Option<&UnitDefinition> {
+        self.definitions.get(name)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &UnitDefinition> {
+        self.definitions.values()
+    }
+    /// Adds `definition`, replacing the one of the same name.
+    pub fn insert(&mut self, definition: UnitDefinition) {
+        self.definitions.insert(definition.name.clone(), definition);
+    }
+}
+
+#[derive(Debug)]
+pub enum DefinitionError {
+    ReadFolder(PathBuf, io::Error),
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, ron::Error),
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefinitionError::ReadFolder(path, error) => write!(f, "{:?}: {}", path, error),
+            DefinitionError::Read(path, error) => write!(f, "{:?}: {}", path, error),
+            DefinitionError::Parse(path, error) => write!(f, "{:?}: {}", path, error),
+        }
+    }
+}
+
+/// Modified definitions, applied to units at the beginning of the next simulation tick,
+/// so they change the same way when a replay is played.
+#[derive(Default)]
+pub struct DefinitionChanges {
+    pub(crate) definitions: Vec<UnitDefinition>,
+}
+
+#[derive(Default)]
+pub struct UnitDefinitionLoader;
+
+impl AssetLoader for UnitDefinitionLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let definition: UnitDefinition = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(definition));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[UNIT_EXTENSION]
+    }
+}
+
+/// Same root folder as bevy's `FileAssetIo`.
+pub fn assets_path() -> PathBuf {
+    if let Ok(manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") {
+        PathBuf::from(manifest_dir).join("assets")
+    } else {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join("assets")))
+            .unwrap_or_else(|| PathBuf::from("assets"))
+    }
+}
+
+/// Returns the paths of every unit definition file, relative to the assets folder.
+fn unit_definition_paths() -> Result<Vec<String>, DefinitionError> {
+    let folder = assets_path().join(UNITS_FOLDER);
+    let mut paths: Vec<String> = fs::read_dir(&folder)
+        .map_err(|error| DefinitionError::ReadFolder(folder, error))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|file_name| file_name.ends_with(UNIT_EXTENSION))
+        .map(|file_name| format!("{}/{}", UNITS_FOLDER, file_name))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn read_definition(path: &Path) -> Result<UnitDefinition, DefinitionError> {
+    let content = fs::read(path).map_err(|error| DefinitionError::Read(path.into(), error))?;
+    ron::de::from_bytes(&content).map_err(|error| DefinitionError::Parse(path.into(), error))
+}
+
+/// Reads the definitions of `paths`, relative to the assets folder.
+fn read_definitions(paths: &[String]) -> Result<UnitDefinitions, DefinitionError> {
+    let mut definitions = UnitDefinitions::default();
+    for path in paths.iter() {
+        definitions.insert(read_definition(&assets_path().join(path))?);
+    }
+    Ok(definitions)
+}
+
+pub struct UnitsPlugin;
+
+impl Plugin for UnitsPlugin {
+    fn build(&self, app: &mut App) {
+        let (paths, mut definitions) = unit_definition_paths()
+            .and_then(|paths| read_definitions(&paths).map(|definitions| (paths, definitions)))
+            .unwrap_or_else(|error| panic!("Could not load unit definitions {}", error));
+
+        app.init_resource::<DefinitionChanges>().stage(
+            SimulationSchedule,
+            |schedule: &mut Schedule| {
+                schedule.add_system_to_stage(
+                    SimulationStage::First,
+                    apply_definition_changes
+                        .label(SimulationLabel::Definitions)
+                        .before(SimulationLabel::SpatialIndex),
+                )
+            },
+        );
+
+        // The asset server then watches them for hot reload.
+        if app.world.contains_resource::<AssetServer>() {
+            app.add_asset::<UnitDefinition>()
+                .init_asset_loader::<UnitDefinitionLoader>()
+                .add_system(unit_definitions_reload_system);
+            let asset_server = app.world.resource::<AssetServer>();
+            definitions.handles = paths
+                .iter()
+                .map(|path| asset_server.load(path.as_str()))
+                .collect();
+        }
+        app.insert_resource(definitions);
+    }
+}
+
+/// Queues modified definition files, for `apply_definition_changes`.
+fn unit_definitions_reload_system(
+    mut events: EventReader<AssetEvent<UnitDefinition>>,
+    assets: Res<Assets<UnitDefinition>>,
+    mut changes: ResMut<DefinitionChanges>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            if let Some(definition) = assets.get(handle) {
+                changes.definitions.push(definition.clone());
+            }
+        }
+    }
+}
+
+/// Applies modified definitions to the resource and to existing units.
+fn apply_definition_changes(
+    mut commands: Commands,
+    mut changes: ResMut<DefinitionChanges>,
+    mut definitions: ResMut<UnitDefinitions>,
+    mut q_units: Query<(
+        Entity,
+        &RenderSprite,
+        &mut Speed,
+        &mut RotateBeforeMove,
+        &mut SeekEnemyRange,
+        &mut Sight,
+        &mut Abilities,
+        &mut AbilityState,
+        &mut OffensiveStats,
+        &mut Health,
+        &mut Defense,
+        Option<&mut Mana>,
+    )>,
+) {
+    if changes.definitions.is_empty() {
+        return;
+    }
+    for definition in std::mem::take(&mut changes.definitions) {
+        for (
+            entity,
+            render_sprite,
+            mut speed,
+            mut rotate_before_move,
+            mut seek_enemy_range,
+            mut sight,
+            mut abilities,
+            mut ability_state,
+            mut offensive_stats,
+            mut health,
+            mut defense,
+            mana,
+        ) in q_units.iter_mut()
+        {
+            if render_sprite.0 != definition.name {
+                continue;
+            }
+            // Size is left alone, as it's baked into the physics collider.
+            speed.speed = definition.speed;
+            rotate_before_move.rotation_speed = definition.rotation_speed;
+            seek_enemy_range.range = definition.seek_enemy_range;
+            sight.range = definition.sight_range;
+            abilities.0 = definition.abilities.clone();
+            ability_state.retain_abilities(&abilities);
+            match (definition.mana(), mana) {
+                (Some(new_mana), Some(mut mana)) => {
+                    mana.max = new_mana.max;
+                    mana.regeneration = new_mana.regeneration;
+                    mana.current = mana.current.min(mana.max);
+                }
+                (Some(new_mana), None) => {
+                    commands.entity(entity).insert(new_mana);
+                }
+                (None, _) => {
+                    commands.entity(entity).remove::<Mana>();
+                }
+            }
+            offensive_stats.power = definition.power;
+            offensive_stats.critical_chance = definition.critical_chance;
+            offensive_stats.critical_multiplier = definition.critical_multiplier;
+            *defense = definition.defense();
+            health.max_hp = definition.max_hp;
+            health.current_hp = health.current_hp.min(health.max_hp);
+        }
+        info!("Reloaded unit definition: {}", definition.name);
+        definitions.insert(definition);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core_game::test::headless_app;
+
+    #[test]
+    fn definitions_of_assets_are_read() {
+        let paths = unit_definition_paths().unwrap();
+        let definitions = read_definitions(&paths).unwrap();
+        assert_eq!(definitions.iter().count(), paths.len());
+        assert!(definitions.get("goblin").is_some());
+    }
+    #[test]
+    fn definition_errors_name_their_file() {
+        let missing = std::env::temp_dir().join("rtas_test_missing.unit.ron");
+        let result = read_definition(&missing);
+        assert!(matches!(result, Err(DefinitionError::Read(path, _)) if path == missing));
+
+        let invalid = std::env::temp_dir().join("rtas_test_invalid.unit.ron");
+        fs::write(&invalid, "(name: \"goblin\")").unwrap();
+        let result = read_definition(&invalid);
+        assert!(matches!(result, Err(DefinitionError::Parse(path, _)) if path == invalid));
+    }
+    #[test]
+    fn definition_changes_apply_to_units() {
+        let mut app = headless_app(|_| {});
+        let name = app
+            .world
+            .query::<&RenderSprite>()
+            .iter(&app.world)
+            .next()
+            .unwrap()
+            .0
+            .clone();
+        let mut definition = app
+            .world
+            .resource::<UnitDefinitions>()
+            .get(&name)
+            .unwrap()
+            .clone();
+        definition.speed = 1f32;
+        definition.max_hp = 1f32;
+        app.world
+            .resource_mut::<DefinitionChanges>()
+            .definitions
+            .push(definition);
+        app.update();
+
+        let definitions = app.world.resource::<UnitDefinitions>();
+        assert_eq!(definitions.get(&name).unwrap().speed, 1f32);
+        for (sprite, speed, health) in app
+            .world
+            .query::<(&RenderSprite, &Speed, &Health)>()
+            .iter(&app.world)
+        {
+            if sprite.0 == name {
+                assert_eq!(speed.speed, 1f32);
+                assert_eq!(health.max_hp, 1f32);
+                assert!(health.current_hp <= 1f32);
+            }
+        }
+    }
+}
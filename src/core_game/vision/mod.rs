@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    components::Team,
+    map::{Map, TILE_SIZE},
+    pathfinding::pathfinding_comp::{self, PosF, TileType},
+    simulation::{SimulationLabel, SimulationSchedule, SimulationStage},
+};
+
+pub struct VisionPlugin;
+
+impl Plugin for VisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Vision>()
+            .stage(SimulationSchedule, |schedule: &mut Schedule| {
+                schedule.add_system_to_stage(
+                    SimulationStage::First,
+                    vision_system
+                        .label(SimulationLabel::Vision)
+                        .after(SimulationLabel::SpatialIndex),
+                )
+            });
+    }
+}
+
+pub(crate) fn default_sight_range() -> f32 {
+    300f32
+}
+
+/// Distance a unit sees its team's surroundings at, walls blocking its sight.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Sight {
+    pub range: f32,
+}
+
+impl Default for Sight {
+    fn default() -> Self {
+        Sight {
+            range: default_sight_range(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileVisibility {
+    /// Never seen by the team.
+    Unexplored,
+    /// Seen before, but not currently.
+    Explored,
+    Visible,
+}
+
+/// Tiles of the map seen by each team, updated at the beginning of each simulation tick.
+///
+/// Only marked as changed when a tile changed, so the fog is only redrawn then.
+#[derive(Default, Clone, PartialEq)]
+pub struct Vision {
+    width: usize,
+    height: usize,
+    teams: HashMap<usize, Vec<TileVisibility>>,
+}
+
+impl Vision {
+    pub fn tile(&self, team: usize, x: usize, y: usize) -> TileVisibility {
+        if x >= self.width || y >= self.height {
+            return TileVisibility::Unexplored;
+        }
+        self.teams
+            .get(&team)
+            .map_or(TileVisibility::Unexplored, |tiles| {
+                tiles[x + y * self.width]
+            })
+    }
+    /// Whether `team` currently sees the world `position`.
+    pub fn is_visible(&self, map: &Map, team: usize, position: Vec2) -> bool {
+        let (x, y) = map.tile_position_at(position);
+        let (x, y) = (x.round(), y.round());
+        x >= 0f32 && y >= 0f32 && self.tile(team, x as usize, y as usize) == TileVisibility::Visible
+    }
+    /// Tiles each team has seen, currently or before, sorted by team.
+    pub fn explored(&self) -> Vec<(usize, Vec<bool>)> {
+        let mut explored: Vec<(usize, Vec<bool>)> = self
+            .teams
+            .iter()
+            .map(|(team, tiles)| {
+                let tiles = tiles
+                    .iter()
+                    .map(|tile| *tile != TileVisibility::Unexplored)
+                    .collect();
+                (*team, tiles)
+            })
+            .collect();
+        explored.sort_by_key(|(team, _)| *team);
+        explored
+    }
+    /// Vision of a `width` by `height` map where teams explored the given tiles.
+    pub fn from_explored(width: usize, height: usize, explored: &[(usize, Vec<bool>)]) -> Vision {
+        let teams = explored
+            .iter()
+            .filter(|(_, tiles)| tiles.len() == width * height)
+            .map(|(team, tiles)| {
+                let tiles = tiles
+                    .iter()
+                    .map(|explored| {
+                        if *explored {
+                            TileVisibility::Explored
+                        } else {
+                            TileVisibility::Unexplored
+                        }
+                    })
+                    .collect();
+                (*team, tiles)
+            })
+            .collect();
+        Vision {
+            width,
+            height,
+            teams,
+        }
+    }
+    /// Forgets what teams saw if the map changed, then turns visible tiles into explored ones.
+    fn fade(&mut self, width: usize, height: usize) {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.teams.clear();
+        }
+        for tiles in self.teams.values_mut() {
+            for tile in tiles.iter_mut() {
+                if *tile == TileVisibility::Visible {
+                    *tile = TileVisibility::Explored;
+                }
+            }
+        }
+    }
+    fn reveal(&mut self, team: usize, tiles: impl IntoIterator<Item = (i32, i32)>) {
+        let (width, height) = (self.width, self.height);
+        let team_tiles = self
+            .teams
+            .entry(team)
+            .or_insert_with(|| vec![TileVisibility::Unexplored; width * height]);
+        for (x, y) in tiles {
+            if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+                team_tiles[x as usize + y as usize * width] = TileVisibility::Visible;
+            }
+        }
+    }
+}
+
+/// Tiles seen from `from` up to `radius`, both in tile units.
+///
+/// Free tiles need a line of sight to their center, walls are seen when next to a seen free tile.
+fn visible_tiles(map: &pathfinding_comp::Map, from: PosF, radius: f32) -> Vec<(i32, i32)> {
+    let min = (
+        (from.0 - radius).floor() as i32,
+        (from.1 - radius).floor() as i32,
+    );
+    let max = (
+        (from.0 + radius).ceil() as i32,
+        (from.1 + radius).ceil() as i32,
+    );
+    let mut visible = vec![];
+    for y in min.1..=max.1 {
+        for x in min.0..=max.0 {
+            let (dx, dy) = (x as f32 - from.0, y as f32 - from.1);
+            if dx * dx + dy * dy > radius * radius {
+                continue;
+            }
+            if map.get_tile(&(x, y)) != Ok(TileType::Free)
+                || !map.line_of_sight(from, (x as f32, y as f32), 0f32)
+            {
+                continue;
+            }
+            visible.push((x, y));
+            for (nx, ny) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let neighbor = (x + nx, y + ny);
+                if map.get_tile(&neighbor) == Ok(TileType::Wall) {
+                    visible.push(neighbor);
+                }
+            }
+        }
+    }
+    visible
+}
+
+pub fn vision_system(
+    mut vision: ResMut<Vision>,
+    map: Res<Map>,
+    pathfinding_map: Res<pathfinding_comp::Map>,
+    q_units: Query<(&Team, &Sight, &Transform)>,
+) {
+    // Computed aside, as tiles still seen are faded then revealed again.
+    let mut next = vision.clone();
+    next.fade(map.map.width, map.map.height);
+    if pathfinding_map.is_ready() {
+        for (team, sight, transform) in q_units.iter() {
+            let from = map.tile_position_at(transform.translation.truncate());
+            let tiles = visible_tiles(&pathfinding_map, from, sight.range / TILE_SIZE);
+            next.reveal(team.id, tiles);
+        }
+    }
+    if *vision != next {
+        *vision = next;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn walls_block_sight() {
+        let mut map = pathfinding_comp::Map::new(7, 3);
+        for y in 0..3 {
+            map.set_tile(&(3, y), TileType::Wall);
+        }
+        let tiles = visible_tiles(&map, (1.0, 1.0), 5.0);
+        assert!(tiles.contains(&(0, 0)));
+        assert!(tiles.contains(&(3, 1)));
+        assert!(!tiles.contains(&(4, 1)));
+
+        let mut vision = Vision::default();
+        vision.fade(7, 3);
+        vision.reveal(0, tiles);
+        assert_eq!(vision.tile(0, 2, 1), TileVisibility::Visible);
+        assert_eq!(vision.tile(1, 2, 1), TileVisibility::Unexplored);
+        vision.fade(7, 3);
+        assert_eq!(vision.tile(0, 2, 1), TileVisibility::Explored);
+        assert_eq!(vision.tile(0, 5, 1), TileVisibility::Unexplored);
+
+        let restored = Vision::from_explored(7, 3, &vision.explored());
+        assert_eq!(restored.tile(0, 2, 1), TileVisibility::Explored);
+        assert_eq!(restored.tile(0, 5, 1), TileVisibility::Unexplored);
+        assert_eq!(restored.tile(1, 2, 1), TileVisibility::Unexplored);
+    }
+}
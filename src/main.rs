@@ -1,15 +1,64 @@
-use bevy::prelude::*;
+#![cfg_attr(test, feature(test))]
+
+use bevy::{asset::AssetServerSettings, prelude::*};
 
 mod client;
 mod core_game;
 
-use client::ClientPlugin;
-use core_game::CorePlugin;
+use client::{camera_pan::camera_pan_comp::CameraPan, ClientPlugin};
+use core_game::{
+    replay::ReplayMode, save::LoadGame, scenario::ScenarioName, CorePlugin, HeadlessCorePlugin,
+};
+
+/// Returns the value following `name` in the command line arguments.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
+}
 
 fn main() {
-    App::new()
+    let headless = std::env::args().any(|arg| arg == "--headless");
+
+    let load = arg_value("--load");
+    let record = arg_value("--record");
+    let replay = arg_value("--replay");
+    if load.is_some() && (record.is_some() || replay.is_some()) {
+        // Replays start from a scenario, they couldn't reproduce a loaded game.
+        eprintln!("--load can't be combined with --record or --replay.");
+        std::process::exit(2);
+    }
+
+    let mut app = App::new();
+    if let Some(path) = record {
+        app.insert_resource(ReplayMode::Record(path.into()));
+    } else if let Some(path) = replay {
+        app.insert_resource(ReplayMode::Play(path.into()));
+    }
+    if let Some(name) = arg_value("--scenario") {
+        app.insert_resource(ScenarioName(name));
+    }
+    if let Some(path) = load {
+        app.insert_resource(LoadGame(path.into()));
+    }
+    if headless {
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(HeadlessCorePlugin);
+    } else {
+        if std::env::args().any(|arg| arg == "--wasd") {
+            app.insert_resource(CameraPan {
+                wasd: true,
+                ..default()
+            });
+        }
+        // Watch asset files, for unit definitions hot reload.
+        app.insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..default()
+        })
         .add_plugins(DefaultPlugins)
         .add_plugin(ClientPlugin)
-        .add_plugin(CorePlugin)
-        .run();
+        .add_plugin(CorePlugin);
+    }
+    app.run();
 }